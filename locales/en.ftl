@@ -220,7 +220,7 @@ sort-by-description = The internal numeric identifier in the changer dropdown us
 
 Name = Name
 
-Date = Date
+Date = Date Created
 
 Options = Options
 
@@ -339,6 +339,73 @@ gslapper-fps-description = Maximum playback frame rate. Supported values: 30, 60
 gslapper-transition-description = Fade between wallpaper changes.
 gslapper-transition-duration-description = Fade duration in seconds, from 0.1 to 5.
 gslapper-cache-size-description = Maximum gSlapper cache size in MB.
+monitor-profiles-description = Per-monitor wallpaper folder, changer and sort settings. Selecting a monitor in the dropdown switches to its profile.
+landscape = Landscape
+portrait = Portrait
+match-monitor-orientation-description = When true, random wallpapers are picked from images matching the monitor's orientation (landscape or portrait).
+match-monitor-orientation = Match Monitor Orientation
+match-monitor-orientation-tooltip = Pick random wallpapers whose orientation matches the monitor, e.g. portrait images for rotated screens.
+span-monitors-description = When true, the selected image is split across all monitors according to their positions and sizes.
+span-monitors = Span Across Monitors
+span-monitors-tooltip = Split one wide image across all monitors instead of setting it on the selected one.
+collection-description = The collection shown in the image grid. Empty shows every image in the wallpaper folder.
+all-images = All Images
+collection-dropdown-tooltip = Only show images from a collection.
+edit-collection = Edit Collection
+edit-collection-tooltip = While a collection is selected, middle-clicking an image adds it to or removes it from that collection instead of toggling favorite.
+delete-collection = Delete
+new-collection = New collection name
+create-collection = Create
+schedule-description = Wallpapers applied by --daemon at set times. Each entry has a "monitor", a "start" (HH:MM, sunrise or sunset with an optional offset such as sunset-30m) and a "source" that is an "image" path, a "folder" path or a "collection" name. An entry lasts until the next entry for the same monitor starts.
+schedule-location-description = The "latitude" and "longitude" in degrees used to compute sunrise and sunset locally, without network access.
+history = History
+history-tooltip = Wallpapers applied to the selected monitor. Ctrl+Z undoes and Ctrl+Shift+Z redoes the last change.
+history-empty = No wallpapers have been applied to this monitor yet.
+history-apply = Apply
+history-undo = Undo
+history-redo = Redo
+history-close = Close
+distinct-random-monitors-description = When true, random wallpapers avoid images already shown on another monitor.
+distinct-random-monitors = Distinct Random Wallpapers
+distinct-random-monitors-tooltip = Never pick the same random image for two monitors while other images are left.
+weighted-random-description = When true, random wallpapers are picked by star rating, favorites are twice as likely and recently shown images are less likely, instead of shuffling without repeats.
+weighted-random = Weighted Random
+weighted-random-tooltip = Favor favorites and highly rated images and hold back recently shown ones. Rate images by right-clicking them.
+add-favorite = Add to favorites
+remove-favorite = Remove from favorites
+image-weight = Rating
+wallpaper-sources-description = Extra folders to load wallpapers from, shown in one grid together with wallpaper_folder. Each entry has a path, max_depth with the number of subfolder levels to look into (null for all), include and exclude glob patterns matched against paths inside the folder and include_hidden for dot files. An entry with the same path as wallpaper_folder sets the rules for that folder.
+loading-images = Loading images
+no-thumbnail = No thumbnail
+edit-tags = Edit Tags
+tags = Tags
+no-tags = This image has no tags yet
+new-tag = New tag
+add-tag = Add
+other-tags = Tags of other images
+stills = Stills
+videos = Videos
+uhd = 4K+
+color-filter = Color
+color-filter-tooltip = Show wallpapers whose dominant colors are close to the picked one, closest first.
+clear-color-filter = Clear Color
+duplicates = Duplicates
+find-duplicates = Find Duplicates
+find-duplicates-tooltip = Group visually similar images of the folder so you can keep the highest-resolution one and move the rest to the trash.
+duplicates-searching = Looking for duplicates...
+no-duplicates = No duplicates found.
+duplicate-keep = Keep
+duplicate-trash = Trash
+trash-others = Move Others to Trash
+trash-all-duplicates = Move All Duplicates to Trash
+date-modified = Date Modified
+file-size = File Size
+resolution = Resolution
+aspect-ratio = Aspect Ratio
+last-used = Recently Used
+most-used = Most Used
+random-order = Random
+random-sort-seed-description = Shuffles the random sort order of the image grid and --next. Picking the random order again in the GUI chooses a new one.
 
 hyprpaper-fit-mode-tooltip = Determines how to display the image.
 
@@ -354,7 +421,7 @@ mpvpaper-slideshow-enable-tooltip = Enable/Disable slideshow mode.
 
 mpvpaper-slideshow-interval-tooltip = Time in seconds to switch between images in slideshow mode.
 
-search-bar-tooltip = Sub-string filter for wallpapers.
+search-bar-tooltip = Filter wallpapers by name, tags and properties, e.g. "tag:nature -tag:dark name:beach" or "w>=3840 ratio:21:9 landscape video". Words without a prefix match the name.
 
 sort-dropdown-tooltip = Sorting method for images.
 
@@ -221,7 +221,7 @@ selected-changer = Cambiador elegido
 
 Name = Nombre
 
-Date = Fecha
+Date = Fecha de creación
 
 Options = Opciones
 
@@ -340,6 +340,73 @@ gslapper-fps-description = Frecuencia máxima de reproducción. Valores admitido
 gslapper-transition-description = Fundido entre cambios de fondo.
 gslapper-transition-duration-description = Duración del fundido en segundos, de 0,1 a 5.
 gslapper-cache-size-description = Tamaño máximo de caché de gSlapper en MB.
+monitor-profiles-description = Carpeta de imágenes, cambiador y orden por monitor. Seleccionar un monitor en la lista cambia a su perfil.
+landscape = Horizontal
+portrait = Vertical
+match-monitor-orientation-description = Si es verdadero, los fondos aleatorios se eligen entre imágenes con la misma orientación que el monitor (horizontal o vertical).
+match-monitor-orientation = Coincidir con la orientación del monitor
+match-monitor-orientation-tooltip = Elige fondos aleatorios con la orientación del monitor, p. ej. imágenes verticales para pantallas giradas.
+span-monitors-description = Si es verdadero, la imagen seleccionada se divide entre todos los monitores según su posición y tamaño.
+span-monitors = Extender entre monitores
+span-monitors-tooltip = Divide una imagen ancha entre todos los monitores en lugar de ponerla solo en el seleccionado.
+collection-description = La colección mostrada en la cuadrícula. Vacío muestra todas las imágenes de la carpeta.
+all-images = Todas las imágenes
+collection-dropdown-tooltip = Mostrar solo imágenes de una colección.
+edit-collection = Editar colección
+edit-collection-tooltip = Con una colección seleccionada, el clic central añade o quita la imagen de esa colección en lugar de marcarla como favorita.
+delete-collection = Eliminar
+new-collection = Nombre de la nueva colección
+create-collection = Crear
+schedule-description = Fondos aplicados por --daemon a horas fijas. Cada entrada tiene un "monitor", un "start" (HH:MM, sunrise o sunset con un desplazamiento opcional como sunset-30m) y un "source" que es una ruta "image", una ruta "folder" o un nombre "collection". Una entrada dura hasta que empieza la siguiente del mismo monitor.
+schedule-location-description = La "latitude" y "longitude" en grados usadas para calcular localmente el amanecer y el atardecer, sin acceso a la red.
+history = Historial
+history-tooltip = Fondos aplicados al monitor seleccionado. Ctrl+Z deshace y Ctrl+Shift+Z rehace el último cambio.
+history-empty = Todavía no se ha aplicado ningún fondo a este monitor.
+history-apply = Aplicar
+history-undo = Deshacer
+history-redo = Rehacer
+history-close = Cerrar
+distinct-random-monitors-description = Si es verdadero, los fondos aleatorios evitan imágenes que ya se muestran en otro monitor.
+distinct-random-monitors = Fondos aleatorios distintos
+distinct-random-monitors-tooltip = No elegir la misma imagen aleatoria para dos monitores mientras queden otras imágenes.
+weighted-random-description = Si es verdadero, los fondos aleatorios se eligen según su puntuación, los favoritos tienen el doble de probabilidad y las imágenes mostradas recientemente tienen menos, en lugar de barajar sin repetir.
+weighted-random = Aleatorio ponderado
+weighted-random-tooltip = Favorece las imágenes favoritas y mejor puntuadas y retrasa las mostradas recientemente. Puntúa imágenes con el clic derecho.
+add-favorite = Añadir a favoritos
+remove-favorite = Quitar de favoritos
+image-weight = Puntuación
+wallpaper-sources-description = Carpetas adicionales de las que cargar fondos, mostradas en una sola cuadrícula junto con wallpaper_folder. Cada entrada tiene una ruta en path, max_depth con el número de niveles de subcarpetas a recorrer (null para todos), patrones glob include y exclude comparados con las rutas dentro de la carpeta e include_hidden para archivos ocultos. Una entrada con la misma ruta que wallpaper_folder define las reglas de esa carpeta.
+loading-images = Cargando imágenes
+no-thumbnail = Sin miniatura
+edit-tags = Editar etiquetas
+tags = Etiquetas
+no-tags = Esta imagen aún no tiene etiquetas
+new-tag = Nueva etiqueta
+add-tag = Añadir
+other-tags = Etiquetas de otras imágenes
+stills = Imágenes fijas
+videos = Vídeos
+uhd = 4K+
+color-filter = Color
+color-filter-tooltip = Muestra imágenes cuyos colores dominantes se parecen al elegido, las más parecidas primero.
+clear-color-filter = Quitar color
+duplicates = Duplicados
+find-duplicates = Buscar duplicados
+find-duplicates-tooltip = Agrupa las imágenes visualmente parecidas de la carpeta para conservar la de mayor resolución y mover el resto a la papelera.
+duplicates-searching = Buscando duplicados...
+no-duplicates = No se encontraron duplicados.
+duplicate-keep = Conservar
+duplicate-trash = A la papelera
+trash-others = Mover los demás a la papelera
+trash-all-duplicates = Mover todos los duplicados a la papelera
+date-modified = Fecha de modificación
+file-size = Tamaño de archivo
+resolution = Resolución
+aspect-ratio = Relación de aspecto
+last-used = Usadas recientemente
+most-used = Más usadas
+random-order = Aleatorio
+random-sort-seed-description = Baraja el orden aleatorio de la cuadrícula de imágenes y de --next. Elegir de nuevo el orden aleatorio en la interfaz escoge uno nuevo.
 
 hyprpaper-fit-mode-tooltip = Determina como la imagen esta desplegada.
 
@@ -355,7 +422,7 @@ mpvpaper-slideshow-enable-tooltip = Permitir presentación de imágenes.
 
 mpvpaper-slideshow-interval-tooltip = Intervalo en segundos de cambiar entre imágenes.
 
-search-bar-tooltip = Cadena de caracteres para filtrar imágenes.
+search-bar-tooltip = Filtra imágenes por nombre, etiquetas y propiedades, p. ej. "tag:nature -tag:dark name:beach" o "w>=3840 ratio:21:9 landscape video". Las palabras sin prefijo buscan en el nombre.
 
 sort-dropdown-tooltip = Método de sorteo para la cuadricula de imágenes.
 
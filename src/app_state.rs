@@ -5,12 +5,26 @@ use crate::{
         GSlapperControl, GSlapperRuntime, GSlapperStatus, apply_gslapper_settings,
         control_gslapper, generate_gslapper_settings_dialog, load_gslapper_runtime,
     },
+    cli::{changer_for_monitor, next_wallpaper},
+    cli_parser::Cli,
     common::{
-        BUTTON_HEIGHT, BUTTON_WIDTH, CacheImageFile, DEFAULT_MARGIN, Wallpaper,
-        get_config_file_path, parse_executable_script,
+        BUTTON_HEIGHT, BUTTON_WIDTH, CacheImageFile, DEFAULT_MARGIN, HistoryEntry,
+        MAX_IMAGE_WEIGHT, MonitorProfile, Wallpaper, get_config_file_path, parse_executable_script,
     },
-    database::DatabaseConnection,
-    monitors::AvailableMonitors,
+    database::{DatabaseConnection, collect_cache_garbage_if_due},
+    duplicates::{find_duplicates, move_to_trash},
+    fs::{WallpaperSource, get_image_files},
+    ipc::{IpcCommand, IpcRequest, ipc_subscription},
+    monitors::{
+        AvailableMonitors, Monitor, MonitorEvent, Orientation, monitor_names, monitor_subscription,
+        saved_wallpaper_for,
+    },
+    palette::palette_distance,
+    query::{Query, has_term, normalize_tag, set_color_term, toggle_term},
+    schedule::{Location, ScheduleEntry},
+    shuffle::{draw_image, images_to_avoid},
+    sort::{ImageUsage, SortOrder, load_usage, sort_images, sort_paths},
+    span::{change_saved_wallpaper, save_spanned_wallpapers, span_wallpaper},
     theme::WaytrogenTheme,
     wallpaper_changers::{
         AWWWResizeMode, AWWWScallingFilter, AWWWTransitionBezier, AWWWTransitionPosition,
@@ -19,8 +33,10 @@ use crate::{
         MpvPaperSettings, MpvPaperSlideshowSettings, SwaybgModes, SwaybgSettings, WallpaperChanger,
         WallpaperChangers, get_available_wallpaper_changers,
     },
+    watcher::{FolderEvent, folder_subscription},
 };
 use anyhow::anyhow;
+use futures::SinkExt;
 use iced::Length::Shrink;
 use iced::alignment::Horizontal::Left;
 use iced::widget::canvas::path::lyon_path::geom::euclid::num::Floor;
@@ -29,14 +45,17 @@ use iced::{
     Alignment::Center,
     Color, Element,
     Length::Fill,
-    Subscription, Task,
+    Subscription, Task, Theme,
     application::BootFn,
     event,
-    widget::{button, column, image, lazy, pick_list, row, stack, text, text_input, toggler},
+    widget::{
+        button, column, image, lazy, pick_list, row, scrollable, stack, text, text_input, toggler,
+    },
     window,
 };
 use iced_aw::{
-    MenuBar,
+    ContextMenu, MenuBar,
+    helpers::color_picker,
     menu::{Item, Menu},
 };
 use log::{debug, error, trace, warn};
@@ -44,30 +63,34 @@ use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::sync::atomic::AtomicUsize;
 use std::{
+    collections::{HashMap, HashSet},
     fmt::Display,
     fs::{OpenOptions, remove_file},
     io::{Read, Write},
     path::{Path, PathBuf},
     process::Command,
-    time::SystemTime,
 };
 use strum::VariantArray;
 use walkdir::WalkDir;
 
 static IMAGE_GRID_COLUMNS: AtomicUsize = AtomicUsize::new(0);
 static IMAGE_GRID_ROWS: AtomicUsize = AtomicUsize::new(0);
+const HISTORY_SHOWN: usize = 50;
 
-#[derive(PartialEq, Eq, PartialOrd, Ord)]
-enum SortKey {
-    Date(std::time::SystemTime),
-    Name(String),
-}
-
-#[derive(Clone, Serialize, Deserialize, Default, VariantArray, PartialEq)]
+#[derive(Clone, Serialize, Deserialize, Default, VariantArray, PartialEq, Debug)]
 pub enum SortBy {
+    /// Creation time, or the modification time where it is not recorded.
     #[default]
     Date,
     Name,
+    Modified,
+    Size,
+    /// Pixel count.
+    Resolution,
+    AspectRatio,
+    LastUsed,
+    MostUsed,
+    Random,
 }
 
 impl Display for SortBy {
@@ -75,11 +98,44 @@ impl Display for SortBy {
         let ret = match self {
             SortBy::Date => TRANSLATION.get_translation("Date"),
             SortBy::Name => TRANSLATION.get_translation("Name"),
+            SortBy::Modified => TRANSLATION.get_translation("date-modified"),
+            SortBy::Size => TRANSLATION.get_translation("file-size"),
+            SortBy::Resolution => TRANSLATION.get_translation("resolution"),
+            SortBy::AspectRatio => TRANSLATION.get_translation("aspect-ratio"),
+            SortBy::LastUsed => TRANSLATION.get_translation("last-used"),
+            SortBy::MostUsed => TRANSLATION.get_translation("most-used"),
+            SortBy::Random => TRANSLATION.get_translation("random-order"),
         };
         write!(f, "{ret}")
     }
 }
 
+#[derive(Clone, PartialEq)]
+struct MonitorOption {
+    name: String,
+    label: String,
+}
+
+impl Display for MonitorOption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.label)
+    }
+}
+
+/// Collection dropdown entry. `None` stands for all images in the filter and
+/// for "not editing" in the options menu.
+#[derive(Clone, PartialEq)]
+struct CollectionOption {
+    name: Option<String>,
+    label: String,
+}
+
+impl Display for CollectionOption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.label)
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct AppState {
@@ -87,6 +143,8 @@ pub struct AppState {
     pub executable_script: String,
     wallpaper_folder_doc: String,
     pub wallpaper_folder: Option<PathBuf>,
+    wallpaper_sources_doc: String,
+    pub wallpaper_sources: Vec<WallpaperSource>,
     saved_wallpapers_doc: String,
     pub saved_wallpapers: Vec<Wallpaper>,
     monitor_doc: String,
@@ -95,6 +153,8 @@ pub struct AppState {
     pub sort_by: Option<SortBy>,
     invert_sort_doc: String,
     pub invert_sort: bool,
+    random_sort_seed_doc: String,
+    pub random_sort_seed: u64,
     changer_doc: String,
     pub changer: Option<WallpaperChangers>,
     image_filter_doc: String,
@@ -167,6 +227,46 @@ pub struct AppState {
     pub theme: WaytrogenTheme,
     favorite_images_only_doc: String,
     pub favorite_images_only: bool,
+    monitor_profiles_doc: String,
+    pub monitor_profiles: Vec<MonitorProfile>,
+    match_monitor_orientation_doc: String,
+    pub match_monitor_orientation: bool,
+    distinct_random_monitors_doc: String,
+    pub distinct_random_monitors: bool,
+    weighted_random_doc: String,
+    pub weighted_random: bool,
+    span_monitors_doc: String,
+    pub span_monitors: bool,
+    collection_doc: String,
+    pub collection: Option<String>,
+    schedule_doc: String,
+    pub schedule: Vec<ScheduleEntry>,
+    schedule_location_doc: String,
+    pub schedule_location: Option<Location>,
+    /// Set by `--collection`; restricts `--random` and `--next` to that set.
+    #[serde(skip)]
+    pub source_collection: Option<String>,
+    /// Set by `--tag`; restricts `--random` and `--next` to images with
+    /// these tags, or without the ones starting with `-`.
+    #[serde(skip)]
+    pub source_tags: Vec<String>,
+    #[serde(skip)]
+    collections: Vec<String>,
+    #[serde(skip)]
+    collection_images: HashSet<PathBuf>,
+    #[serde(skip)]
+    editing_collection: Option<String>,
+    #[serde(skip)]
+    new_collection_name: String,
+    #[serde(skip)]
+    image_tags: HashMap<PathBuf, Vec<String>>,
+    #[serde(skip)]
+    tag_editor: Option<PathBuf>,
+    #[serde(skip)]
+    new_tag: String,
+    /// Loaded only while the grid is sorted by usage.
+    #[serde(skip)]
+    image_usage: HashMap<PathBuf, ImageUsage>,
     #[serde(skip)]
     image_grid_images: Vec<CacheImageFile>,
     #[serde(skip)]
@@ -174,6 +274,8 @@ pub struct AppState {
     #[serde(skip)]
     available_monitors: Vec<String>,
     #[serde(skip)]
+    monitor_details: Vec<Monitor>,
+    #[serde(skip)]
     available_changers: Vec<WallpaperChangers>,
     pub hyprpaper_fill_mode: Option<HyprpaperFitModes>,
     #[serde(skip)]
@@ -185,14 +287,35 @@ pub struct AppState {
     #[serde(skip)]
     pub show_awww_color_picker: bool,
     #[serde(skip)]
+    show_color_filter_picker: bool,
+    #[serde(skip)]
     pub internal_theme: Option<iced::Theme>,
     #[serde(skip)]
     pub image_grid_loading: bool,
+    /// Bumped on every scan so batches of an abandoned scan are ignored.
+    #[serde(skip)]
+    grid_generation: u64,
+    #[serde(skip)]
+    grid_scan: Option<iced::task::Handle>,
+    /// Images processed and found by the running scan.
+    #[serde(skip)]
+    grid_progress: (usize, usize),
     #[serde(skip)]
     pub gslapper_error: Option<String>,
     #[serde(skip)]
     pub show_gslapper_settings: bool,
     #[serde(skip)]
+    pub show_history: bool,
+    #[serde(skip)]
+    pub history: Vec<HistoryEntry>,
+    #[serde(skip)]
+    show_duplicates: bool,
+    /// Groups of visually similar images, the one to keep first.
+    #[serde(skip)]
+    duplicates: Vec<Vec<CacheImageFile>>,
+    #[serde(skip)]
+    duplicates_loading: bool,
+    #[serde(skip)]
     pub show_gslapper_advanced: bool,
     #[serde(skip)]
     pub gslapper_settings_draft: Option<GSllaperSettings>,
@@ -211,6 +334,8 @@ impl Default for AppState {
             executable_script: String::default(),
             wallpaper_folder_doc: TRANSLATION.get_translation("current-wallpaper-path-description"),
             wallpaper_folder: None,
+            wallpaper_sources_doc: TRANSLATION.get_translation("wallpaper-sources-description"),
+            wallpaper_sources: Vec::default(),
             saved_wallpapers_doc: TRANSLATION.get_translation("wallpaper-state-description"),
             saved_wallpapers: vec![Wallpaper::default()],
             monitor_doc: TRANSLATION.get_translation("monitor-dropdown-id-description"),
@@ -219,6 +344,8 @@ impl Default for AppState {
             sort_by: Option::default(),
             invert_sort_doc: TRANSLATION.get_translation("invert-sort-description"),
             invert_sort: bool::default(),
+            random_sort_seed_doc: TRANSLATION.get_translation("random-sort-seed-description"),
+            random_sort_seed: u64::default(),
             changer_doc: TRANSLATION.get_translation("last-used-changer-description"),
             changer: Option::default(),
             image_filter_doc: TRANSLATION.get_translation("the-search-string-for-the-wallpapers"),
@@ -311,21 +438,59 @@ impl Default for AppState {
             image_grid_images: Vec::default(),
             filtered_images: Vec::default(),
             available_monitors: Vec::default(),
+            monitor_details: Vec::default(),
             available_changers: Vec::default(),
             hyprpaper_fill_mode: Option::default(),
             sway_bg_color_internal: Color::default(),
             show_swaybg_color_picker: Default::default(),
             awww_fill_color_internal: Color::default(),
             show_awww_color_picker: Default::default(),
+            show_color_filter_picker: false,
             theme_doc: TRANSLATION.get_translation("theme-description"),
             theme: WaytrogenTheme::default(),
             internal_theme: Option::default(),
             favorite_images_only_doc: TRANSLATION
                 .get_translation("favorite-image-only-description"),
             favorite_images_only: false,
+            monitor_profiles_doc: TRANSLATION.get_translation("monitor-profiles-description"),
+            monitor_profiles: Vec::default(),
+            match_monitor_orientation_doc: TRANSLATION
+                .get_translation("match-monitor-orientation-description"),
+            match_monitor_orientation: false,
+            distinct_random_monitors_doc: TRANSLATION
+                .get_translation("distinct-random-monitors-description"),
+            distinct_random_monitors: false,
+            weighted_random_doc: TRANSLATION.get_translation("weighted-random-description"),
+            weighted_random: false,
+            span_monitors_doc: TRANSLATION.get_translation("span-monitors-description"),
+            span_monitors: false,
+            collection_doc: TRANSLATION.get_translation("collection-description"),
+            collection: None,
+            schedule_doc: TRANSLATION.get_translation("schedule-description"),
+            schedule: Vec::default(),
+            schedule_location_doc: TRANSLATION.get_translation("schedule-location-description"),
+            schedule_location: None,
+            source_collection: None,
+            source_tags: Vec::default(),
+            collections: Vec::default(),
+            collection_images: HashSet::default(),
+            editing_collection: None,
+            new_collection_name: String::default(),
+            image_tags: HashMap::default(),
+            image_usage: HashMap::default(),
+            tag_editor: None,
+            new_tag: String::default(),
             image_grid_loading: false,
+            grid_generation: 0,
+            grid_scan: None,
+            grid_progress: (0, 0),
             gslapper_error: None,
             show_gslapper_settings: false,
+            show_history: false,
+            history: Vec::default(),
+            show_duplicates: false,
+            duplicates: Vec::default(),
+            duplicates_loading: false,
             show_gslapper_advanced: false,
             gslapper_settings_draft: None,
             gslapper_status: None,
@@ -335,12 +500,23 @@ impl Default for AppState {
     }
 }
 
+#[derive(Clone)]
+pub struct WallpaperChange {
+    pub monitor: String,
+    pub changer: WallpaperChangers,
+    pub request: Option<IpcRequest>,
+}
+
 #[derive(Clone)]
 pub enum Messages {
     PopulateImageGrid,
-    ImageGridPopulated(AppStateImages),
+    ImageGridScanned(u64, usize),
+    ImageGridBatch(u64, usize, Vec<CacheImageFile>),
+    ImageGridPopulated(u64),
+    FolderChanged(FolderEvent),
+    FolderImagesCached(PathBuf, Vec<CacheImageFile>),
     ChangeWallpaper(PathBuf),
-    WallpaperChangeFinished(PathBuf, Result<(), String>),
+    WallpaperChangeFinished(WallpaperChange, PathBuf, Result<(), String>),
     ChangeWallpaperFolder,
     PopulateMonitorDropdown,
     MonitorDropdownPopulated(Vec<String>),
@@ -348,6 +524,11 @@ pub enum Messages {
     MonitorChanged(String),
     SortByChanged(SortBy),
     SearchBarInputted(String),
+    SearchTermToggled(&'static str),
+    ShowColorFilterPicker,
+    ColorFilterSubmitted(Color),
+    ColorFilterCancelled,
+    ColorFilterCleared,
     ImagesFiltered(AppStateImages),
     WallpaperChangerChanged(WallpaperChangers),
     InvertSortChanged(bool),
@@ -405,6 +586,75 @@ pub enum Messages {
     ImageGridScrollUp,
     ImgaeGridScrollDown,
     ResetRowOffset,
+    IpcCommandReceived(IpcRequest),
+    MatchMonitorOrientationToggled(bool),
+    DistinctRandomMonitorsToggled(bool),
+    WeightedRandomToggled(bool),
+    WallpaperWeightChanged(PathBuf, u8),
+    MonitorsChanged(MonitorEvent),
+    SpanMonitorsToggled(bool),
+    CollectionFilterChanged(Option<String>),
+    EditingCollectionChanged(Option<String>),
+    NewCollectionNameInputted(String),
+    CreateCollection,
+    DeleteCollection,
+    WallpaperCollectionToggle(PathBuf),
+    WallpaperSpanFinished(PathBuf, Result<Vec<Wallpaper>, String>),
+    OpenHistory,
+    CloseHistory,
+    HistoryDialogPressed,
+    HistoryUndo,
+    HistoryRedo,
+    HistoryStepFinished(HistoryEntry, bool, Result<(), String>),
+    OpenTagEditor(PathBuf),
+    CloseTagEditor,
+    TagEditorPressed,
+    NewTagInputted(String),
+    AddTag,
+    ImageTagToggled(String),
+    OpenDuplicates,
+    CloseDuplicates,
+    DuplicatesDialogPressed,
+    DuplicatesFound(Vec<Vec<CacheImageFile>>),
+    TrashDuplicates(usize),
+    TrashAllDuplicates,
+}
+
+fn image_context_menu<'a>(path: &Path, favorite: bool, weight: u8) -> Element<'a, Messages> {
+    let stars = (1..=MAX_IMAGE_WEIGHT).fold(row![].spacing(4), |stars, star| {
+        stars.push(
+            button(text!["{star}"])
+                .style(if star <= weight {
+                    button::primary
+                } else {
+                    button::secondary
+                })
+                .on_press(Messages::WallpaperWeightChanged(path.to_path_buf(), star)),
+        )
+    });
+    container(
+        column![
+            button(text![
+                "{}",
+                TRANSLATION.get_translation(if favorite {
+                    "remove-favorite"
+                } else {
+                    "add-favorite"
+                })
+            ])
+            .style(button::text)
+            .on_press(Messages::WallpaperFavoriteToggle(path.to_path_buf())),
+            text!["{}", TRANSLATION.get_translation("image-weight")],
+            stars,
+            button(text!["{}", TRANSLATION.get_translation("edit-tags")])
+                .style(button::text)
+                .on_press(Messages::OpenTagEditor(path.to_path_buf())),
+        ]
+        .spacing(6),
+    )
+    .padding(DEFAULT_MARGIN)
+    .style(container::bordered_box)
+    .into()
 }
 
 impl BootFn<AppState, Messages> for AppState {
@@ -455,6 +705,7 @@ impl BootFn<AppState, Messages> for AppState {
 
         if let Ok(m) = AvailableMonitors::get_monitors() {
             instance.available_monitors = m.available_monitors;
+            instance.monitor_details = m.monitors;
             if instance
                 .available_monitors
                 .contains(&instance.selected_monitor_item)
@@ -464,6 +715,9 @@ impl BootFn<AppState, Messages> for AppState {
                 instance.monitor = instance.available_monitors.first().cloned();
             }
         }
+        instance.apply_monitor_profile();
+        instance.load_collections();
+        instance.load_image_tags();
 
         let changer = if let Some(changer) = instance.changer.clone() {
             let c = match changer {
@@ -536,6 +790,102 @@ pub struct AppStateImages {
     pub unsupported_images: Vec<CacheImageFile>,
 }
 
+/// What the changer formats, search bar, favorites toggle and collection
+/// filter keep out of the grid.
+struct ImageFilter {
+    formats: Option<Vec<String>>,
+    query: Query,
+    image_tags: HashMap<PathBuf, Vec<String>>,
+    favorites_only: bool,
+    collection_images: Option<HashSet<PathBuf>>,
+}
+
+impl ImageFilter {
+    fn hides(&self, image: &CacheImageFile) -> bool {
+        self.formats.as_ref().is_some_and(|formats| {
+            let ext = image
+                .path
+                .extension()
+                .and_then(|e| e.to_str())
+                .unwrap_or_default();
+
+            !formats.contains(&ext.to_string())
+                || !self.query.matches(
+                    image,
+                    self.image_tags.get(&image.path).map_or(&[], Vec::as_slice),
+                )
+                || (self.favorites_only && !image.favorite)
+                || self
+                    .collection_images
+                    .as_ref()
+                    .is_some_and(|c| !c.contains(&image.path))
+        })
+    }
+}
+
+/// Search terms the toolbar toggles: the term, its label and the terms it
+/// replaces.
+const SEARCH_TOGGLES: [(&str, &str, &[&str]); 5] = [
+    ("image", "stills", &["video", "animation"]),
+    ("video", "videos", &["image", "animation"]),
+    ("landscape", "landscape", &["portrait"]),
+    ("portrait", "portrait", &["landscape"]),
+    ("w>=3840", "uhd", &[]),
+];
+
+const CACHED_BATCH_SIZE: usize = 512;
+const THUMBNAIL_BATCH_SIZE: usize = 32;
+
+/// Finds the images of `sources` and hands them to `send` in batches: first
+/// every image with an up to date thumbnail, then the ones that need a new
+/// thumbnail as they are generated. Stops early once `send` returns false.
+fn scan_image_grid(
+    generation: u64,
+    sources: &[WallpaperSource],
+    order: &SortOrder,
+    send: impl Fn(Messages) -> bool,
+) {
+    let mut entries = get_image_files(sources, &WallpaperChangers::all_accepted_formats());
+    sort_paths(&mut entries, order);
+    if !send(Messages::ImageGridScanned(generation, entries.len())) {
+        return;
+    }
+
+    let mut uncached = vec![];
+    for chunk in entries.chunks(CACHED_BATCH_SIZE) {
+        let looked_up = chunk
+            .par_iter()
+            .map_init(
+                || DatabaseConnection::new().ok(),
+                |conn, path| {
+                    let image = conn.as_ref().and_then(|c| c.select_fresh_image_file(path));
+                    (path, image)
+                },
+            )
+            .collect::<Vec<_>>();
+        let mut images = vec![];
+        for (path, image) in looked_up {
+            match image {
+                Some(image) => images.push(image),
+                None => uncached.push(path.clone()),
+            }
+        }
+        if !send(Messages::ImageGridBatch(generation, images.len(), images)) {
+            return;
+        }
+    }
+    for chunk in uncached.chunks(THUMBNAIL_BATCH_SIZE) {
+        let images = chunk
+            .par_iter()
+            .filter_map(|p| DatabaseConnection::check_cache(p).ok())
+            .collect();
+        if !send(Messages::ImageGridBatch(generation, chunk.len(), images)) {
+            return;
+        }
+    }
+    send(Messages::ImageGridPopulated(generation));
+}
+
 impl AppState {
     pub fn get_config_file() -> anyhow::Result<AppState> {
         let config_file = get_config_file_path()?;
@@ -585,95 +935,236 @@ impl AppState {
         }
         Ok(config_file_struct)
     }
-    fn populate_image_grid(&self) -> iced::Task<Messages> {
-        let invert_sort = self.invert_sort;
-        let changer = self.changer.clone();
+
+    pub fn apply_cli_arguments(&mut self, args: &Cli) {
+        if let Some(e) = args.external_script.as_ref() {
+            self.executable_script = e.clone();
+        }
+        if let Some(h) = args.hide_bottom_bar {
+            self.hide_changer_options_box = h;
+        }
+        self.source_collection.clone_from(&args.collection);
+        self.source_tags.clone_from(&args.tag);
+    }
+
+    #[must_use]
+    pub fn monitor_profile(&self, monitor: &str) -> Option<&MonitorProfile> {
+        self.monitor_profiles.iter().find(|p| p.monitor == monitor)
+    }
+
+    /// The folders to fill the grid from: `folder` with the rules configured
+    /// for it, followed by the other configured sources.
+    #[must_use]
+    pub fn wallpaper_sources_for(&self, folder: Option<&Path>) -> Vec<WallpaperSource> {
+        let mut sources = folder
+            .map(|f| {
+                self.wallpaper_sources
+                    .iter()
+                    .find(|s| s.path == f)
+                    .cloned()
+                    .unwrap_or_else(|| WallpaperSource::new(f.to_path_buf()))
+            })
+            .into_iter()
+            .collect::<Vec<_>>();
+        sources.extend(
+            self.wallpaper_sources
+                .iter()
+                .filter(|s| folder.is_none_or(|f| s.path != f))
+                .cloned(),
+        );
+        sources
+    }
+
+    fn save_monitor_profile(&mut self) {
+        let Some(monitor) = self.monitor.clone() else {
+            return;
+        };
+        let profile = MonitorProfile {
+            monitor,
+            wallpaper_folder: self.wallpaper_folder.clone(),
+            changer: self.changer.clone(),
+            sort_by: self.sort_by.clone(),
+            invert_sort: self.invert_sort,
+        };
+        match self
+            .monitor_profiles
+            .iter_mut()
+            .find(|p| p.monitor == profile.monitor)
+        {
+            Some(p) => *p = profile,
+            None => self.monitor_profiles.push(profile),
+        }
+    }
+
+    /// Switches folder, changer and sort settings to the selected monitor's
+    /// profile. Returns `false` when the monitor has no profile yet, in which
+    /// case the current settings are kept and become its profile on the next
+    /// switch.
+    fn apply_monitor_profile(&mut self) -> bool {
+        let Some(profile) = self
+            .monitor
+            .as_ref()
+            .and_then(|m| self.monitor_profile(m))
+            .cloned()
+        else {
+            return false;
+        };
+        self.wallpaper_folder = profile.wallpaper_folder;
+        if let Some(changer) = profile.changer {
+            self.load_changer_settings(&changer);
+            self.changer = Some(changer);
+        }
+        if profile.sort_by.is_some() {
+            self.sort_by = profile.sort_by;
+        }
+        self.invert_sort = profile.invert_sort;
+        true
+    }
+
+    fn load_changer_settings(&mut self, changer: &WallpaperChangers) {
+        match changer {
+            WallpaperChangers::Hyprpaper(settings) => {
+                self.hyprpaper_fill_mode = Some(settings.fit_mode.clone());
+            }
+            WallpaperChangers::Swaybg(settings) => {
+                self.swaybg_mode = Some(settings.mode.clone());
+                self.swaybg_color.clone_from(&settings.fill_color);
+            }
+            WallpaperChangers::MpvPaper(settings) => {
+                self.mpvpaper_pause_option = Some(settings.pause_mode.clone());
+                self.mpvpaper_slideshow_enable = settings.slideshow_settings.enable;
+                self.mpvpaper_slideshow_interval = settings.slideshow_settings.seconds;
+                self.mpvpaper_additional_options
+                    .clone_from(&settings.additional_options);
+            }
+            WallpaperChangers::Awww(settings) => {
+                self.awww_resize = Some(settings.resize_mode.clone());
+                self.awww_fill_color.clone_from(&settings.fill_color);
+                self.awww_scaling_filter = Some(settings.scalling_filter.clone());
+                self.awww_transition_type = Some(settings.transition_type.clone());
+                self.awww_transition_step = settings.transition_step;
+                self.awww_transition_duration = settings.transition_duration;
+                self.awww_transition_fps = settings.transition_fps;
+                self.awww_transition_angle = settings.transition_angle;
+                self.awww_transition_position
+                    .clone_from(&settings.transition_position.position);
+                self.awww_invert_y = settings.invert_y;
+                self.awww_transition_bezier_p0 = settings.transition_bezier.p0;
+                self.awww_transition_bezier_p1 = settings.transition_bezier.p1;
+                self.awww_transition_bezier_p2 = settings.transition_bezier.p2;
+                self.awww_transition_bezier_p3 = settings.transition_bezier.p3;
+                self.awww_transition_wave_width = settings.transition_wave.width;
+                self.awww_transition_wave_height = settings.transition_wave.height;
+            }
+            WallpaperChangers::GSlapper(settings) => {
+                self.gslapper_scale_mode = Some(settings.scale_mode.clone());
+                self.gslapper_pause_mode = Some(settings.pause_mode.clone());
+                self.gslapper_loop = settings.loop_video;
+                self.gslapper_additional_options
+                    .clone_from(&settings.additional_options);
+                self.gslapper_fps_cap = settings.fps_cap;
+                self.gslapper_transition_enabled = settings.transition_enabled;
+                self.gslapper_transition_duration = settings.transition_duration;
+                self.gslapper_cache_size = settings.cache_size_mb;
+            }
+        }
+    }
+
+    fn populate_image_grid(&mut self) -> iced::Task<Messages> {
+        if let Some(scan) = self.grid_scan.take() {
+            scan.abort();
+        }
+        self.grid_generation += 1;
+        self.grid_progress = (0, 0);
+        self.image_grid_images = vec![];
+        self.filtered_images = vec![];
+        let generation = self.grid_generation;
+        let (invert, seed) = (self.invert_sort, self.random_sort_seed);
 
         let Some(sort_by) = self.sort_by.clone() else {
             return Task::none();
         };
-        let Some(wf) = self.wallpaper_folder.clone() else {
-            return Task::done(Messages::ImageGridPopulated(AppStateImages::default()));
-        };
-        if !wf.is_dir() {
+        self.image_usage = load_usage(&sort_by);
+        let usage = self.image_usage.clone();
+        let sources = self.wallpaper_sources_for(self.wallpaper_folder.as_deref());
+        if sources.is_empty() {
+            return Task::done(Messages::ImageGridPopulated(generation));
+        }
+        if !sources.iter().any(|s| s.path.is_dir()) {
             return Task::none();
         }
 
-        let accepted_formats = WallpaperChangers::all_accepted_formats();
+        let stream = iced::stream::channel(4, async move |output| {
+            let (tx, rx) = futures::channel::oneshot::channel();
+            rayon::spawn(move || {
+                let order = SortOrder {
+                    sort_by: &sort_by,
+                    invert,
+                    seed,
+                    usage: &usage,
+                };
+                scan_image_grid(generation, &sources, &order, |message| {
+                    futures::executor::block_on(output.clone().send(message)).is_ok()
+                });
+                let _ = tx.send(());
+            });
+            let _ = rx.await;
+        });
+        let (task, handle) = Task::stream(stream).abortable();
+        self.grid_scan = Some(handle);
+        task
+    }
 
+    /// Caches the images at `path`, a file or a directory that changed in one of
+    /// the wallpaper sources. Only images that are new or changed get a
+    /// thumbnail.
+    fn cache_changed_images(&self, path: PathBuf) -> Task<Messages> {
+        let accepted_formats = WallpaperChangers::all_accepted_formats();
+        let sources = self.wallpaper_sources_for(self.wallpaper_folder.as_deref());
         Task::future(async move {
             let (tx, rx) = futures::channel::oneshot::channel();
-
             rayon::spawn(move || {
-                let mut entries: Vec<(PathBuf, SortKey)> = WalkDir::new(&wf)
+                let filters = sources
+                    .iter()
+                    .map(|s| s.filter(&accepted_formats))
+                    .collect::<Vec<_>>();
+                let images = WalkDir::new(&path)
                     .into_iter()
                     .filter_map(std::result::Result::ok)
-                    .filter_map(|e| {
-                        let path = e.into_path();
-                        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
-                        if !accepted_formats.contains(&ext) {
-                            return None;
-                        }
-                        let key = match sort_by {
-                            SortBy::Date => {
-                                let ts = std::fs::metadata(&path)
-                                    .and_then(|m| m.created())
-                                    .unwrap_or(SystemTime::UNIX_EPOCH);
-                                SortKey::Date(ts)
-                            }
-                            SortBy::Name => {
-                                let name = path
-                                    .file_name()
-                                    .map(|n| n.to_string_lossy().into_owned())
-                                    .unwrap_or_default();
-                                SortKey::Name(name)
-                            }
-                        };
-                        Some((path, key))
-                    })
-                    .collect();
-
-                entries.sort_unstable_by(|(_, a), (_, b)| {
-                    let ord = a.cmp(b);
-                    if invert_sort { ord.reverse() } else { ord }
-                });
-
-                let all_images = entries
+                    .filter(|e| e.file_type().is_file())
+                    .map(walkdir::DirEntry::into_path)
+                    .filter(|p| filters.iter().any(|f| f.accepts(p)))
+                    .collect::<Vec<_>>()
                     .into_par_iter()
-                    .filter_map(|(p, _)| DatabaseConnection::check_cache(&p).ok())
-                    .collect::<Vec<_>>();
-
-                // Categorize on the rayon thread instead of blocking update()
-                let (mut supported, mut unsupported) = (vec![], vec![]);
-                if let Some(ref changer) = changer {
-                    let formats = changer.accepted_formats();
-                    for image in all_images {
-                        let ext = image
-                            .cached_image_path
-                            .extension()
-                            .and_then(|e| e.to_str())
-                            .unwrap_or_default()
-                            .to_string();
-                        if formats.contains(&ext) {
-                            supported.push(image);
-                        } else {
-                            unsupported.push(image);
-                        }
-                    }
-                }
-
-                let _ = tx.send(AppStateImages {
-                    supported_images: supported,
-                    unsupported_images: unsupported,
-                });
+                    .filter_map(|p| DatabaseConnection::check_cache(&p).ok())
+                    .collect();
+                let _ = tx.send((path, images));
             });
-
-            rx.await.unwrap_or_else(|_| AppStateImages {
-                supported_images: vec![],
-                unsupported_images: vec![],
-            })
+            rx.await.ok()
         })
-        .then(|images| Task::done(Messages::ImageGridPopulated(images)))
+        .then(|cached| match cached {
+            Some((path, images)) => Task::done(Messages::FolderImagesCached(path, images)),
+            None => Task::none(),
+        })
+    }
+
+    fn remove_images_under(&mut self, path: &Path) {
+        self.image_grid_images.retain(|i| !i.path.starts_with(path));
+        self.filtered_images.retain(|i| !i.path.starts_with(path));
+    }
+
+    fn handle_folder_event(&mut self, event: FolderEvent) -> Task<Messages> {
+        match event {
+            FolderEvent::Changed(path) => self.cache_changed_images(path),
+            FolderEvent::Removed(path) => {
+                self.remove_images_under(&path);
+                Task::none()
+            }
+            FolderEvent::Renamed(from, to) => {
+                self.remove_images_under(&from);
+                self.cache_changed_images(to)
+            }
+        }
     }
 
     pub fn write_to_config_file(&self) -> anyhow::Result<()> {
@@ -703,48 +1194,63 @@ impl AppState {
     }
 
     fn sort_image_grid(&mut self, sort_by: &SortBy) {
-        let comparator = match sort_by {
-            SortBy::Date => match &self.invert_sort {
-                true => |x: &CacheImageFile, y: &CacheImageFile| y.date.cmp(&x.date),
-                false => |x: &CacheImageFile, y: &CacheImageFile| x.date.cmp(&y.date),
-            },
-            SortBy::Name => match &self.invert_sort {
-                true => |x: &CacheImageFile, y: &CacheImageFile| y.name.cmp(&x.name),
-                false => |x: &CacheImageFile, y: &CacheImageFile| x.name.cmp(&y.name),
-            },
+        let order = SortOrder {
+            sort_by,
+            invert: self.invert_sort,
+            seed: self.random_sort_seed,
+            usage: &self.image_usage,
         };
-        self.image_grid_images.sort_by(comparator);
+        sort_images(&mut self.image_grid_images, &order);
+        if let Some(color) = Query::parse(&self.image_filter).color() {
+            // Closest colors first, the chosen order breaks ties.
+            self.image_grid_images.sort_by(|x, y| {
+                palette_distance(&x.palette, color).total_cmp(&palette_distance(&y.palette, color))
+            });
+        }
+    }
+
+    fn image_filter(&self, query: String) -> ImageFilter {
+        ImageFilter {
+            formats: self
+                .changer
+                .as_ref()
+                .map(super::wallpaper_changers::WallpaperChanger::accepted_formats),
+            query: Query::parse(&query),
+            image_tags: self.image_tags.clone(),
+            favorites_only: self.favorite_images_only,
+            collection_images: self
+                .collection
+                .as_ref()
+                .map(|_| self.collection_images.clone()),
+        }
+    }
+
+    /// Adds images to the grid, or to the filtered out ones when the current
+    /// filter hides them.
+    fn add_grid_images(&mut self, images: Vec<CacheImageFile>) {
+        let filter = self.image_filter(self.image_filter.clone());
+        for image in images {
+            if filter.hides(&image) {
+                self.filtered_images.push(image);
+            } else {
+                self.image_grid_images.push(image);
+            }
+        }
+        if let Some(s) = &self.sort_by.clone() {
+            self.sort_image_grid(s);
+        }
     }
 
     fn filter_images(&self, query: String) -> iced::Task<Messages> {
         let mut all_images = self.image_grid_images.clone();
         all_images.append(&mut self.filtered_images.clone());
-
-        let accepted_formats = self
-            .changer
-            .as_ref()
-            .map(super::wallpaper_changers::WallpaperChanger::accepted_formats);
-        let favorites_only = self.favorite_images_only;
+        let filter = self.image_filter(query);
 
         Task::future(async move {
             let (tx, rx) = futures::channel::oneshot::channel();
 
             rayon::spawn(move || {
-                let mut unsupported_images = vec![];
-
-                if let Some(formats) = accepted_formats {
-                    unsupported_images.extend(all_images.extract_if(.., |i| {
-                        let ext = i
-                            .path
-                            .extension()
-                            .and_then(|e| e.to_str())
-                            .unwrap_or_default();
-
-                        !formats.contains(&ext.to_string())
-                            || !i.name.contains(&query)
-                            || (favorites_only && !i.favorite)
-                    }));
-                }
+                let unsupported_images = all_images.extract_if(.., |i| filter.hides(i)).collect();
 
                 let _ = tx.send(AppStateImages {
                     supported_images: all_images,
@@ -759,20 +1265,73 @@ impl AppState {
         })
         .then(|result| Task::done(Messages::ImagesFiltered(result)))
     }
+
+    /// Looks for duplicates among all images of the grid, hidden ones included.
+    fn find_grid_duplicates(&self) -> Task<Messages> {
+        let mut all_images = self.image_grid_images.clone();
+        all_images.append(&mut self.filtered_images.clone());
+
+        Task::future(async move {
+            let (tx, rx) = futures::channel::oneshot::channel();
+            rayon::spawn(move || {
+                let _ = tx.send(find_duplicates(&all_images));
+            });
+            rx.await.unwrap_or_default()
+        })
+        .then(|groups| Task::done(Messages::DuplicatesFound(groups)))
+    }
+
+    fn trash_duplicate_group(&mut self, index: usize) {
+        if index >= self.duplicates.len() {
+            return;
+        }
+        let group = self.duplicates.remove(index);
+        for image in group.iter().skip(1) {
+            match move_to_trash(&image.path) {
+                Ok(()) => self.remove_images_under(&image.path),
+                Err(e) => {
+                    error!("Failed to trash {}: {e}", image.path.display());
+                    self.gslapper_error = Some(format!("{}: {e}", image.path.display()));
+                }
+            }
+        }
+    }
+
     fn change_wallpaper(&self, path: PathBuf) -> Task<Messages> {
         let Some(changer) = self.changer.clone() else {
             return Task::none();
         };
+        if self.span_monitors {
+            let monitors = self.monitor_details.clone();
+            return Task::future(async move {
+                let result =
+                    span_wallpaper(&changer, &path, &monitors).map_err(|error| error.to_string());
+                (path, result)
+            })
+            .then(|(path, result)| Task::done(Messages::WallpaperSpanFinished(path, result)));
+        }
         let Some(monitor) = self.monitor.clone() else {
             return Task::none();
         };
+        Self::apply_wallpaper(
+            WallpaperChange {
+                monitor,
+                changer,
+                request: None,
+            },
+            path,
+        )
+    }
+
+    fn apply_wallpaper(change: WallpaperChange, path: PathBuf) -> Task<Messages> {
         Task::future(async move {
-            let result = changer
-                .change(path.clone(), monitor)
+            let result = change
+                .changer
+                .clone()
+                .change(path.clone(), change.monitor.clone())
                 .map_err(|error| error.to_string());
-            (path, result)
+            Messages::WallpaperChangeFinished(change, path, result)
         })
-        .then(|(path, result)| Task::done(Messages::WallpaperChangeFinished(path, result)))
     }
 
     fn load_gslapper_runtime(&self) -> Task<Messages> {
@@ -791,11 +1350,15 @@ impl AppState {
             .then(|result| Task::done(Messages::GSlapperRuntimeLoaded(result)))
     }
 
-    fn execute_external_script(&self, wallpaper_path: &Path) -> Task<Messages> {
+    fn execute_external_script(
+        &self,
+        monitor: Option<&str>,
+        wallpaper_path: &Path,
+    ) -> Task<Messages> {
         let external_script_path = self.executable_script.clone();
         let wallpaper_path = wallpaper_path.to_path_buf();
         let internal_state = self.clone();
-        let monitor = self.monitor.clone();
+        let monitor = monitor.map(str::to_owned);
         Task::future(async move {
             let external_script_path = match std::fs::canonicalize(external_script_path.clone()) {
                 Ok(p) => p,
@@ -870,50 +1433,664 @@ impl AppState {
         }
     }
 
-    pub fn update(&mut self, message: Messages) -> Task<Messages> {
-        match message {
-            Messages::PopulateImageGrid => {
-                self.image_grid_loading = true;
-                self.populate_image_grid()
+    fn set_image_weight(&mut self, image_path: &Path, weight: u8) {
+        let result = DatabaseConnection::new().and_then(|conn| {
+            let mut image_file = conn.select_image_file(image_path)?;
+            image_file.weight = weight;
+            conn.insert_image_file(&image_file)
+        });
+        if let Err(e) = result {
+            error!("Failed to rate {}: {e}", image_path.display());
+            return;
+        }
+        self.image_grid_images
+            .iter_mut()
+            .chain(self.filtered_images.iter_mut())
+            .filter(|p| p.path == image_path)
+            .for_each(|p| p.weight = weight);
+    }
+
+    fn load_collections(&mut self) {
+        let result = DatabaseConnection::new().and_then(|conn| {
+            self.collections = conn.select_collections()?;
+            self.collection_images = match &self.collection {
+                Some(name) => conn.select_collection_images(name)?.into_iter().collect(),
+                None => HashSet::default(),
+            };
+            Ok(())
+        });
+        if let Err(e) = result {
+            error!("Failed to load collections: {e}");
+        }
+        if self
+            .collection
+            .as_ref()
+            .is_some_and(|c| !self.collections.contains(c))
+        {
+            self.collection = None;
+        }
+        if self
+            .editing_collection
+            .as_ref()
+            .is_some_and(|c| !self.collections.contains(c))
+        {
+            self.editing_collection = None;
+        }
+    }
+
+    fn save_applied_wallpaper(
+        &mut self,
+        monitor: &str,
+        wallpaper_path: &Path,
+        changer: &WallpaperChangers,
+    ) {
+        if monitor == TRANSLATION.get_translation("All") {
+            self.saved_wallpapers = self
+                .saved_wallpapers
+                .iter()
+                .filter(|i| i.monitor == TRANSLATION.get_translation("All"))
+                .cloned()
+                .collect::<Vec<_>>();
+        } else {
+            self.saved_wallpapers = self
+                .saved_wallpapers
+                .iter()
+                .filter(|i| i.monitor != TRANSLATION.get_translation("All"))
+                .cloned()
+                .collect::<Vec<_>>();
+        }
+        let monitor_description = self
+            .monitor_details
+            .iter()
+            .find(|m| m.name == monitor)
+            .map(|m| m.description.clone())
+            .unwrap_or_default();
+        match self
+            .saved_wallpapers
+            .iter_mut()
+            .find(|w| w.monitor == monitor)
+        {
+            Some(w) => {
+                w.changer = changer.clone();
+                w.path = wallpaper_path.to_str().unwrap_or_default().to_string();
+                w.monitor_description = monitor_description;
+                w.span = false;
+            }
+            None => self.saved_wallpapers.push(Wallpaper {
+                monitor: monitor.to_owned(),
+                path: wallpaper_path.to_str().unwrap_or_default().to_string(),
+                changer: changer.clone(),
+                monitor_description,
+                span: false,
+            }),
+        }
+    }
+
+    fn record_history(
+        &mut self,
+        monitor: &str,
+        wallpaper_path: &Path,
+        changer: &WallpaperChangers,
+    ) {
+        if let Err(e) = DatabaseConnection::new()
+            .and_then(|conn| conn.insert_history(monitor, wallpaper_path, changer))
+        {
+            error!("Failed to record wallpaper history: {e}");
+        }
+        self.load_history();
+    }
+
+    fn load_history(&mut self) {
+        let Some(monitor) = &self.monitor else {
+            self.history.clear();
+            return;
+        };
+        match DatabaseConnection::new().and_then(|conn| conn.select_history(monitor, HISTORY_SHOWN))
+        {
+            Ok(history) => self.history = history,
+            Err(e) => error!("Failed to load wallpaper history: {e}"),
+        }
+    }
+
+    /// Applies the previous (`undo`) or next wallpaper from the selected
+    /// monitor's history. The step is only recorded in the database, a new
+    /// history entry is not added.
+    fn step_history(&mut self, undo: bool) -> Task<Messages> {
+        let Some(monitor) = self.monitor.clone() else {
+            return Task::none();
+        };
+        let entry = DatabaseConnection::new().and_then(|conn| {
+            if undo {
+                conn.undo_history(&monitor)
+            } else {
+                conn.redo_history(&monitor)
             }
-            Messages::ImageGridPopulated(i) => {
-                self.image_grid_images = i.supported_images;
-                self.filtered_images = i.unsupported_images;
-                self.filter_images(self.image_filter.clone())
+        });
+        let entry = match entry {
+            Ok(Some(entry)) => entry,
+            Ok(None) => return Task::none(),
+            Err(e) => {
+                error!("Failed to read wallpaper history: {e}");
+                return Task::none();
             }
-            Messages::ChangeWallpaper(p) => self.change_wallpaper(p),
-            Messages::ChangeWallpaperFolder => Self::open_wallpaper_folder_file_dialog(),
-            Messages::WallpaperFolderChanged(f) => {
-                self.wallpaper_folder = Some(f);
-                self.image_grid_images = vec![];
-                self.filtered_images = vec![];
-                Task::done(Messages::PopulateImageGrid)
+        };
+        Task::future(async move {
+            let result = entry
+                .changer
+                .clone()
+                .change(entry.path.clone(), entry.monitor.clone())
+                .map_err(|error| error.to_string());
+            (entry, result)
+        })
+        .then(move |(entry, result)| Task::done(Messages::HistoryStepFinished(entry, undo, result)))
+    }
+
+    fn history_step_finished(
+        &mut self,
+        entry: &HistoryEntry,
+        undo: bool,
+        result: Result<(), String>,
+    ) -> Task<Messages> {
+        if let Err(error) = result {
+            // Put the history back where it was, the wallpaper did not change.
+            let reverted = DatabaseConnection::new().and_then(|conn| {
+                if undo {
+                    conn.redo_history(&entry.monitor)
+                } else {
+                    conn.undo_history(&entry.monitor)
+                }
+            });
+            if let Err(e) = reverted {
+                error!("Failed to revert wallpaper history: {e}");
             }
-            Messages::MonitorDropdownPopulated(monitors) => {
-                self.available_monitors = monitors;
-                self.monitor = self.available_monitors.first().cloned();
-                Task::none()
+            self.gslapper_error = Some(error);
+            self.load_history();
+            return Task::none();
+        }
+        self.gslapper_error = None;
+        self.save_applied_wallpaper(&entry.monitor, &entry.path, &entry.changer);
+        self.load_history();
+        self.execute_external_script(Some(&entry.monitor), &entry.path)
+    }
+
+    fn create_collection(&mut self) -> Task<Messages> {
+        let name = self.new_collection_name.trim().to_owned();
+        if name.is_empty() {
+            return Task::none();
+        }
+        match DatabaseConnection::new().and_then(|conn| conn.insert_collection(&name)) {
+            Ok(()) => {
+                self.new_collection_name.clear();
+                self.editing_collection = Some(name);
+                self.load_collections();
             }
-            Messages::MonitorChanged(m) => {
-                self.monitor = Some(m.clone());
-                self.selected_monitor_item = m;
+            Err(e) => error!("Failed to create collection \"{name}\": {e}"),
+        }
+        Task::none()
+    }
+
+    fn delete_collection(&mut self) -> Task<Messages> {
+        let Some(name) = self.editing_collection.take() else {
+            return Task::none();
+        };
+        if let Err(e) = DatabaseConnection::new().and_then(|conn| conn.delete_collection(&name)) {
+            error!("Failed to delete collection \"{name}\": {e}");
+        }
+        self.load_collections();
+        self.filter_images(self.image_filter.clone())
+    }
+
+    fn toggle_collection_image(&mut self, image_path: &Path) -> Task<Messages> {
+        let Some(name) = self.editing_collection.clone() else {
+            return self.toggle_favorite_image(image_path);
+        };
+        let result = DatabaseConnection::new().and_then(|conn| {
+            if conn
+                .select_collection_images(&name)?
+                .iter()
+                .any(|p| p == image_path)
+            {
+                conn.delete_collection_image(&name, image_path)
+            } else {
+                conn.insert_collection_image(&name, image_path)
+            }
+        });
+        if let Err(e) = result {
+            error!("Failed to update collection \"{name}\": {e}");
+            return Task::none();
+        }
+        if self.collection.as_ref() == Some(&name) {
+            self.load_collections();
+            self.filter_images(self.image_filter.clone())
+        } else {
+            Task::none()
+        }
+    }
+
+    fn load_image_tags(&mut self) {
+        match DatabaseConnection::new().and_then(|conn| conn.select_image_tags()) {
+            Ok(tags) => self.image_tags = tags,
+            Err(e) => error!("Failed to load image tags: {e}"),
+        }
+    }
+
+    fn known_tags(&self) -> Vec<&String> {
+        let mut tags = self.image_tags.values().flatten().collect::<Vec<_>>();
+        tags.sort();
+        tags.dedup();
+        tags
+    }
+
+    fn toggle_image_tag(&mut self, tag: &str) -> Task<Messages> {
+        let (Some(path), tag) = (self.tag_editor.clone(), normalize_tag(tag)) else {
+            return Task::none();
+        };
+        if tag.is_empty() {
+            return Task::none();
+        }
+        let tags = self.image_tags.entry(path.clone()).or_default();
+        let tagged = tags.contains(&tag);
+        let result = DatabaseConnection::new().and_then(|conn| {
+            if tagged {
+                conn.delete_image_tag(&path, &tag)
+            } else {
+                conn.insert_image_tag(&path, &tag)
+            }
+        });
+        if let Err(e) = result {
+            error!("Failed to update the tags of {}: {e}", path.display());
+            return Task::none();
+        }
+        if tagged {
+            tags.retain(|t| *t != tag);
+            if tags.is_empty() {
+                self.image_tags.remove(&path);
+            }
+        } else {
+            tags.push(tag);
+            tags.sort();
+        }
+        self.filter_images(self.image_filter.clone())
+    }
+
+    fn collection_options(&self, none_label: &str) -> Vec<CollectionOption> {
+        std::iter::once(CollectionOption {
+            name: None,
+            label: none_label.to_owned(),
+        })
+        .chain(self.collections.iter().map(|c| CollectionOption {
+            name: Some(c.clone()),
+            label: c.clone(),
+        }))
+        .collect()
+    }
+
+    fn next_grid_image(&self) -> Option<PathBuf> {
+        let monitor = self.monitor.as_ref()?;
+        let current = self
+            .saved_wallpapers
+            .iter()
+            .find(|w| w.monitor == *monitor)
+            .map(|w| PathBuf::from(&w.path));
+        let index = self
+            .image_grid_images
+            .iter()
+            .position(|i| Some(&i.path) == current.as_ref())
+            .map_or(0, |i| (i + 1) % self.image_grid_images.len());
+        self.image_grid_images.get(index).map(|i| i.path.clone())
+    }
+
+    /// Where an IPC change to `monitor` goes: the selected monitor uses the
+    /// changer on screen, any other one its profile's changer, so the GUI
+    /// selection stays where the user left it.
+    fn ipc_wallpaper_change(
+        &self,
+        monitor: &str,
+        request: &IpcRequest,
+    ) -> anyhow::Result<WallpaperChange> {
+        if !self.available_monitors.iter().any(|m| m == monitor) {
+            return Err(anyhow!("Display \"{monitor}\" does not exist."));
+        }
+        let changer = if self.monitor.as_deref() == Some(monitor) {
+            self.changer.clone()
+        } else {
+            self.monitor_profile(monitor)
+                .and_then(|p| p.changer.clone())
+        }
+        .unwrap_or_else(|| changer_for_monitor(monitor, self));
+        Ok(WallpaperChange {
+            monitor: monitor.to_owned(),
+            changer,
+            request: Some(request.clone()),
+        })
+    }
+
+    fn draw_grid_image(&self) -> anyhow::Result<Option<PathBuf>> {
+        let monitor = self.monitor.clone().unwrap_or_default();
+        let mut eligible = self
+            .image_grid_images
+            .iter()
+            .map(|i| i.path.clone())
+            .collect::<Vec<_>>();
+        if self.match_monitor_orientation
+            && let Some(details) = self.monitor_details.iter().find(|m| m.name == monitor)
+        {
+            // Same rule as `--random`: images of unknown size stay eligible and
+            // the orientation is ignored when nothing matches it.
+            let orientation = details.orientation();
+            let matching = self
+                .image_grid_images
+                .iter()
+                .filter(|i| {
+                    i.media
+                        .is_none_or(|m| Orientation::from_size(m.width, m.height) == orientation)
+                })
+                .map(|i| i.path.clone())
+                .collect::<Vec<_>>();
+            if matching.is_empty() {
+                warn!(
+                    "No {orientation} wallpapers found for display \"{monitor}\", ignoring orientation."
+                );
+            } else {
+                eligible = matching;
+            }
+        }
+        let avoid = images_to_avoid(
+            &monitor,
+            &self.saved_wallpapers,
+            self.distinct_random_monitors,
+        );
+        draw_image(
+            &DatabaseConnection::new()?,
+            &monitor,
+            &eligible,
+            &avoid,
+            self.weighted_random,
+        )
+    }
+
+    /// Replaces every persisted setting with `config`, booting it like a fresh
+    /// start, while the open dialogs, the running scan and the `--collection`
+    /// and `--tag` filters carry over.
+    fn reload_config(&mut self, config: &AppState) -> Task<Messages> {
+        let (mut reloaded, task) = config.boot();
+        reloaded.source_collection = self.source_collection.take();
+        reloaded.source_tags = std::mem::take(&mut self.source_tags);
+        reloaded.editing_collection = self.editing_collection.take();
+        reloaded.new_collection_name = std::mem::take(&mut self.new_collection_name);
+        reloaded.tag_editor = self.tag_editor.take();
+        reloaded.new_tag = std::mem::take(&mut self.new_tag);
+        reloaded.grid_generation = self.grid_generation;
+        reloaded.grid_scan = self.grid_scan.take();
+        reloaded.gslapper_error = self.gslapper_error.take();
+        reloaded.show_gslapper_settings = self.show_gslapper_settings;
+        reloaded.show_gslapper_advanced = self.show_gslapper_advanced;
+        reloaded.gslapper_settings_draft = self.gslapper_settings_draft.take();
+        reloaded.gslapper_status = self.gslapper_status.take();
+        reloaded.gslapper_cache_status = self.gslapper_cache_status.take();
+        reloaded.show_history = self.show_history;
+        reloaded.show_duplicates = self.show_duplicates;
+        reloaded.duplicates = std::mem::take(&mut self.duplicates);
+        reloaded.duplicates_loading = self.duplicates_loading;
+        if reloaded.show_history {
+            reloaded.load_history();
+        }
+        *self = reloaded;
+        task
+    }
+
+    fn handle_ipc_request(&mut self, request: &IpcRequest) -> Task<Messages> {
+        let selected = self.monitor.clone().unwrap_or_default();
+        let (change, path) = match &request.command {
+            IpcCommand::Set { monitor, path } => (
+                self.ipc_wallpaper_change(monitor, request),
+                Ok(Some(path.clone())),
+            ),
+            IpcCommand::Next(Some(monitor)) if *monitor != selected => {
+                let change = match self.ipc_wallpaper_change(monitor, request) {
+                    Ok(change) => change,
+                    Err(e) => {
+                        request.respond(Err(e));
+                        return Task::none();
+                    }
+                };
+                let state = self.clone();
+                return Task::future(async move {
+                    let path = next_wallpaper(&change.monitor, &state);
+                    (change, path)
+                })
+                .then(|(change, path)| match path {
+                    Some(path) => Self::apply_wallpaper(change, path),
+                    None => {
+                        if let Some(request) = &change.request {
+                            request.respond(Err(anyhow!("No wallpapers are available")));
+                        }
+                        Task::none()
+                    }
+                });
+            }
+            IpcCommand::Next(_) => (
+                self.ipc_wallpaper_change(&selected, request),
+                Ok(self.next_grid_image()),
+            ),
+            IpcCommand::Random => (
+                self.ipc_wallpaper_change(&selected, request),
+                self.draw_grid_image(),
+            ),
+            IpcCommand::Query => {
+                request.respond(
+                    serde_json::to_string(&self.saved_wallpapers).map_err(anyhow::Error::from),
+                );
+                return Task::none();
+            }
+            IpcCommand::Reload => {
+                return match AppState::get_config_file() {
+                    Ok(config) => {
+                        let task = self.reload_config(&config);
+                        request.respond(Ok("OK".to_owned()));
+                        task
+                    }
+                    Err(e) => {
+                        request.respond(Err(e));
+                        Task::none()
+                    }
+                };
+            }
+        };
+        match change.and_then(|c| path.map(|p| (c, p))) {
+            Ok((change, Some(p))) => Self::apply_wallpaper(change, p),
+            Ok((_, None)) => {
+                request.respond(Err(anyhow!("No wallpapers are available in the grid")));
                 Task::none()
             }
+            Err(e) => {
+                request.respond(Err(e));
+                Task::none()
+            }
+        }
+    }
+
+    fn handle_monitor_event(&mut self, event: MonitorEvent) -> Task<Messages> {
+        let task = match event {
+            MonitorEvent::Added(monitor) => {
+                self.monitor_details.retain(|m| m.name != monitor.name);
+                self.monitor_details.push(monitor.clone());
+                self.reapply_saved_wallpaper(&monitor)
+            }
+            MonitorEvent::Changed(monitor) => {
+                if let Some(m) = self
+                    .monitor_details
+                    .iter_mut()
+                    .find(|m| m.name == monitor.name)
+                {
+                    *m = monitor;
+                }
+                Task::none()
+            }
+            MonitorEvent::Removed(name) => {
+                self.monitor_details.retain(|m| m.name != name);
+                if self.monitor.as_ref() == Some(&name)
+                    && let Some(all) = self.available_monitors.first().cloned()
+                {
+                    Task::done(Messages::MonitorChanged(all))
+                } else {
+                    Task::none()
+                }
+            }
+        };
+        self.monitor_details.sort_by(|m1, m2| m1.name.cmp(&m2.name));
+        self.available_monitors = monitor_names(&self.monitor_details);
+        task
+    }
+
+    fn reapply_saved_wallpaper(&mut self, monitor: &Monitor) -> Task<Messages> {
+        let Some((index, target)) =
+            saved_wallpaper_for(monitor, &self.saved_wallpapers, &self.monitor_details)
+        else {
+            return Task::none();
+        };
+        // Remember the new connector name so --restore finds this screen too.
+        self.saved_wallpapers[index].monitor.clone_from(&target);
+        let wallpaper = self.saved_wallpapers[index].clone();
+        let monitors = self.monitor_details.clone();
+        Task::future(async move {
+            if let Err(e) = change_saved_wallpaper(&wallpaper, &target, &monitors) {
+                error!("Failed to restore wallpaper on \"{target}\": {e}");
+            }
+        })
+        .discard()
+    }
+
+    pub fn update(&mut self, message: Messages) -> Task<Messages> {
+        match message {
+            Messages::PopulateImageGrid => {
+                self.image_grid_loading = true;
+                self.populate_image_grid()
+            }
+            Messages::ImageGridScanned(generation, found) => {
+                if generation == self.grid_generation {
+                    self.grid_progress.1 = found;
+                }
+                Task::none()
+            }
+            Messages::ImageGridBatch(generation, processed, images) => {
+                if generation == self.grid_generation {
+                    self.grid_progress.0 += processed;
+                    self.add_grid_images(images);
+                }
+                Task::none()
+            }
+            Messages::ImageGridPopulated(generation) => {
+                if generation != self.grid_generation {
+                    return Task::none();
+                }
+                self.grid_scan = None;
+                self.image_grid_loading = false;
+                let saved_wallpapers = self.saved_wallpapers.clone();
+                Task::future(async move { collect_cache_garbage_if_due(&saved_wallpapers) })
+                    .discard()
+            }
+            Messages::FolderChanged(event) => self.handle_folder_event(event),
+            Messages::FolderImagesCached(path, images) => {
+                // Results for a folder that was switched away from meanwhile.
+                if !self
+                    .wallpaper_sources_for(self.wallpaper_folder.as_deref())
+                    .iter()
+                    .any(|s| path.starts_with(&s.path))
+                {
+                    return Task::none();
+                }
+                self.remove_images_under(&path);
+                self.add_grid_images(images);
+                Task::none()
+            }
+            Messages::ChangeWallpaper(p) => self.change_wallpaper(p),
+            Messages::ChangeWallpaperFolder => Self::open_wallpaper_folder_file_dialog(),
+            Messages::WallpaperFolderChanged(f) => {
+                self.wallpaper_folder = Some(f);
+                self.image_grid_images = vec![];
+                self.filtered_images = vec![];
+                Task::done(Messages::PopulateImageGrid)
+            }
+            Messages::MonitorDropdownPopulated(monitors) => {
+                self.available_monitors = monitors;
+                self.monitor = self.available_monitors.first().cloned();
+                Task::none()
+            }
+            Messages::MonitorChanged(m) => {
+                self.save_monitor_profile();
+                self.monitor = Some(m.clone());
+                self.selected_monitor_item = m;
+                if self.show_history {
+                    self.load_history();
+                }
+                if self.apply_monitor_profile() {
+                    self.image_grid_images = vec![];
+                    self.filtered_images = vec![];
+                    self.row_offset = 0;
+                    Task::done(Messages::PopulateImageGrid)
+                } else {
+                    Task::none()
+                }
+            }
             Messages::SortByChanged(sort_by) => {
+                // Picking the random order again shuffles anew.
+                if sort_by == SortBy::Random {
+                    self.random_sort_seed = rand::random();
+                }
+                self.image_usage = load_usage(&sort_by);
                 self.sort_image_grid(&sort_by);
+                self.sort_by = Some(sort_by);
                 Task::none()
             }
             Messages::SearchBarInputted(s) => {
                 self.image_filter.clone_from(&s);
                 self.filter_images(s)
             }
+            Messages::SearchTermToggled(term) => {
+                let exclusive = SEARCH_TOGGLES
+                    .iter()
+                    .find(|(t, _, _)| *t == term)
+                    .map_or(&[][..], |(_, _, e)| e);
+                self.image_filter = toggle_term(&self.image_filter, term, exclusive);
+                self.filter_images(self.image_filter.clone())
+            }
+            Messages::ShowColorFilterPicker => {
+                self.show_color_filter_picker = true;
+                Task::none()
+            }
+            Messages::ColorFilterCancelled => {
+                self.show_color_filter_picker = false;
+                Task::none()
+            }
+            Messages::ColorFilterSubmitted(color) => {
+                self.show_color_filter_picker = false;
+                let [r, g, b, _] = color.into_rgba8();
+                self.image_filter = set_color_term(&self.image_filter, Some([r, g, b]));
+                self.filter_images(self.image_filter.clone())
+            }
+            Messages::ColorFilterCleared => {
+                self.image_filter = set_color_term(&self.image_filter, None);
+                self.filter_images(self.image_filter.clone())
+            }
             Messages::ImagesFiltered(app_state_images) => {
+                // Batches of a running scan that arrived while filtering are not
+                // part of the result yet.
+                let known = app_state_images
+                    .supported_images
+                    .iter()
+                    .chain(&app_state_images.unsupported_images)
+                    .map(|i| i.path.clone())
+                    .collect::<HashSet<_>>();
+                let arrived = self
+                    .image_grid_images
+                    .drain(..)
+                    .chain(self.filtered_images.drain(..))
+                    .filter(|i| !known.contains(&i.path))
+                    .collect::<Vec<_>>();
                 self.image_grid_images = app_state_images.supported_images;
                 self.filtered_images = app_state_images.unsupported_images;
-                if let Some(s) = &self.sort_by.clone() {
-                    self.sort_image_grid(s);
-                }
-                self.image_grid_loading = false;
+                self.add_grid_images(arrived);
                 Task::none()
             }
             Messages::WallpaperChangerChanged(wallpaper_changer) => {
@@ -928,57 +2105,24 @@ impl AppState {
             | Messages::ExternalScriptExecuted
             | Messages::AwwwAdvancedSettingsButtonClicked
             | Messages::PopulateMonitorDropdown => Task::none(),
-            Messages::WallpaperChangeFinished(_, Err(error)) => {
+            Messages::WallpaperChangeFinished(change, _, Err(error)) => {
+                if let Some(request) = &change.request {
+                    request.respond(Err(anyhow!(error.clone())));
+                }
                 self.gslapper_error = Some(error);
                 Task::none()
             }
-            Messages::WallpaperChangeFinished(wallpaper_path, Ok(())) => {
+            Messages::WallpaperChangeFinished(change, wallpaper_path, Ok(())) => {
                 self.gslapper_error = None;
-                if let Some(changer) = &self.changer
-                    && let Some(monitor) = &self.monitor
-                {
-                    if monitor == &TRANSLATION.get_translation("All") {
-                        self.saved_wallpapers = self
-                            .saved_wallpapers
-                            .iter()
-                            .filter(|i| i.monitor == TRANSLATION.get_translation("All"))
-                            .cloned()
-                            .collect::<Vec<_>>();
-                    } else {
-                        self.saved_wallpapers = self
-                            .saved_wallpapers
-                            .iter()
-                            .filter(|i| i.monitor != TRANSLATION.get_translation("All"))
-                            .cloned()
-                            .collect::<Vec<_>>();
-                    }
-                    match self
-                        .saved_wallpapers
-                        .iter_mut()
-                        .find(|w| w.monitor == *monitor)
-                    {
-                        Some(w) => {
-                            w.changer = changer.clone();
-                            w.path = wallpaper_path
-                                .clone()
-                                .to_str()
-                                .unwrap_or_default()
-                                .to_string();
-                        }
-                        None => self.saved_wallpapers.push(Wallpaper {
-                            monitor: monitor.clone(),
-                            path: wallpaper_path
-                                .clone()
-                                .to_str()
-                                .unwrap_or_default()
-                                .to_string(),
-                            changer: changer.clone(),
-                        }),
-                    }
+                self.save_applied_wallpaper(&change.monitor, &wallpaper_path, &change.changer);
+                self.record_history(&change.monitor, &wallpaper_path, &change.changer);
+                if let Some(request) = &change.request {
+                    request.respond(Ok("OK".to_owned()));
                 }
-                self.execute_external_script(&wallpaper_path)
+                self.execute_external_script(Some(&change.monitor), &wallpaper_path)
             }
             Messages::CloseRequested => {
+                self.save_monitor_profile();
                 if let Err(e) = self.write_to_config_file() {
                     error!("Failed to write to config file: {e}");
                 }
@@ -1390,7 +2534,77 @@ impl AppState {
                 Task::none()
             }
             Messages::GSlapperControlRequested(control) => self.control_gslapper(control),
-            Messages::GSlapperDialogPressed => Task::none(),
+            Messages::GSlapperDialogPressed
+            | Messages::HistoryDialogPressed
+            | Messages::TagEditorPressed
+            | Messages::DuplicatesDialogPressed => Task::none(),
+            Messages::OpenDuplicates => {
+                self.show_duplicates = true;
+                self.duplicates_loading = true;
+                self.duplicates.clear();
+                self.gslapper_error = None;
+                self.find_grid_duplicates()
+            }
+            Messages::CloseDuplicates => {
+                self.show_duplicates = false;
+                Task::none()
+            }
+            Messages::DuplicatesFound(groups) => {
+                self.duplicates = groups;
+                self.duplicates_loading = false;
+                Task::none()
+            }
+            Messages::TrashDuplicates(index) => {
+                self.trash_duplicate_group(index);
+                Task::none()
+            }
+            Messages::TrashAllDuplicates => {
+                while !self.duplicates.is_empty() {
+                    self.trash_duplicate_group(0);
+                }
+                Task::none()
+            }
+            Messages::OpenHistory => {
+                self.show_history = true;
+                self.load_history();
+                Task::none()
+            }
+            Messages::CloseHistory => {
+                self.show_history = false;
+                Task::none()
+            }
+            Messages::HistoryUndo => self.step_history(true),
+            Messages::HistoryRedo => self.step_history(false),
+            Messages::HistoryStepFinished(entry, undo, result) => {
+                self.history_step_finished(&entry, undo, result)
+            }
+            Messages::OpenTagEditor(path) => {
+                self.tag_editor = Some(path);
+                self.new_tag.clear();
+                Task::none()
+            }
+            Messages::CloseTagEditor => {
+                self.tag_editor = None;
+                Task::none()
+            }
+            Messages::NewTagInputted(tag) => {
+                self.new_tag = tag;
+                Task::none()
+            }
+            Messages::AddTag => {
+                let tag = std::mem::take(&mut self.new_tag);
+                let has_tag = self
+                    .tag_editor
+                    .as_ref()
+                    .and_then(|p| self.image_tags.get(p))
+                    .is_some_and(|tags| tags.contains(&normalize_tag(&tag)));
+                if has_tag {
+                    Task::none()
+                } else {
+                    self.toggle_image_tag(&tag)
+                }
+            }
+            Messages::ImageTagToggled(tag) => self.toggle_image_tag(&tag),
             Messages::GSlapperSettingsApplied(settings, Ok(runtime)) => {
                 self.gslapper_scale_mode = Some(settings.scale_mode.clone());
                 self.gslapper_pause_mode = Some(settings.pause_mode.clone());
@@ -1526,17 +2740,267 @@ impl AppState {
                     self.row_offset = ((self.image_grid_images.len()
                         - (image_grid_rows * image_grid_columns))
                         / image_grid_rows)
-                        .floor() as usize;
+                        .floor();
                 }
                 Task::none()
             }
+            Messages::IpcCommandReceived(request) => self.handle_ipc_request(&request),
+            Messages::MonitorsChanged(event) => self.handle_monitor_event(event),
+            Messages::CollectionFilterChanged(collection) => {
+                self.collection = collection;
+                self.load_collections();
+                self.row_offset = 0;
+                self.filter_images(self.image_filter.clone())
+            }
+            Messages::EditingCollectionChanged(collection) => {
+                self.editing_collection = collection;
+                Task::none()
+            }
+            Messages::NewCollectionNameInputted(name) => {
+                self.new_collection_name = name;
+                Task::none()
+            }
+            Messages::CreateCollection => self.create_collection(),
+            Messages::DeleteCollection => self.delete_collection(),
+            Messages::WallpaperCollectionToggle(path) => self.toggle_collection_image(&path),
+            Messages::SpanMonitorsToggled(t) => {
+                self.span_monitors = t;
+                Task::none()
+            }
+            Messages::WallpaperSpanFinished(_, Err(error)) => {
+                self.gslapper_error = Some(error);
+                Task::none()
+            }
+            Messages::WallpaperSpanFinished(wallpaper_path, Ok(spanned)) => {
+                self.gslapper_error = None;
+                save_spanned_wallpapers(&mut self.saved_wallpapers, spanned);
+                self.execute_external_script(self.monitor.as_deref(), &wallpaper_path)
+            }
+            Messages::MatchMonitorOrientationToggled(t) => {
+                self.match_monitor_orientation = t;
+                Task::none()
+            }
+            Messages::DistinctRandomMonitorsToggled(t) => {
+                self.distinct_random_monitors = t;
+                Task::none()
+            }
+            Messages::WeightedRandomToggled(t) => {
+                self.weighted_random = t;
+                Task::none()
+            }
+            Messages::WallpaperWeightChanged(path, weight) => {
+                self.set_image_weight(&path, weight);
+                Task::none()
+            }
         }
     }
 
+    fn generate_tag_editor_dialog(&self) -> Element<'_, Messages> {
+        let path = self.tag_editor.clone().unwrap_or_default();
+        let tags = self.image_tags.get(&path).cloned().unwrap_or_default();
+        let tag_button = |tag: &String, style: fn(&Theme, button::Status) -> button::Style| {
+            button(text(tag.clone()))
+                .style(style)
+                .on_press(Messages::ImageTagToggled(tag.clone()))
+        };
+        let image_tags = if tags.is_empty() {
+            row![text!["{}", TRANSLATION.get_translation("no-tags")]]
+        } else {
+            tags.iter().fold(row![].spacing(6), |row, tag| {
+                row.push(tag_button(tag, button::primary))
+            })
+        };
+        let other_tags = self
+            .known_tags()
+            .into_iter()
+            .filter(|t| !tags.contains(t))
+            .fold(row![].spacing(6), |row, tag| {
+                row.push(tag_button(tag, button::secondary))
+            });
+
+        container(
+            column![
+                text!["{}", TRANSLATION.get_translation("tags")].size(20),
+                text(
+                    path.file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .into_owned()
+                ),
+                image_tags.wrap().vertical_spacing(6),
+                row![
+                    text_input(&TRANSLATION.get_translation("new-tag"), &self.new_tag)
+                        .on_input(Messages::NewTagInputted)
+                        .on_submit(Messages::AddTag),
+                    button(text!["{}", TRANSLATION.get_translation("add-tag")])
+                        .on_press(Messages::AddTag),
+                ]
+                .spacing(10),
+                text!["{}", TRANSLATION.get_translation("other-tags")],
+                scrollable(other_tags.wrap().vertical_spacing(6)).height(Fill),
+                button(text!["{}", TRANSLATION.get_translation("history-close")])
+                    .on_press(Messages::CloseTagEditor),
+            ]
+            .spacing(12),
+        )
+        .padding(20)
+        .width(620)
+        .height(480)
+        .style(container::bordered_box)
+        .into()
+    }
+
+    fn generate_duplicates_dialog(&self) -> Element<'_, Messages> {
+        let mut groups = column![].spacing(16);
+        if self.duplicates_loading {
+            groups = groups.push(text![
+                "{}",
+                TRANSLATION.get_translation("duplicates-searching")
+            ]);
+        } else if self.duplicates.is_empty() {
+            groups = groups.push(text!["{}", TRANSLATION.get_translation("no-duplicates")]);
+        }
+        for (index, group) in self.duplicates.iter().enumerate() {
+            let images = group
+                .iter()
+                .enumerate()
+                .fold(row![].spacing(10), |row, (i, entry)| {
+                    let media = entry.media.unwrap_or_default();
+                    let (label, style): (_, fn(&Theme) -> text::Style) = if i == 0 {
+                        ("duplicate-keep", text::success)
+                    } else {
+                        ("duplicate-trash", text::secondary)
+                    };
+                    row.push(
+                        column![
+                            image(&entry.cached_image_path)
+                                .content_fit(iced::ContentFit::Cover)
+                                .width(160)
+                                .height(90),
+                            text!["{}", TRANSLATION.get_translation(label)].style(style),
+                            text!["{}x{}", media.width, media.height],
+                            text(entry.name.clone()).size(12),
+                        ]
+                        .spacing(4)
+                        .width(160),
+                    )
+                });
+            groups = groups.push(
+                column![
+                    images.wrap().vertical_spacing(10),
+                    button(text!["{}", TRANSLATION.get_translation("trash-others")])
+                        .on_press(Messages::TrashDuplicates(index)),
+                ]
+                .spacing(8),
+            );
+        }
+
+        let mut content = column![
+            text!["{}", TRANSLATION.get_translation("duplicates")].size(20),
+            scrollable(groups).height(Fill),
+        ]
+        .spacing(12);
+        if let Some(error) = &self.gslapper_error {
+            content = content.push(text(error).style(text::danger));
+        }
+        content = content.push(
+            row![
+                button(text![
+                    "{}",
+                    TRANSLATION.get_translation("trash-all-duplicates")
+                ])
+                .on_press_maybe(
+                    (!self.duplicates.is_empty()).then_some(Messages::TrashAllDuplicates)
+                ),
+                button(text!["{}", TRANSLATION.get_translation("history-close")])
+                    .on_press(Messages::CloseDuplicates),
+            ]
+            .spacing(10),
+        );
+
+        container(content)
+            .padding(20)
+            .width(820)
+            .height(720)
+            .style(container::bordered_box)
+            .into()
+    }
+
+    fn generate_history_dialog(&self) -> Element<'_, Messages> {
+        let mut entries = column![].spacing(6);
+        if self.history.is_empty() {
+            entries = entries.push(text!["{}", TRANSLATION.get_translation("history-empty")]);
+        }
+        for entry in &self.history {
+            let applied_at = chrono::DateTime::from_timestamp(entry.applied_at, 0)
+                .map(|t| {
+                    t.with_timezone(&chrono::Local)
+                        .format("%Y-%m-%d %H:%M")
+                        .to_string()
+                })
+                .unwrap_or_default();
+            let name = entry
+                .path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned();
+            let mut label = text!["{applied_at}  {name}"].width(Fill);
+            if entry.undone {
+                label = label.style(text::secondary);
+            }
+            entries = entries.push(
+                row![
+                    label,
+                    button(text!["{}", TRANSLATION.get_translation("history-apply")])
+                        .on_press(Messages::ChangeWallpaper(entry.path.clone())),
+                ]
+                .spacing(10)
+                .align_y(Center),
+            );
+        }
+
+        let mut content = column![
+            text!["{}", TRANSLATION.get_translation("history")].size(20),
+            text![
+                "{}: {}",
+                TRANSLATION.get_translation("gslapper-output"),
+                self.monitor.clone().unwrap_or_default()
+            ],
+            scrollable(entries).height(Fill),
+        ]
+        .spacing(12);
+        if let Some(error) = &self.gslapper_error {
+            content = content.push(text(error).style(text::danger));
+        }
+        let can_undo = self.history.iter().filter(|e| !e.undone).count() > 1;
+        let can_redo = self.history.iter().any(|e| e.undone);
+        content = content.push(
+            row![
+                button(text!["{}", TRANSLATION.get_translation("history-undo")])
+                    .on_press_maybe(can_undo.then_some(Messages::HistoryUndo)),
+                button(text!["{}", TRANSLATION.get_translation("history-redo")])
+                    .on_press_maybe(can_redo.then_some(Messages::HistoryRedo)),
+                button(text!["{}", TRANSLATION.get_translation("history-close")])
+                    .on_press(Messages::CloseHistory),
+            ]
+            .spacing(10),
+        );
+
+        container(content)
+            .padding(20)
+            .width(620)
+            .height(720)
+            .style(container::bordered_box)
+            .into()
+    }
+
     pub fn view(&self) -> Element<'_, Messages> {
         let content = match &self.changer {
             Some(changer) => {
-                let image_grid: Element<'_, Messages> = if self.image_grid_loading {
+                let image_grid: Element<'_, Messages> = if self.image_grid_loading
+                    && self.image_grid_images.is_empty()
+                {
                     row![
                         text![
                             "{}",
@@ -1560,10 +3024,10 @@ impl AppState {
                 } else {
                     let responsive_grid = responsive(|size| {
                         let number_of_columns = (size.width
-                            / ((BUTTON_WIDTH + DEFAULT_MARGIN * 2.0) as f32))
+                            / (BUTTON_WIDTH + DEFAULT_MARGIN * 2.0))
                             .floor() as usize;
                         let number_of_rows = (size.height
-                            / ((BUTTON_HEIGHT + DEFAULT_MARGIN * 2.0) as f32))
+                            / (BUTTON_HEIGHT + DEFAULT_MARGIN * 2.0))
                             .floor() as usize;
                         IMAGE_GRID_COLUMNS
                             .store(number_of_columns, std::sync::atomic::Ordering::Relaxed);
@@ -1594,24 +3058,33 @@ impl AppState {
                             let image_button =
                                 lazy(cached_image_file, move |i| -> Element<'_, Messages> {
                                     let path = i.path.clone();
-                                    create_tooltip(
+                                    let thumbnail = image(&i.cached_image_path)
+                                        .content_fit(iced::ContentFit::Cover)
+                                        .width(Fill)
+                                        .height(Fill);
+                                    let thumbnail: Element<'_, Messages> = if i.has_thumbnail() {
+                                        thumbnail.into()
+                                    } else {
+                                        stack![
+                                            thumbnail,
+                                            container(text![
+                                                "{}",
+                                                TRANSLATION.get_translation("no-thumbnail")
+                                            ])
+                                            .center(Fill)
+                                        ]
+                                        .into()
+                                    };
+                                    let tile = create_tooltip(
                                         container(
                                             mouse_area(
-                                                container(
-                                                    image(&i.cached_image_path)
-                                                        .content_fit(iced::ContentFit::Cover)
-                                                        .width(Fill)
-                                                        .height(Fill),
-                                                )
-                                                .width(max_button_width)
-                                                .height(max_button_height)
-                                                .clip(true),
+                                                container(thumbnail)
+                                                    .width(max_button_width)
+                                                    .height(max_button_height)
+                                                    .clip(true),
                                             )
                                             .on_press(Messages::ChangeWallpaper(path.clone()))
-                                            .on_middle_press(Messages::WallpaperFavoriteToggle(
-                                                path.clone(),
-                                            ))
-                                            .on_right_press(Messages::WallpaperFavoriteToggle(
+                                            .on_middle_press(Messages::WallpaperCollectionToggle(
                                                 path.clone(),
                                             )),
                                         )
@@ -1620,7 +3093,11 @@ impl AppState {
                                         .height(Fill)
                                         .into(),
                                         text!["{}", i.path.to_string_lossy().to_string()].into(),
-                                    )
+                                    );
+                                    let (favorite, weight) = (i.favorite, i.weight);
+                                    ContextMenu::new(tile, move || {
+                                        image_context_menu(&path, favorite, weight)
+                                    })
                                     .into()
                                 });
                             // Check if not first element and not at the end of a column
@@ -1636,15 +3113,42 @@ impl AppState {
                         image_grid = image_grid.push(image_row);
                         image_grid.align_x(Left).into()
                     });
-                    responsive_grid.into()
+                    if self.image_grid_loading {
+                        let (processed, found) = self.grid_progress;
+                        column![
+                            text![
+                                "{} {processed}/{found}",
+                                TRANSLATION.get_translation("loading-images")
+                            ],
+                            responsive_grid
+                        ]
+                        .spacing(DEFAULT_MARGIN)
+                        .into()
+                    } else {
+                        responsive_grid.into()
+                    }
                 };
 
+                let monitor_options = self
+                    .available_monitors
+                    .iter()
+                    .map(|name| MonitorOption {
+                        name: name.clone(),
+                        label: self
+                            .monitor_details
+                            .iter()
+                            .find(|m| m.name == *name)
+                            .map_or_else(|| name.clone(), Monitor::label),
+                    })
+                    .collect::<Vec<_>>();
+                let selected_monitor = self
+                    .monitor
+                    .as_ref()
+                    .and_then(|m| monitor_options.iter().find(|o| o.name == *m).cloned());
                 let monitors_dropdown = create_tooltip(
-                    pick_list(
-                        self.available_monitors.as_slice(),
-                        self.monitor.clone(),
-                        Messages::MonitorChanged,
-                    )
+                    pick_list(monitor_options, selected_monitor, |o| {
+                        Messages::MonitorChanged(o.name)
+                    })
                     .into(),
                     text![
                         "{}",
@@ -1663,6 +3167,31 @@ impl AppState {
                     text!["{}", TRANSLATION.get_translation("sort-dropdown-tooltip")].into(),
                 );
 
+                let collection_filter_options =
+                    self.collection_options(&TRANSLATION.get_translation("all-images"));
+                let selected_collection_filter = collection_filter_options
+                    .iter()
+                    .find(|o| o.name == self.collection)
+                    .cloned();
+                let collection_dropdown = create_tooltip(
+                    pick_list(collection_filter_options, selected_collection_filter, |o| {
+                        Messages::CollectionFilterChanged(o.name)
+                    })
+                    .into(),
+                    text![
+                        "{}",
+                        TRANSLATION.get_translation("collection-dropdown-tooltip")
+                    ]
+                    .into(),
+                );
+
+                let editing_collection_options =
+                    self.collection_options(&TRANSLATION.get_translation("none"));
+                let selected_editing_collection = editing_collection_options
+                    .iter()
+                    .find(|o| o.name == self.editing_collection)
+                    .cloned();
+
                 let search_bar = create_tooltip(
                     text_input(
                         &TRANSLATION.get_translation("find-images"),
@@ -1674,6 +3203,51 @@ impl AppState {
                     text!["{}", TRANSLATION.get_translation("search-bar-tooltip")].into(),
                 );
 
+                let search_toggles =
+                    SEARCH_TOGGLES
+                        .iter()
+                        .fold(row![].spacing(4), |toggles, (term, label, _)| {
+                            toggles.push(
+                                button(text!["{}", TRANSLATION.get_translation(label)])
+                                    .style(if has_term(&self.image_filter, term) {
+                                        button::primary
+                                    } else {
+                                        button::secondary
+                                    })
+                                    .on_press(Messages::SearchTermToggled(term)),
+                            )
+                        });
+
+                let filter_color = Query::parse(&self.image_filter).color();
+                let mut color_filter = row![create_tooltip(
+                    color_picker(
+                        self.show_color_filter_picker,
+                        filter_color.map_or(Color::BLACK, |[r, g, b]| Color::from_rgb8(r, g, b)),
+                        button(text!["{}", TRANSLATION.get_translation("color-filter")])
+                            .style(if filter_color.is_some() {
+                                button::primary
+                            } else {
+                                button::secondary
+                            })
+                            .on_press(Messages::ShowColorFilterPicker),
+                        Messages::ColorFilterCancelled,
+                        Messages::ColorFilterSubmitted,
+                    )
+                    .into(),
+                    text!["{}", TRANSLATION.get_translation("color-filter-tooltip")].into(),
+                )]
+                .spacing(4);
+                if filter_color.is_some() {
+                    color_filter = color_filter.push(
+                        button(text![
+                            "{}",
+                            TRANSLATION.get_translation("clear-color-filter")
+                        ])
+                        .style(button::secondary)
+                        .on_press(Messages::ColorFilterCleared),
+                    );
+                }
+
                 let options_menu: Element<'_, Messages> = MenuBar::new(vec![Item::with_menu(
                     button(text!["{}", TRANSLATION.get_translation("Options")])
                         .on_press(Messages::OptionMenuOpened),
@@ -1745,6 +3319,145 @@ impl AppState {
                                 .width(Fill)
                                 .align_y(Center),
                             ),
+                            Item::new(
+                                row![
+                                    create_tooltip(
+                                        text![
+                                            "{}",
+                                            TRANSLATION
+                                                .get_translation("match-monitor-orientation")
+                                        ]
+                                        .into(),
+                                        text![
+                                            "{}",
+                                            TRANSLATION.get_translation(
+                                                "match-monitor-orientation-tooltip"
+                                            )
+                                        ]
+                                        .into(),
+                                    ),
+                                    toggler(self.match_monitor_orientation)
+                                        .on_toggle(Messages::MatchMonitorOrientationToggled)
+                                ]
+                                .spacing(DEFAULT_MARGIN)
+                                .width(Fill)
+                                .align_y(Center),
+                            ),
+                            Item::new(
+                                row![
+                                    create_tooltip(
+                                        text![
+                                            "{}",
+                                            TRANSLATION.get_translation("distinct-random-monitors")
+                                        ]
+                                        .into(),
+                                        text![
+                                            "{}",
+                                            TRANSLATION.get_translation(
+                                                "distinct-random-monitors-tooltip"
+                                            )
+                                        ]
+                                        .into(),
+                                    ),
+                                    toggler(self.distinct_random_monitors)
+                                        .on_toggle(Messages::DistinctRandomMonitorsToggled)
+                                ]
+                                .spacing(DEFAULT_MARGIN)
+                                .width(Fill)
+                                .align_y(Center),
+                            ),
+                            Item::new(
+                                row![
+                                    create_tooltip(
+                                        text!["{}", TRANSLATION.get_translation("weighted-random")]
+                                            .into(),
+                                        text![
+                                            "{}",
+                                            TRANSLATION.get_translation("weighted-random-tooltip")
+                                        ]
+                                        .into(),
+                                    ),
+                                    toggler(self.weighted_random)
+                                        .on_toggle(Messages::WeightedRandomToggled)
+                                ]
+                                .spacing(DEFAULT_MARGIN)
+                                .width(Fill)
+                                .align_y(Center),
+                            ),
+                            Item::new(
+                                row![
+                                    create_tooltip(
+                                        text!["{}", TRANSLATION.get_translation("span-monitors")]
+                                            .into(),
+                                        text![
+                                            "{}",
+                                            TRANSLATION.get_translation("span-monitors-tooltip")
+                                        ]
+                                        .into(),
+                                    ),
+                                    toggler(self.span_monitors)
+                                        .on_toggle(Messages::SpanMonitorsToggled)
+                                ]
+                                .spacing(DEFAULT_MARGIN)
+                                .width(Fill)
+                                .align_y(Center),
+                            ),
+                            Item::new(
+                                row![
+                                    create_tooltip(
+                                        text!["{}", TRANSLATION.get_translation("edit-collection")]
+                                            .into(),
+                                        text![
+                                            "{}",
+                                            TRANSLATION.get_translation("edit-collection-tooltip")
+                                        ]
+                                        .into(),
+                                    ),
+                                    pick_list(
+                                        editing_collection_options,
+                                        selected_editing_collection,
+                                        |o| Messages::EditingCollectionChanged(o.name),
+                                    ),
+                                    button(text![
+                                        "{}",
+                                        TRANSLATION.get_translation("delete-collection")
+                                    ])
+                                    .on_press_maybe(
+                                        self.editing_collection
+                                            .as_ref()
+                                            .map(|_| Messages::DeleteCollection)
+                                    ),
+                                ]
+                                .spacing(DEFAULT_MARGIN)
+                                .width(Fill)
+                                .align_y(Center),
+                            ),
+                            Item::new(
+                                row![
+                                    text_input(
+                                        &TRANSLATION.get_translation("new-collection"),
+                                        &self.new_collection_name,
+                                    )
+                                    .on_input(Messages::NewCollectionNameInputted)
+                                    .on_submit(Messages::CreateCollection),
+                                    button(text![
+                                        "{}",
+                                        TRANSLATION.get_translation("create-collection")
+                                    ])
+                                    .on_press(Messages::CreateCollection),
+                                ]
+                                .spacing(DEFAULT_MARGIN)
+                                .width(Fill)
+                                .align_y(Center),
+                            ),
+                            Item::new(create_tooltip(
+                                button(text!["{}", TRANSLATION.get_translation("find-duplicates")])
+                                    .on_press(Messages::OpenDuplicates)
+                                    .width(Fill)
+                                    .into(),
+                                text!["{}", TRANSLATION.get_translation("find-duplicates-tooltip")]
+                                    .into(),
+                            )),
                         ]
                         .into(),
                     )
@@ -1774,7 +3487,15 @@ impl AppState {
                         text!["{}", TRANSLATION.get_translation("image-folder-tooltip")].into(),
                     ),
                     sort_dropdown,
+                    collection_dropdown,
                     search_bar,
+                    search_toggles,
+                    color_filter,
+                    create_tooltip(
+                        button(text!["{}", TRANSLATION.get_translation("history")])
+                            .on_press(Messages::OpenHistory).into(),
+                        text!["{}", TRANSLATION.get_translation("history-tooltip")].into(),
+                    ),
                     options_menu,
                     changer_dropdown,
                 ]
@@ -1814,7 +3535,35 @@ impl AppState {
             .into(),
         };
 
-        if self.show_gslapper_settings {
+        let dialog = if self.show_gslapper_settings {
+            Some((
+                generate_gslapper_settings_dialog(self),
+                Messages::CloseGSlapperSettings,
+                Messages::GSlapperDialogPressed,
+            ))
+        } else if self.show_history {
+            Some((
+                self.generate_history_dialog(),
+                Messages::CloseHistory,
+                Messages::HistoryDialogPressed,
+            ))
+        } else if self.tag_editor.is_some() {
+            Some((
+                self.generate_tag_editor_dialog(),
+                Messages::CloseTagEditor,
+                Messages::TagEditorPressed,
+            ))
+        } else if self.show_duplicates {
+            Some((
+                self.generate_duplicates_dialog(),
+                Messages::CloseDuplicates,
+                Messages::DuplicatesDialogPressed,
+            ))
+        } else {
+            None
+        };
+
+        if let Some((dialog, close, pressed)) = dialog {
             stack![
                 content,
                 mouse_area(
@@ -1826,15 +3575,12 @@ impl AppState {
                         .width(Fill)
                         .height(Fill),
                 )
-                .on_press(Messages::CloseGSlapperSettings),
-                container(
-                    mouse_area(generate_gslapper_settings_dialog(self))
-                        .on_press(Messages::GSlapperDialogPressed),
-                )
-                .align_x(Center)
-                .align_y(Center)
-                .width(Fill)
-                .height(Fill),
+                .on_press(close),
+                container(mouse_area(dialog).on_press(pressed))
+                    .align_x(Center)
+                    .align_y(Center)
+                    .width(Fill)
+                    .height(Fill),
             ]
             .width(Fill)
             .height(Fill)
@@ -1845,9 +3591,14 @@ impl AppState {
     }
 
     fn subscription(&self) -> Subscription<Messages> {
-        Subscription::filter_map(
-            event::listen().with(self.show_gslapper_settings),
-            |(settings_open, event)| match event {
+        let events = Subscription::filter_map(
+            event::listen().with((
+                self.show_gslapper_settings,
+                self.show_history,
+                self.tag_editor.is_some(),
+                self.show_duplicates,
+            )),
+            |((settings_open, history_open, tag_editor_open, duplicates_open), event)| match event {
                 iced::Event::Window(iced::window::Event::CloseRequested) => {
                     Some(Messages::CloseRequested)
                 }
@@ -1866,6 +3617,29 @@ impl AppState {
                     key: iced::keyboard::Key::Named(iced::keyboard::key::Named::Escape),
                     ..
                 }) if settings_open => Some(Messages::CloseGSlapperSettings),
+                iced::Event::Keyboard(iced::keyboard::Event::KeyPressed {
+                    key: iced::keyboard::Key::Named(iced::keyboard::key::Named::Escape),
+                    ..
+                }) if history_open => Some(Messages::CloseHistory),
+                iced::Event::Keyboard(iced::keyboard::Event::KeyPressed {
+                    key: iced::keyboard::Key::Named(iced::keyboard::key::Named::Escape),
+                    ..
+                }) if tag_editor_open => Some(Messages::CloseTagEditor),
+                iced::Event::Keyboard(iced::keyboard::Event::KeyPressed {
+                    key: iced::keyboard::Key::Named(iced::keyboard::key::Named::Escape),
+                    ..
+                }) if duplicates_open => Some(Messages::CloseDuplicates),
+                iced::Event::Keyboard(iced::keyboard::Event::KeyPressed {
+                    key: iced::keyboard::Key::Character(c),
+                    modifiers,
+                    ..
+                }) if modifiers.command() && c.eq_ignore_ascii_case("z") => {
+                    if modifiers.shift() {
+                        Some(Messages::HistoryRedo)
+                    } else {
+                        Some(Messages::HistoryUndo)
+                    }
+                }
                 iced::Event::Mouse(iced::mouse::Event::WheelScrolled { delta }) => match delta {
                     iced::mouse::ScrollDelta::Lines { x: _, y } => {
                         if y < 0.0 {
@@ -1884,7 +3658,14 @@ impl AppState {
                 },
                 _ => None,
             },
-        )
+        );
+        let mut subscriptions = vec![events, ipc_subscription(), monitor_subscription()];
+        subscriptions.extend(
+            self.wallpaper_sources_for(self.wallpaper_folder.as_deref())
+                .into_iter()
+                .map(|s| folder_subscription(s.path)),
+        );
+        Subscription::batch(subscriptions)
     }
 
     fn theme(&self) -> iced::Theme {
@@ -1919,7 +3700,13 @@ mod tests {
         };
         let path = PathBuf::from("/tmp/missing.png");
 
+        let change = WallpaperChange {
+            monitor: "DP-1".to_owned(),
+            changer: WallpaperChangers::GSlapper(GSllaperSettings::default()),
+            request: None,
+        };
         let _ = app.update(Messages::WallpaperChangeFinished(
+            change,
             path,
             Err("gSlapper failed".to_owned()),
         ));
@@ -1989,4 +3776,163 @@ mod tests {
         assert_eq!(restored.gslapper_fps_cap, 30);
         assert_eq!(restored.gslapper_cache_size, 256);
     }
+
+    #[test]
+    fn switching_monitor_loads_its_profile_and_saves_the_previous_one() {
+        let swaybg = SwaybgSettings {
+            mode: SwaybgModes::Center,
+            fill_color: "#112233".to_owned(),
+        };
+        let mut app = AppState {
+            monitor: Some("DP-1".to_owned()),
+            wallpaper_folder: Some(PathBuf::from("/wallpapers/landscape")),
+            changer: Some(WallpaperChangers::Hyprpaper(HyprpaperSettings::default())),
+            monitor_profiles: vec![MonitorProfile {
+                monitor: "HDMI-A-1".to_owned(),
+                wallpaper_folder: Some(PathBuf::from("/wallpapers/portrait")),
+                changer: Some(WallpaperChangers::Swaybg(swaybg.clone())),
+                sort_by: Some(SortBy::Name),
+                invert_sort: true,
+            }],
+            ..AppState::default()
+        };
+
+        let _ = app.update(Messages::MonitorChanged("HDMI-A-1".to_owned()));
+
+        assert_eq!(
+            app.wallpaper_folder,
+            Some(PathBuf::from("/wallpapers/portrait"))
+        );
+        assert_eq!(app.changer, Some(WallpaperChangers::Swaybg(swaybg)));
+        assert_eq!(app.swaybg_mode, Some(SwaybgModes::Center));
+        assert_eq!(app.swaybg_color, "#112233");
+        assert!(app.invert_sort);
+        assert_eq!(
+            app.monitor_profile("DP-1").unwrap().wallpaper_folder,
+            Some(PathBuf::from("/wallpapers/landscape"))
+        );
+    }
+
+    #[test]
+    fn hotplugged_monitor_is_listed_and_keeps_its_wallpaper() {
+        let dock = Monitor {
+            name: "DP-5".to_owned(),
+            description: "Dell U2720Q".to_owned(),
+            ..Monitor::default()
+        };
+        let mut app = AppState {
+            saved_wallpapers: vec![Wallpaper {
+                monitor: "DP-3".to_owned(),
+                monitor_description: "Dell U2720Q".to_owned(),
+                path: "/wallpapers/a.png".to_owned(),
+                ..Wallpaper::default()
+            }],
+            ..AppState::default()
+        };
+
+        let _ = app.update(Messages::MonitorsChanged(MonitorEvent::Added(dock)));
+
+        assert_eq!(app.available_monitors[1..], ["DP-5".to_owned()]);
+        assert_eq!(app.saved_wallpapers[0].monitor, "DP-5");
+
+        let _ = app.update(Messages::MonitorsChanged(MonitorEvent::Removed(
+            "DP-5".to_owned(),
+        )));
+
+        assert_eq!(app.available_monitors.len(), 1);
+        assert_eq!(app.saved_wallpapers.len(), 1);
+    }
+
+    #[test]
+    fn folder_changes_update_only_the_affected_images() {
+        let image = |p: &str| CacheImageFile {
+            path: PathBuf::from(p),
+            ..CacheImageFile::default()
+        };
+        let paths = |app: &AppState| {
+            app.image_grid_images
+                .iter()
+                .map(|i| i.path.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        };
+        let mut app = AppState {
+            wallpaper_folder: Some(PathBuf::from("/w")),
+            image_grid_images: vec![
+                image("/w/a.png"),
+                image("/w/sub/b.png"),
+                image("/w/sub/c.png"),
+            ],
+            ..AppState::default()
+        };
+
+        let _ = app.update(Messages::FolderChanged(FolderEvent::Removed(
+            PathBuf::from("/w/sub"),
+        )));
+        assert_eq!(paths(&app), ["/w/a.png"]);
+
+        let _ = app.update(Messages::FolderImagesCached(
+            PathBuf::from("/w/a.png"),
+            vec![image("/w/a.png")],
+        ));
+        let _ = app.update(Messages::FolderImagesCached(
+            PathBuf::from("/w/new"),
+            vec![image("/w/new/d.png")],
+        ));
+        let _ = app.update(Messages::FolderImagesCached(
+            PathBuf::from("/elsewhere"),
+            vec![image("/elsewhere/e.png")],
+        ));
+        assert_eq!(paths(&app), ["/w/a.png", "/w/new/d.png"]);
+    }
+
+    #[test]
+    fn grid_batches_of_abandoned_scans_are_ignored() {
+        let image = |p: &str| CacheImageFile {
+            path: PathBuf::from(p),
+            ..CacheImageFile::default()
+        };
+        let mut app = AppState {
+            image_grid_loading: true,
+            grid_generation: 2,
+            ..AppState::default()
+        };
+
+        let _ = app.update(Messages::ImageGridScanned(2, 3));
+        let _ = app.update(Messages::ImageGridBatch(1, 1, vec![image("/old.png")]));
+        let _ = app.update(Messages::ImageGridBatch(2, 2, vec![image("/a.png")]));
+        assert_eq!(app.grid_progress, (2, 3));
+        assert_eq!(app.image_grid_images.len(), 1);
+
+        // A filter pass that started before the batch must not drop it.
+        let _ = app.update(Messages::ImagesFiltered(AppStateImages::default()));
+        assert_eq!(app.image_grid_images[0].path, PathBuf::from("/a.png"));
+
+        let _ = app.update(Messages::ImageGridPopulated(1));
+        assert!(app.image_grid_loading);
+        let _ = app.update(Messages::ImageGridPopulated(2));
+        assert!(!app.image_grid_loading);
+    }
+
+    #[test]
+    fn wallpaper_folder_comes_first_with_its_configured_rules() {
+        let rules = WallpaperSource {
+            max_depth: Some(1),
+            ..WallpaperSource::new(PathBuf::from("/w"))
+        };
+        let extra = WallpaperSource::new(PathBuf::from("/extra"));
+        let app = AppState {
+            wallpaper_folder: Some(PathBuf::from("/w")),
+            wallpaper_sources: vec![extra.clone(), rules.clone()],
+            ..AppState::default()
+        };
+
+        assert_eq!(
+            app.wallpaper_sources_for(app.wallpaper_folder.as_deref()),
+            [rules.clone(), extra.clone()]
+        );
+        assert_eq!(
+            app.wallpaper_sources_for(Some(Path::new("/other"))),
+            [WallpaperSource::new(PathBuf::from("/other")), extra, rules]
+        );
+    }
 }
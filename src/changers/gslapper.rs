@@ -1,6 +1,6 @@
 use crate::{
     app_state::{AppState, Messages},
-    common::create_tooltip,
+    common::{create_tooltip, get_runtime_dir},
     locale::TRANSLATION,
     wallpaper_changers::{
         GSllaperSettings, GSllapperPauseMode, GSllapperScaleMode, WallpaperChangers,
@@ -96,16 +96,8 @@ fn managed_socket_path_in(root: &Path, monitor: &str) -> PathBuf {
     ))
 }
 
-fn managed_runtime_dir() -> anyhow::Result<PathBuf> {
-    let root = std::env::var_os("XDG_RUNTIME_DIR")
-        .ok_or_else(|| anyhow::anyhow!("XDG_RUNTIME_DIR is not set"))?;
-    let dir = PathBuf::from(root).join("waytrogen");
-    fs::create_dir_all(&dir)?;
-    Ok(dir)
-}
-
 fn managed_socket_path(monitor: &str) -> anyhow::Result<PathBuf> {
-    Ok(managed_socket_path_in(&managed_runtime_dir()?, monitor))
+    Ok(managed_socket_path_in(&get_runtime_dir()?, monitor))
 }
 
 fn is_managed_socket(path: &Path) -> bool {
@@ -500,7 +492,7 @@ pub fn stop_gslapper(monitor: &str) -> anyhow::Result<()> {
 
 pub fn stop_all_managed_gslappers() -> anyhow::Result<()> {
     let _guard = lifecycle_guard()?;
-    let runtime_dir = managed_runtime_dir()?;
+    let runtime_dir = get_runtime_dir()?;
     let mut first_error = None;
     for entry in fs::read_dir(runtime_dir)? {
         let path = entry?.path();
@@ -1,16 +1,28 @@
 use crate::{
     app_state::AppState,
+    cli_parser::Cli,
     common::{
-        APP_VERSION, CACHE_FILE_NAME, CONFIG_APP_NAME, Wallpaper,
-        sort_by_sort_dropdown_string,
+        APP_VERSION, CONFIG_APP_NAME, CacheImageFile, Wallpaper, get_config_file_path,
+        get_thumbnail_dir,
     },
+    database::DatabaseConnection,
+    duplicates::{find_duplicates, move_to_trash},
+    fs::get_image_files,
+    library::Library,
+    monitors::{
+        AvailableMonitors, Monitor, Orientation, resolve_monitor_name, saved_wallpaper_for,
+    },
+    query::Query,
+    shuffle::{draw_image, images_to_avoid},
+    sort::{SortOrder, load_usage, sort_paths},
+    span::{change_saved_wallpaper, collect_span_garbage, save_spanned_wallpapers, span_wallpaper},
     wallpaper_changers::{WallpaperChanger, WallpaperChangers},
-    cli_parser::Cli
 };
 use anyhow::anyhow;
 use log::debug;
+use rayon::prelude::*;
 use std::{
-    fs::remove_dir_all,
+    fs::{remove_dir_all, remove_file},
     path::{Path, PathBuf},
     thread,
     time::Duration,
@@ -18,18 +30,31 @@ use std::{
 
 use log::{error, warn};
 
+fn connected_monitors() -> Vec<Monitor> {
+    match AvailableMonitors::get_monitors() {
+        Ok(m) => m.monitors,
+        Err(e) => {
+            warn!("Failed to query connected monitors: {e}");
+            Vec::new()
+        }
+    }
+}
+
 pub fn restore_wallpapers(app_state: &AppState) -> anyhow::Result<()> {
     WallpaperChangers::killall_changers();
+    let monitors = connected_monitors();
     let previous_wallpapers = app_state.saved_wallpapers.clone();
-    for wallpaper in previous_wallpapers {
+    for mut wallpaper in previous_wallpapers {
         if wallpaper == Wallpaper::default() {
             continue;
         }
+        wallpaper.monitor = resolve_monitor_name(
+            &wallpaper.monitor,
+            &wallpaper.monitor_description,
+            &monitors,
+        );
         debug!("Restoring: {wallpaper:?}");
-        wallpaper.clone().changer.change(
-            PathBuf::from(wallpaper.clone().path),
-            wallpaper.clone().monitor,
-        )?;
+        change_saved_wallpaper(&wallpaper, &wallpaper.monitor, &monitors)?;
         match wallpaper.clone().changer {
             WallpaperChangers::Hyprpaper(_) => {
                 thread::sleep(Duration::from_secs(1));
@@ -43,6 +68,25 @@ pub fn restore_wallpapers(app_state: &AppState) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Sets the saved wallpaper on a monitor that was plugged in after startup.
+/// Returns `false` when nothing was saved for it.
+pub fn reapply_saved_wallpaper(
+    monitor: &Monitor,
+    app_state: &mut AppState,
+) -> anyhow::Result<bool> {
+    let monitors = connected_monitors();
+    let Some((index, target)) =
+        saved_wallpaper_for(monitor, &app_state.saved_wallpapers, &monitors)
+    else {
+        return Ok(false);
+    };
+    app_state.saved_wallpapers[index]
+        .monitor
+        .clone_from(&target);
+    change_saved_wallpaper(&app_state.saved_wallpapers[index], &target, &monitors)?;
+    Ok(true)
+}
+
 pub fn print_wallpaper_state(app_state: &AppState) -> anyhow::Result<()> {
     println!(
         "{}",
@@ -51,70 +95,357 @@ pub fn print_wallpaper_state(app_state: &AppState) -> anyhow::Result<()> {
     Ok(())
 }
 
-fn get_previous_supported_wallpapers(app_state: &AppState) -> Vec<PathBuf> {
-    let previous_wallpapers = app_state.saved_wallpapers.clone();
-    let wallpaper = previous_wallpapers[0].clone();
-    let path = Path::new(&wallpaper.path)
-        .parent()
-        .unwrap_or_else(|| Path::new(""));
+pub fn get_collection_images(name: &str) -> Vec<PathBuf> {
+    let images = DatabaseConnection::new().and_then(|conn| {
+        if !conn.select_collections()?.iter().any(|c| c == name) {
+            return Err(anyhow!("Collection \"{name}\" does not exist"));
+        }
+        conn.select_collection_images(name)
+    });
+    match images {
+        Ok(images) => images.into_iter().filter(|p| p.is_file()).collect(),
+        Err(e) => {
+            error!("Failed to get collection images: {e}");
+            Vec::new()
+        }
+    }
+}
 
-    walkdir::WalkDir::new(path)
-        .follow_links(true)
-        .follow_root_links(true)
-        .into_iter()
-        .filter_map(std::result::Result::ok)
-        .filter(|f| f.file_type().is_file())
-        .map(|d| d.path().to_path_buf())
-        .filter(|p| {
+pub fn filter_by_tags(images: Vec<PathBuf>, tags: &[String]) -> Vec<PathBuf> {
+    let query = Query::from_tags(tags);
+    if query.is_empty() {
+        return images;
+    }
+    match DatabaseConnection::new().and_then(|conn| conn.select_image_tags()) {
+        Ok(image_tags) => images
+            .into_iter()
+            .filter(|p| {
+                // Tag terms only look at the tags, not at the cache entry.
+                let image = CacheImageFile {
+                    path: p.clone(),
+                    ..CacheImageFile::default()
+                };
+                query.matches(&image, image_tags.get(p).map_or(&[], Vec::as_slice))
+            })
+            .collect(),
+        Err(e) => {
+            error!("Failed to get image tags: {e}");
+            Vec::new()
+        }
+    }
+}
+
+fn get_previous_supported_wallpapers(app_state: &AppState, wallpaper: &Wallpaper) -> Vec<PathBuf> {
+    filter_by_tags(
+        get_source_wallpapers(app_state, wallpaper),
+        &app_state.source_tags,
+    )
+}
+
+/// The images `--random` and `--next` draw from for `wallpaper`'s monitor,
+/// before the `--tag` filters.
+fn get_source_wallpapers(app_state: &AppState, wallpaper: &Wallpaper) -> Vec<PathBuf> {
+    if let Some(collection) = &app_state.source_collection {
+        return get_collection_images(collection)
+            .into_iter()
+            .filter(|p| {
+                wallpaper.changer.accepted_formats().iter().any(|f| {
+                    f == p
+                        .extension()
+                        .unwrap_or_default()
+                        .to_str()
+                        .unwrap_or_default()
+                })
+            })
+            .collect();
+    }
+    let previous_wallpapers = app_state.saved_wallpapers.clone();
+    // A monitor profile pins the output to its own folder and changer. Without
+    // one, the folder of the first saved wallpaper is shared by every output.
+    let (path, changers) = match app_state
+        .monitor_profile(&wallpaper.monitor)
+        .and_then(|p| p.wallpaper_folder.clone())
+    {
+        Some(folder) => (folder, vec![wallpaper.changer.clone()]),
+        None => (
+            Path::new(&previous_wallpapers[0].path)
+                .parent()
+                .unwrap_or_else(|| Path::new(""))
+                .to_path_buf(),
             previous_wallpapers
                 .iter()
                 .map(|w| w.changer.clone())
-                .all(|c: WallpaperChangers| {
-                    c.accepted_formats().iter().any(|f| {
-                        f == p
-                            .extension()
-                            .unwrap_or_default()
-                            .to_str()
-                            .unwrap_or_default()
-                    })
-                })
+                .collect::<Vec<_>>(),
+        ),
+    };
+
+    let formats = WallpaperChangers::all_accepted_formats()
+        .into_iter()
+        .filter(|f| changers.iter().all(|c| c.accepted_formats().contains(f)))
+        .collect::<Vec<_>>();
+    get_image_files(&app_state.wallpaper_sources_for(Some(&path)), &formats)
+}
+
+fn get_sorted_supported_wallpapers(app_state: &AppState, wallpaper: &Wallpaper) -> Vec<PathBuf> {
+    let mut files = get_previous_supported_wallpapers(app_state, wallpaper);
+    let (sort_by, invert_sort) = match app_state.monitor_profile(&wallpaper.monitor) {
+        Some(p) => (
+            p.sort_by
+                .clone()
+                .or_else(|| app_state.sort_by.clone())
+                .unwrap_or_default(),
+            p.invert_sort,
+        ),
+        None => (
+            app_state.sort_by.clone().unwrap_or_default(),
+            app_state.invert_sort,
+        ),
+    };
+    let order = SortOrder {
+        sort_by: &sort_by,
+        invert: invert_sort,
+        seed: app_state.random_sort_seed,
+        usage: &load_usage(&sort_by),
+    };
+    sort_paths(&mut files, &order);
+    files
+}
+
+/// The wallpaper after the one saved for `monitor`, in the order of its own
+/// folder and sort settings.
+pub fn next_wallpaper(monitor: &str, app_state: &AppState) -> Option<PathBuf> {
+    let wallpaper = app_state
+        .saved_wallpapers
+        .iter()
+        .find(|w| w.monitor == monitor)
+        .cloned()
+        .unwrap_or_else(|| Wallpaper {
+            monitor: monitor.to_owned(),
+            ..Wallpaper::default()
+        });
+    let files = get_sorted_supported_wallpapers(app_state, &wallpaper);
+    let current = PathBuf::from(&wallpaper.path);
+    let index = files
+        .iter()
+        .position(|f| *f == current)
+        .map_or(0, |i| (i + 1) % files.len());
+    files.get(index).cloned()
+}
+
+/// Keeps the images whose orientation matches `orientation`. Images whose size
+/// cannot be read are kept so an unreadable header never empties the pool.
+fn filter_by_orientation(files: Vec<PathBuf>, orientation: Orientation) -> Vec<PathBuf> {
+    files
+        .into_iter()
+        .filter(|f| {
+            image::image_dimensions(f).map_or(true, |(width, height)| {
+                Orientation::from_size(width, height) == orientation
+            })
         })
-        .collect::<Vec<_>>()
+        .collect()
 }
 
-pub fn set_random_wallpapers(app_state: &mut AppState) -> anyhow::Result<()> {
+pub fn set_random_wallpapers(monitor: &str, app_state: &mut AppState) -> anyhow::Result<()> {
     let mut previous_wallpapers = app_state.saved_wallpapers.clone();
-    let files = get_previous_supported_wallpapers(app_state);
-    WallpaperChangers::killall_changers();
-    for w in &mut previous_wallpapers {
-        let index = rand::random_range(0..files.len());
-        log::debug!("{index}");
-        w.changer
-            .clone()
-            .change(files[index].clone(), w.monitor.clone())?;
-        files[index]
-            .clone()
-            .to_str()
-            .unwrap_or_default()
-            .clone_into(&mut w.path);
+    let monitors = if app_state.match_monitor_orientation {
+        connected_monitors()
+    } else {
+        Vec::new()
+    };
+    let conn = DatabaseConnection::new()?;
+    if monitor == "All" {
+        WallpaperChangers::killall_changers();
+    }
+    for i in 0..previous_wallpapers.len() {
+        if monitor != "All" && previous_wallpapers[i].monitor != monitor {
+            continue;
+        }
+        // Other outputs may already show their new image, so what to avoid is
+        // worked out from the wallpapers set so far.
+        let avoid = images_to_avoid(
+            &previous_wallpapers[i].monitor,
+            &previous_wallpapers,
+            app_state.distinct_random_monitors,
+        );
+        let w = &mut previous_wallpapers[i];
+        let mut files = get_previous_supported_wallpapers(app_state, w);
+        if let Some(monitor) = monitors.iter().find(|m| m.name == w.monitor) {
+            let matching = filter_by_orientation(files.clone(), monitor.orientation());
+            if matching.is_empty() {
+                warn!(
+                    "No {} wallpapers found for display \"{}\", ignoring orientation.",
+                    monitor.orientation(),
+                    w.monitor
+                );
+            } else {
+                files = matching;
+            }
+        }
+        if files.is_empty() {
+            error!(
+                "No supported wallpapers found for display \"{}\".",
+                w.monitor
+            );
+            continue;
+        }
+        let Some(image) = draw_image(&conn, &w.monitor, &files, &avoid, app_state.weighted_random)?
+        else {
+            continue;
+        };
+        log::debug!("{}", image.display());
+        w.changer.clone().change(image.clone(), w.monitor.clone())?;
+        image.to_str().unwrap_or_default().clone_into(&mut w.path);
+        w.span = false;
     }
     app_state.saved_wallpapers = previous_wallpapers;
     Ok(())
 }
 
+/// The changer last used on `monitor`, falling back to the last selected one.
+#[must_use]
+pub fn changer_for_monitor(monitor: &str, app_state: &AppState) -> WallpaperChangers {
+    app_state
+        .saved_wallpapers
+        .iter()
+        .find(|w| w.monitor == monitor)
+        .map(|w| w.changer.clone())
+        .or_else(|| app_state.changer.clone())
+        .or_else(|| {
+            app_state
+                .saved_wallpapers
+                .first()
+                .map(|w| w.changer.clone())
+        })
+        .unwrap_or_default()
+}
+
+pub fn set_wallpaper(monitor: &str, path: &Path, app_state: &mut AppState) -> anyhow::Result<()> {
+    if !path.is_file() {
+        return Err(anyhow!("{} is not a file", path.display()));
+    }
+    let changer = changer_for_monitor(monitor, app_state);
+    changer
+        .clone()
+        .change(path.to_path_buf(), monitor.to_owned())?;
+    save_wallpaper(monitor, path, changer, app_state);
+    Ok(())
+}
+
+fn save_wallpaper(
+    monitor: &str,
+    path: &Path,
+    changer: WallpaperChangers,
+    app_state: &mut AppState,
+) {
+    let path = path.to_str().unwrap_or_default().to_owned();
+    let monitor_description = connected_monitors()
+        .into_iter()
+        .find(|m| m.name == monitor)
+        .map(|m| m.description)
+        .unwrap_or_default();
+    app_state
+        .saved_wallpapers
+        .retain(|w| *w != Wallpaper::default());
+    match app_state
+        .saved_wallpapers
+        .iter_mut()
+        .find(|w| w.monitor == monitor)
+    {
+        Some(w) => {
+            w.changer = changer;
+            w.path = path;
+            w.monitor_description = monitor_description;
+            w.span = false;
+        }
+        None => app_state.saved_wallpapers.push(Wallpaper {
+            monitor: monitor.to_owned(),
+            path,
+            changer,
+            monitor_description,
+            span: false,
+        }),
+    }
+}
+
+/// Steps back through the history of wallpapers applied in the GUI. "All"
+/// steps back on every monitor that has a saved wallpaper and keeps the ones
+/// that succeeded when another monitor fails.
+pub fn set_previous_wallpaper(monitor: &str, app_state: &mut AppState) -> anyhow::Result<()> {
+    let conn = DatabaseConnection::new()?;
+    if monitor != "All" {
+        return step_back_history(&conn, monitor, app_state);
+    }
+    let monitors = app_state
+        .saved_wallpapers
+        .iter()
+        .map(|w| w.monitor.clone())
+        .collect::<Vec<_>>();
+    let mut failed = 0;
+    for monitor in &monitors {
+        if let Err(e) = step_back_history(&conn, monitor, app_state) {
+            error!("Failed to set the previous wallpaper on \"{monitor}\": {e}");
+            failed += 1;
+        }
+    }
+    if failed > 0 && failed == monitors.len() {
+        return Err(anyhow!(
+            "Failed to set the previous wallpaper on every monitor"
+        ));
+    }
+    Ok(())
+}
+
+fn step_back_history(
+    conn: &DatabaseConnection,
+    monitor: &str,
+    app_state: &mut AppState,
+) -> anyhow::Result<()> {
+    let Some(entry) = conn.undo_history(monitor)? else {
+        warn!("No earlier wallpaper in the history of \"{monitor}\"");
+        return Ok(());
+    };
+    if let Err(e) = entry
+        .changer
+        .clone()
+        .change(entry.path.clone(), monitor.to_owned())
+    {
+        conn.redo_history(monitor)?;
+        return Err(e);
+    }
+    save_wallpaper(monitor, &entry.path, entry.changer, app_state);
+    Ok(())
+}
+
+pub fn set_spanned_wallpaper(path: &Path, app_state: &mut AppState) -> anyhow::Result<()> {
+    if !path.is_file() {
+        return Err(anyhow!("{} is not a file", path.display()));
+    }
+    let changer = app_state
+        .changer
+        .clone()
+        .or_else(|| {
+            app_state
+                .saved_wallpapers
+                .first()
+                .map(|w| w.changer.clone())
+        })
+        .unwrap_or_default();
+    WallpaperChangers::killall_changers();
+    let spanned = span_wallpaper(&changer, path, &connected_monitors())?;
+    save_spanned_wallpapers(&mut app_state.saved_wallpapers, spanned);
+    Ok(())
+}
+
 pub fn print_app_version() -> anyhow::Result<()> {
     println!("{APP_VERSION}");
     Ok(())
 }
 
-pub fn cycle_next_wallpaper(args: &Cli, app_state: &mut AppState) -> anyhow::Result<()> {
+pub fn cycle_next_wallpaper(monitor: &str, app_state: &mut AppState) -> anyhow::Result<()> {
     let mut previous_wallpapers = app_state.saved_wallpapers.clone();
-    let sort_dropdown_string = app_state.sort_by.clone().unwrap_or_default();
-    let mut files = get_previous_supported_wallpapers(app_state);
-    let invert_sort_state = app_state.invert_sort;
-    sort_by_sort_dropdown_string(&mut files, &sort_dropdown_string, invert_sort_state);
-    if args.next.clone().unwrap_or_default() == "All" {
+    if monitor == "All" {
         for previous_wallpaper in &mut previous_wallpapers {
+            let files = get_sorted_supported_wallpapers(app_state, previous_wallpaper);
             let wallpaper_index = files.iter().position(|p| {
                 p.clone()
                     == previous_wallpaper
@@ -127,15 +458,13 @@ pub fn cycle_next_wallpaper(args: &Cli, app_state: &mut AppState) -> anyhow::Res
     } else {
         let previous_wallpaper = previous_wallpapers
             .iter()
-            .find(|w| *w.monitor == args.next.clone().unwrap_or_default());
+            .find(|w| *w.monitor == *monitor);
         if previous_wallpaper.is_none() {
-            error!(
-                "Display \"{}\" does not exist.",
-                args.next.clone().unwrap_or_default()
-            );
+            error!("Display \"{monitor}\" does not exist.");
             return Err(anyhow!("Failed to get previous wallpaper"));
         }
         let mut previous_wallpaper = previous_wallpaper.unwrap().clone();
+        let files = get_sorted_supported_wallpapers(app_state, &previous_wallpaper);
         try_set_next_wallpaper(
             &files,
             files.iter().position(|f| {
@@ -170,6 +499,7 @@ fn try_set_next_wallpaper(
         path.to_str()
             .unwrap_or_default()
             .clone_into(&mut previous_wallpaper.path);
+        previous_wallpaper.span = false;
     } else {
         warn!(
             "Wallpaper {} could not be found. Using first wallpaper",
@@ -188,6 +518,7 @@ fn try_set_next_wallpaper(
                 p.to_str()
                     .unwrap_or_default()
                     .clone_into(&mut previous_wallpaper.path);
+                previous_wallpaper.span = false;
             }
             None => {
                 error!(
@@ -199,38 +530,185 @@ fn try_set_next_wallpaper(
     Ok(())
 }
 
-pub fn delete_image_cache() -> anyhow::Result<()> {
+pub fn collect_cache_garbage(app_state: &AppState) -> anyhow::Result<()> {
+    let garbage = DatabaseConnection::new()?.collect_garbage(&get_thumbnail_dir()?, false)?;
+    let tiles = collect_span_garbage(&app_state.saved_wallpapers)?;
+    println!(
+        "Removed {} cache entries, {} thumbnails and {tiles} span tiles",
+        garbage.entries, garbage.thumbnails
+    );
+    Ok(())
+}
+
+pub fn find_duplicate_wallpapers(app_state: &AppState, trash: bool) -> anyhow::Result<()> {
+    let files = get_image_files(
+        &app_state.wallpaper_sources_for(app_state.wallpaper_folder.as_deref()),
+        &WallpaperChangers::all_accepted_formats(),
+    );
+    let images = files
+        .par_iter()
+        .filter_map(|p| DatabaseConnection::check_cache(p).ok())
+        .collect::<Vec<_>>();
+    let groups = find_duplicates(&images);
+    for group in &groups {
+        for (i, image) in group.iter().enumerate() {
+            let media = image.media.unwrap_or_default();
+            println!(
+                "{}\t{}x{}\t{}",
+                if i == 0 { "keep" } else { "trash" },
+                media.width,
+                media.height,
+                image.path.display()
+            );
+            if trash && i > 0 {
+                move_to_trash(&image.path)?;
+            }
+        }
+        println!();
+    }
+    println!(
+        "Found {} groups of duplicates among {} images",
+        groups.len(),
+        images.len()
+    );
+    Ok(())
+}
+
+pub fn export_library(file: &Path, config_file: &AppState) -> anyhow::Result<()> {
+    let library = Library::export(&DatabaseConnection::new()?)?;
+    let (images, collections, history) = (
+        library.images.len(),
+        library.collections.len(),
+        library.history.len(),
+    );
+    library.write_to_file(file, config_file.wallpaper_folder.as_deref())?;
+    println!(
+        "Exported {images} rated images, {collections} collections and {history} history entries"
+    );
+    Ok(())
+}
+
+pub fn import_library(file: &Path, config_file: &AppState) -> anyhow::Result<()> {
+    let library = Library::read_from_file(file, config_file.wallpaper_folder.as_deref())?;
+    library.import(&DatabaseConnection::new()?)?;
+    println!(
+        "Imported {} rated images, {} collections and {} history entries",
+        library.images.len(),
+        library.collections.len(),
+        library.history.len()
+    );
+    Ok(())
+}
+
+/// Size in bytes and number of files under `path`.
+fn disk_usage(path: &Path) -> (u64, usize) {
+    walkdir::WalkDir::new(path)
+        .into_iter()
+        .filter_map(std::result::Result::ok)
+        .filter(|e| e.file_type().is_file())
+        .fold((0, 0), |(size, files), e| {
+            (
+                size + e.metadata().map(|m| m.len()).unwrap_or_default(),
+                files + 1,
+            )
+        })
+}
+
+/// Handles `--delete-cache`, `--reset-config` and `--purge`. The cache holds
+/// thumbnails and the library (favorites, ratings and collections), the config
+/// holds `config.json` with the saved wallpapers and settings.
+pub fn delete_app_data(args: &Cli) -> anyhow::Result<()> {
     let xdg_dirs = xdg::BaseDirectories::with_prefix(CONFIG_APP_NAME);
-    let cache_path = xdg_dirs.place_cache_file(CACHE_FILE_NAME);
-    if cache_path.is_err() {
-        let msg = format!("Failed to get cache path, {}", cache_path.err().unwrap());
-        error!("{msg}");
-        return Err(anyhow!("{msg}"));
+    let mut targets = Vec::new();
+    let delete_cache = args.delete_cache || args.purge;
+    if delete_cache {
+        targets.push(
+            xdg_dirs
+                .get_cache_home()
+                .ok_or_else(|| anyhow!("Failed to get XDG cache home"))?,
+        );
+    }
+    if args.purge {
+        targets.push(
+            xdg_dirs
+                .get_config_home()
+                .ok_or_else(|| anyhow!("Failed to get XDG config home"))?,
+        );
+    } else if args.reset_config {
+        targets.push(get_config_file_path()?);
     }
+    let targets = targets
+        .into_iter()
+        .filter(|t| t.exists())
+        .collect::<Vec<_>>();
 
-    let Some(cache_home_dir) = xdg_dirs.get_config_home() else {
-        return Err(anyhow!("Failed to get XDG config home"));
-    };
+    let keep_library = delete_cache && args.keep_library;
+    if args.dry_run {
+        for target in &targets {
+            let (size, files) = disk_usage(target);
+            println!(
+                "Would remove {} ({files} files, {:.1} MiB)",
+                target.display(),
+                size as f64 / (1024.0 * 1024.0)
+            );
+        }
+        if keep_library {
+            println!("Would keep the rated images, collections and history");
+        }
+        return Ok(());
+    }
 
-    let Some(cache_dir) = xdg_dirs.get_cache_home() else {
-        return Err(anyhow!("Failed to get XDG cache home"));
+    // The library is written outside the directories being removed so it is
+    // not lost when the import into the new cache fails.
+    let library_file = if keep_library {
+        let library = Library::export(&DatabaseConnection::new()?)?;
+        if library.is_empty() {
+            None
+        } else {
+            let file = tempfile::Builder::new()
+                .prefix("waytrogen-library-")
+                .suffix(".json")
+                .tempfile()?;
+            library.write_to_file(file.path(), None)?;
+            Some(file)
+        }
+    } else {
+        None
     };
-
-    match remove_dir_all(cache_dir) {
-        Ok(()) => {}
-        Err(e) => {
-            let msg = format!("Failed to delete cache: {e}");
+    for target in &targets {
+        let result = if target.is_dir() {
+            remove_dir_all(target)
+        } else {
+            remove_file(target)
+        };
+        if let Err(e) = result {
+            let msg = format!("Failed to delete {}: {e}", target.display());
             error!("{msg}");
             return Err(anyhow!("{msg}"));
         }
+        println!("Removed {}", target.display());
     }
-
-    match remove_dir_all(cache_home_dir) {
-        Ok(()) => Ok(()),
-        Err(e) => {
-            let msg = format!("Failed to delete config file: {e}");
-            error!("{msg}");
-            Err(anyhow!("{msg}"))
+    if let Some(file) = library_file {
+        let imported = Library::read_from_file(file.path(), None).and_then(|library| {
+            library
+                .import(&DatabaseConnection::new()?)
+                .map(|()| library)
+        });
+        match imported {
+            Ok(library) => println!(
+                "Kept {} rated images, {} collections and {} history entries",
+                library.images.len(),
+                library.collections.len(),
+                library.history.len()
+            ),
+            Err(e) => {
+                let (_, path) = file.keep()?;
+                return Err(anyhow!(
+                    "Failed to restore the library, it is saved in {}: {e}",
+                    path.display()
+                ));
+            }
         }
     }
+    Ok(())
 }
@@ -1,6 +1,7 @@
 use clap::Parser;
 use std::path::PathBuf;
 use std::os::unix::fs::PermissionsExt;
+use std::time::Duration;
 
 pub fn parse_executable_script(s: &str) -> anyhow::Result<String> {
     if s.is_empty() {
@@ -16,6 +17,38 @@ pub fn parse_executable_script(s: &str) -> anyhow::Result<String> {
     Ok(s.to_owned())
 }
 
+pub fn parse_interval(s: &str) -> anyhow::Result<Duration> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (amount, unit) = s.split_at(split);
+    let amount = amount.parse::<u64>()?;
+    let unit_seconds = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        _ => return Err(anyhow::anyhow!("Unknown interval unit \"{unit}\", use s, m or h")),
+    };
+    let seconds = amount
+        .checked_mul(unit_seconds)
+        .ok_or_else(|| anyhow::anyhow!("Interval \"{s}\" is too long"))?;
+    if seconds == 0 {
+        return Err(anyhow::anyhow!("Interval must be greater than zero"));
+    }
+    Ok(Duration::from_secs(seconds))
+}
+
+pub fn parse_monitor_interval(s: &str) -> anyhow::Result<(String, Duration)> {
+    let Some((monitor, interval)) = s.split_once('=') else {
+        return Err(anyhow::anyhow!(
+            "Expected <MONITOR>=<INTERVAL>, e.g. DP-1=5m"
+        ));
+    };
+    if monitor.is_empty() {
+        return Err(anyhow::anyhow!("Monitor name is missing"));
+    }
+    Ok((monitor.to_owned(), parse_interval(interval)?))
+}
+
 #[derive(Parser, Clone)]
 pub struct Cli {
     #[arg(short, long)]
@@ -43,9 +76,88 @@ pub struct Cli {
     /// Startup delay to allow monitors to initialize.
     pub startup_delay: u64,
     #[arg(short, long)]
-    /// Delete image cache.
+    /// Delete the image cache: thumbnails, favorites, ratings, tags and collections. The config file is kept.
     pub delete_cache: bool,
+    #[arg(long)]
+    /// Delete config.json, resetting saved wallpapers and settings. The image cache is kept.
+    pub reset_config: bool,
+    #[arg(long)]
+    /// Delete both the image cache and the whole config directory.
+    pub purge: bool,
+    #[arg(long)]
+    /// With --delete-cache, --reset-config or --purge, only list what would be removed.
+    pub dry_run: bool,
+    #[arg(long)]
+    /// With --delete-cache or --purge, carry favorites, ratings, tags, collections and history over to the new cache.
+    pub keep_library: bool,
+    #[arg(long, value_name = "FILE")]
+    /// Write favorites, ratings, tags, collections and history to a JSON file. Paths inside the wallpaper folder are stored relative to it.
+    pub export_library: Option<PathBuf>,
+    #[arg(long, value_name = "FILE")]
+    /// Read a file written by --export-library into the image cache, resolving relative paths against the current wallpaper folder.
+    pub import_library: Option<PathBuf>,
+    #[arg(long)]
+    /// Remove cached thumbnails and entries of images that no longer exist, including their favorites, ratings and tags, and span tiles no saved wallpaper uses. Also runs on its own once a day while the GUI or daemon is open, but then keeps favorited, rated and tagged images in case their drive is only unmounted.
+    pub gc_cache: bool,
+    #[arg(long)]
+    /// List groups of visually similar images in the wallpaper folder, with the highest-resolution image of each group first.
+    pub find_duplicates: bool,
+    #[arg(long)]
+    /// With --find-duplicates, move all but the highest-resolution image of each group to the trash.
+    pub trash_duplicates: bool,
     #[arg(short = 'b', long)]
     /// Hide bottom bar
     pub hide_bottom_bar: Option<bool>,
+    #[arg(long)]
+    /// Keep running and change wallpaper(s) every interval. Cycles the next wallpaper, or sets random ones when combined with --random.
+    pub daemon: bool,
+    #[arg(long, default_value = "15m", value_parser = parse_interval)]
+    /// Time between wallpaper changes in daemon mode. Accepts seconds or a s, m or h suffix, e.g. 90, 30s, 15m, 1h.
+    pub interval: Duration,
+    #[arg(long, value_name = "MONITOR=INTERVAL", value_parser = parse_monitor_interval)]
+    /// Time between wallpaper changes on one monitor in daemon mode, e.g. DP-1=5m. Repeat for more monitors; the others use --interval.
+    pub monitor_interval: Vec<(String, Duration)>,
+    #[arg(long, value_name = "COMMAND")]
+    /// Send a command to a running waytrogen instance and print its response. Commands: "set <monitor> <path>", "next [monitor]", "random", "query" and "reload".
+    pub ipc: Option<String>,
+    #[arg(long, value_name = "IMAGE")]
+    /// Split one image across all monitors according to their positions and sizes, using the last set changer.
+    pub span: Option<PathBuf>,
+    #[arg(long, value_name = "NAME")]
+    /// Only draw wallpapers from this collection with --random, --next and --daemon. Collections are managed in the GUI options menu.
+    pub collection: Option<String>,
+    #[arg(long, value_name = "TAG", allow_hyphen_values = true)]
+    /// Only draw wallpapers with this tag with --random, --next and --daemon. Repeat to require several tags, or prefix a tag with "-" to leave out images that have it. Tags are edited from the image grid.
+    pub tag: Vec<String>,
+    #[arg(long, value_name = "MONITOR", num_args = 0..=1, default_missing_value = "All")]
+    /// Step back to the previously applied wallpaper on a monitor, using the history recorded by the GUI. Without a monitor, or with "All", steps back on every monitor.
+    pub previous: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interval_accepts_plain_seconds_and_unit_suffixes() {
+        assert_eq!(parse_interval("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_interval("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_interval("15m").unwrap(), Duration::from_secs(15 * 60));
+        assert_eq!(parse_interval("2h").unwrap(), Duration::from_secs(2 * 60 * 60));
+        assert!(parse_interval("0m").is_err());
+        assert!(parse_interval("5d").is_err());
+        assert!(parse_interval("m").is_err());
+        assert!(parse_interval(&format!("{}h", u64::MAX / 60)).is_err());
+    }
+
+    #[test]
+    fn monitor_intervals_name_a_monitor_and_an_interval() {
+        assert_eq!(
+            parse_monitor_interval("DP-1=5m").unwrap(),
+            ("DP-1".to_owned(), Duration::from_secs(5 * 60))
+        );
+        assert!(parse_monitor_interval("DP-1").is_err());
+        assert!(parse_monitor_interval("=5m").is_err());
+        assert!(parse_monitor_interval("DP-1=soon").is_err());
+    }
 }
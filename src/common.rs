@@ -1,20 +1,22 @@
+use crate::app_state::Messages;
+use crate::duplicates::perceptual_hash;
+use crate::media::{MediaInfo, probe};
+use crate::palette::{Rgb, dominant_colors};
+use crate::thumbnail::{PLACEHOLDER_THUMBNAIL, placeholder_thumbnail, write_thumbnail};
 use crate::wallpaper_changers::WallpaperChangers;
-use crate::{app_state::Messages, locale::TRANSLATION};
 use anyhow::anyhow;
 use iced::widget::{Tooltip, container, tooltip};
 use iced::{Element, Renderer, Theme};
-use image::ImageReader;
-use log::trace;
+use log::{trace, warn};
 use serde::{Deserialize, Serialize};
 use std::{
     fs,
-    io::Cursor,
+    io::{Read, Seek, SeekFrom},
     os::unix::fs::PermissionsExt,
     path::{Path, PathBuf},
-    process::Command,
     time::{Duration, UNIX_EPOCH},
 };
-use uuid::Uuid;
+use xxhash_rust::xxh3::Xxh3;
 
 use crate::app_state::SortBy;
 
@@ -27,22 +29,136 @@ pub const APP_ID: &str = "org.Waytrogen.Waytrogen";
 pub const GETTEXT_DOMAIN: &str = "waytrogen";
 pub const CONFIG_APP_NAME: &str = "waytrogen";
 pub const CACHE_FILE_NAME: &str = "cache.db";
+const THUMBNAIL_DIR: &str = "thumbnails";
 pub const CONFIG_FILE_NAME: &str = "config.json";
 pub const DEFAULT_TOOLTIP_DELAY: Duration = Duration::from_millis(500);
+/// Star rating given to images that were never rated, out of
+/// `MAX_IMAGE_WEIGHT`.
+pub const DEFAULT_IMAGE_WEIGHT: u8 = 3;
+pub const MAX_IMAGE_WEIGHT: u8 = 5;
+/// Files larger than twice this are only hashed at their start and end.
+const THUMBNAIL_HASH_SAMPLE: u64 = 8 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheFreshness {
+    Fresh,
+    /// Only the modification time changed, the contents are the same.
+    Touched,
+    /// The contents changed or the thumbnail is gone, so it has to be
+    /// generated again.
+    Stale,
+}
 
 #[derive(Debug, Clone, Default, PartialEq, Hash)]
 pub struct CacheImageFile {
     pub cached_image_path: PathBuf,
     pub name: String,
     pub date: u32,
+    /// When the source was created, or its modification time where the
+    /// filesystem does not record that. Zero when not recorded yet.
+    pub created: u32,
     pub path: PathBuf,
     pub favorite: bool,
+    pub weight: u8,
+    pub size: u64,
+    pub content_hash: String,
+    /// `None` for entries cached before media properties were recorded.
+    pub media: Option<MediaInfo>,
+    /// Dominant colors of the thumbnail, most common first.
+    pub palette: Vec<Rgb>,
+    pub perceptual_hash: Option<u64>,
 }
 
 impl CacheImageFile {
     pub fn from_file(path: &Path) -> anyhow::Result<CacheImageFile> {
-        let image = Self::generate_thumbnail(path)?;
-        Self::create_gtk_image(path, &image)
+        let (content_hash, size) = Self::hash_contents(path)?;
+        let image = Self::generate_thumbnail(path, &content_hash, size)?;
+        let mut image_file = Self::create_gtk_image(path, &image, content_hash, size)?;
+        image_file.record_properties(path);
+        Ok(image_file)
+    }
+
+    pub fn record_properties(&mut self, path: &Path) {
+        self.media = Some(probe(path));
+        self.palette = Vec::new();
+        self.perceptual_hash = None;
+        if !self.has_thumbnail() {
+            return;
+        }
+        match image::open(&self.cached_image_path) {
+            Ok(thumbnail) => {
+                self.palette = dominant_colors(&thumbnail.to_rgb8());
+                self.perceptual_hash = Some(perceptual_hash(&thumbnail));
+            }
+            Err(e) => warn!("Failed to read the thumbnail of {}: {e}", path.display()),
+        }
+    }
+
+    /// Compares the entry with the current state of `path`. The contents are
+    /// only hashed when the modification time changed but the size did not.
+    pub fn freshness(&self, path: &Path) -> anyhow::Result<CacheFreshness> {
+        if !self.cached_image_path.is_file() {
+            return Ok(CacheFreshness::Stale);
+        }
+        let (_, date) = Self::get_metadata(path)?;
+        let size = fs::metadata(path)?.len();
+        // Entries written before sizes were stored have a size of zero.
+        let size_known = self.size != 0;
+        if date == self.date && (!size_known || size == self.size) {
+            return Ok(CacheFreshness::Fresh);
+        }
+        if size_known && size != self.size {
+            return Ok(CacheFreshness::Stale);
+        }
+        if !self.content_hash.is_empty() && Self::hash_contents(path)?.0 == self.content_hash {
+            return Ok(CacheFreshness::Touched);
+        }
+        Ok(CacheFreshness::Stale)
+    }
+
+    pub fn update_metadata(&mut self, path: &Path) -> anyhow::Result<()> {
+        self.date = Self::get_metadata(path)?.1;
+        self.created = Self::get_created(path)?;
+        self.size = fs::metadata(path)?.len();
+        Ok(())
+    }
+
+    /// Generates a new entry for the changed `path`, keeping the favorite flag
+    /// and rating of this one.
+    pub fn regenerate(&self, path: &Path) -> anyhow::Result<CacheImageFile> {
+        let mut image_file = Self::from_file(path)?;
+        image_file.favorite = self.favorite;
+        image_file.weight = self.weight;
+        Ok(image_file)
+    }
+
+    /// Hashes the contents of `path`, sampling only the start and end of large
+    /// files such as videos. Returns the hash and the file size. The hash names
+    /// thumbnails on disk, so it has to stay the same across builds.
+    fn hash_contents(path: &Path) -> anyhow::Result<(String, u64)> {
+        let mut file = fs::File::open(path)?;
+        let size = file.metadata()?.len();
+        let mut hasher = Xxh3::new();
+        let mut buffer = vec![0; 64 * 1024];
+        let mut hash_section = |file: &mut fs::File, length: u64| -> anyhow::Result<()> {
+            let mut section = file.take(length);
+            loop {
+                let read = section.read(&mut buffer)?;
+                if read == 0 {
+                    return Ok(());
+                }
+                hasher.update(&buffer[..read]);
+            }
+        };
+        if size <= 2 * THUMBNAIL_HASH_SAMPLE {
+            hash_section(&mut file, size)?;
+        } else {
+            hash_section(&mut file, THUMBNAIL_HASH_SAMPLE)?;
+            file.seek(SeekFrom::End(-THUMBNAIL_HASH_SAMPLE.cast_signed()))?;
+            hash_section(&mut file, THUMBNAIL_HASH_SAMPLE)?;
+        }
+        hasher.update(&size.to_le_bytes());
+        Ok((format!("{:016x}", hasher.digest()), size))
     }
 
     fn get_metadata(path: &Path) -> anyhow::Result<(String, u32)> {
@@ -55,72 +171,66 @@ impl CacheImageFile {
         Ok((name, date))
     }
 
-    fn create_gtk_image(path: &Path, image: &Path) -> anyhow::Result<CacheImageFile> {
+    /// Many filesystems do not record when a file was created, in which case
+    /// the modification time stands in.
+    pub fn get_created(path: &Path) -> anyhow::Result<u32> {
+        let metadata = fs::metadata(path)?;
+        let created = metadata.created().or_else(|_| metadata.modified())?;
+        let created = created.duration_since(UNIX_EPOCH)?.as_secs();
+        Ok(u32::try_from(created)?)
+    }
+
+    fn create_gtk_image(
+        path: &Path,
+        image: &Path,
+        content_hash: String,
+        size: u64,
+    ) -> anyhow::Result<CacheImageFile> {
         let fields = Self::get_metadata(path)?;
         let image_file = CacheImageFile {
             cached_image_path: image.to_path_buf(),
             path: path.to_path_buf(),
             name: fields.0,
             date: fields.1,
+            created: Self::get_created(path)?,
             favorite: false,
+            weight: DEFAULT_IMAGE_WEIGHT,
+            size,
+            content_hash,
+            media: None,
+            palette: Vec::new(),
+            perceptual_hash: None,
         };
         Ok(image_file)
     }
 
-    fn generate_thumbnail(path: &Path) -> anyhow::Result<PathBuf> {
-        if let Ok(i) = Self::try_write_thumbnail_with_image(path) {
-            return Ok(i);
-        }
-        if let Ok(i) = Self::try_write_thumbnail_with_ffmpeg(path) {
-            return Ok(i);
+    /// Thumbnails are named after the source contents, so identical files
+    /// share one and an already generated thumbnail is reused as is. Files no
+    /// backend can read get the placeholder thumbnail.
+    fn generate_thumbnail(path: &Path, content_hash: &str, size: u64) -> anyhow::Result<PathBuf> {
+        let thumbnail_dir = get_thumbnail_dir()?;
+        let thumbnail = thumbnail_dir.join(format!("{content_hash}-{size:x}.png"));
+        if thumbnail.is_file() {
+            return Ok(thumbnail);
         }
-        Err(anyhow::anyhow!(
-            "{}: {}",
-            TRANSLATION.get_translation("failed-to-create-thumbnail-for"),
-            path.to_string_lossy()
-        ))
-    }
-    fn try_write_thumbnail_with_ffmpeg(path: &Path) -> anyhow::Result<PathBuf> {
-        let temp_dir = String::from_utf8(Command::new("mktemp").arg("-d").output()?.stdout)?;
-        let output_path = PathBuf::from(temp_dir.trim()).join("temp.png");
-        trace!("ffmpeg Output Path: {}", output_path.to_string_lossy());
-        let code = Command::new("ffmpeg")
-            .arg("-i")
-            .arg(path)
-            .arg("-y")
-            .arg("-ss")
-            .arg("00:00:00")
-            .arg("-frames:v")
-            .arg("1")
-            .arg(output_path.clone())
-            .spawn()?
-            .wait()?
-            .code()
-            .unwrap_or(255);
-        match code {
-            0 => Self::try_write_thumbnail_with_image(&output_path),
-            _ => Err(anyhow::anyhow!(TRANSLATION.get_translation(
-                "Thumbnail could not be generated using ffmpg."
-            ))),
+        match write_thumbnail(path, &thumbnail) {
+            Ok(backend) => {
+                trace!("Thumbnail of {} written by {backend}", path.display());
+                Ok(thumbnail)
+            }
+            Err(e) => {
+                warn!("{e}");
+                placeholder_thumbnail(&thumbnail_dir)
+            }
         }
     }
 
-    fn try_write_thumbnail_with_image(path: &Path) -> anyhow::Result<PathBuf> {
-        let thumbnail = ImageReader::open(path)?
-            .with_guessed_format()?
-            .decode()?
-            .thumbnail(THUMBNAIL_WIDTH as u32, THUMBNAIL_HEIGHT as u32)
-            .to_rgb8();
-        let image_name = format!("{}.png", Uuid::new_v4());
-        let xdg_dirs = xdg::BaseDirectories::with_prefix(CONFIG_APP_NAME);
-        let Some(cache_dir) = xdg_dirs.get_cache_home() else {
-            return Err(anyhow!("Failed to get cache directory"));
-        };
-        let image_file = cache_dir.join(Path::new(&image_name));
-        let mut buff: Vec<u8> = vec![];
-        thumbnail.write_to(&mut Cursor::new(&mut buff), image::ImageFormat::Png)?;
-        fs::write(&image_file, buff)?;
-        Ok(image_file)
+    /// Whether a real thumbnail could be made, as opposed to the placeholder.
+    #[must_use]
+    pub fn has_thumbnail(&self) -> bool {
+        self.cached_image_path
+            .file_name()
+            .is_none_or(|n| n != PLACEHOLDER_THUMBNAIL)
     }
 }
 
@@ -129,43 +239,37 @@ pub struct Wallpaper {
     pub monitor: String,
     pub path: String,
     pub changer: WallpaperChangers,
+    #[serde(default)]
+    pub monitor_description: String,
+    /// `path` is the full image, cut into tiles across monitors on restore.
+    #[serde(default)]
+    pub span: bool,
 }
 
-pub const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
+/// A wallpaper that was applied from the GUI. `undone` entries were stepped
+/// back over and can be redone until a new wallpaper is applied.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HistoryEntry {
+    pub id: i64,
+    pub monitor: String,
+    pub path: PathBuf,
+    pub changer: WallpaperChangers,
+    pub applied_at: i64,
+    pub undone: bool,
+}
 
-pub fn sort_by_sort_dropdown_string(files: &mut [PathBuf], sort_by: &SortBy, invert_sort: bool) {
-    match sort_by {
-        SortBy::Name => {
-            files.sort_by(|f1, f2| {
-                if invert_sort {
-                    f1.file_name().partial_cmp(&f2.file_name()).unwrap()
-                } else {
-                    f2.file_name().partial_cmp(&f1.file_name()).unwrap()
-                }
-            });
-        }
-        SortBy::Date => {
-            files.sort_by(|f1, f2| {
-                if invert_sort {
-                    f1.metadata()
-                        .unwrap()
-                        .created()
-                        .unwrap()
-                        .partial_cmp(&f2.metadata().unwrap().created().unwrap())
-                        .unwrap()
-                } else {
-                    f2.metadata()
-                        .unwrap()
-                        .created()
-                        .unwrap()
-                        .partial_cmp(&f1.metadata().unwrap().created().unwrap())
-                        .unwrap()
-                }
-            });
-        }
-    }
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct MonitorProfile {
+    pub monitor: String,
+    pub wallpaper_folder: Option<PathBuf>,
+    pub changer: Option<WallpaperChangers>,
+    pub sort_by: Option<SortBy>,
+    pub invert_sort: bool,
 }
 
+pub const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
+
 pub fn parse_executable_script(s: &str) -> anyhow::Result<String> {
     if s.is_empty() {
         return Ok(String::new());
@@ -186,6 +290,42 @@ pub fn get_config_file_path() -> anyhow::Result<PathBuf> {
     Ok(config_file)
 }
 
+pub fn get_thumbnail_dir() -> anyhow::Result<PathBuf> {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix(CONFIG_APP_NAME);
+    let Some(cache_dir) = xdg_dirs.get_cache_home() else {
+        return Err(anyhow!("Failed to get cache directory"));
+    };
+    let thumbnail_dir = cache_dir.join(THUMBNAIL_DIR);
+    if !thumbnail_dir.is_dir() {
+        fs::create_dir_all(&thumbnail_dir)?;
+        remove_legacy_thumbnails(&cache_dir);
+    }
+    Ok(thumbnail_dir)
+}
+
+/// Older versions wrote thumbnails straight into the cache directory.
+fn remove_legacy_thumbnails(cache_dir: &Path) {
+    let Ok(entries) = fs::read_dir(cache_dir) else {
+        return;
+    };
+    for thumbnail in entries.filter_map(Result::ok).map(|e| e.path()) {
+        if thumbnail.is_file()
+            && thumbnail.extension().is_some_and(|e| e == "png")
+            && let Err(e) = fs::remove_file(&thumbnail)
+        {
+            warn!("Failed to delete {}: {e}", thumbnail.display());
+        }
+    }
+}
+
+pub fn get_runtime_dir() -> anyhow::Result<PathBuf> {
+    let root = std::env::var_os("XDG_RUNTIME_DIR")
+        .ok_or_else(|| anyhow::anyhow!("XDG_RUNTIME_DIR is not set"))?;
+    let dir = PathBuf::from(root).join(CONFIG_APP_NAME);
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
 pub fn create_tooltip<'a>(
     element: Element<'a, Messages>,
     tooltip_element: Element<'a, Messages>,
@@ -194,3 +334,52 @@ pub fn create_tooltip<'a>(
         .delay(DEFAULT_TOOLTIP_DELAY)
         .style(container::bordered_box)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::SystemTime;
+    use tempfile::TempDir;
+
+    #[test]
+    fn cache_entries_notice_edited_and_touched_sources() {
+        let temp = TempDir::new().unwrap();
+        let dir = temp.path();
+        let (source, thumbnail) = (dir.join("beach.jpg"), dir.join("thumbnail.png"));
+        fs::write(&source, b"first").unwrap();
+        fs::write(&thumbnail, b"").unwrap();
+        let set_modified = |secs: u64| {
+            fs::File::options()
+                .write(true)
+                .open(&source)
+                .unwrap()
+                .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+                .unwrap();
+        };
+        set_modified(1_000);
+        let mut entry = CacheImageFile {
+            cached_image_path: thumbnail.clone(),
+            content_hash: CacheImageFile::hash_contents(&source).unwrap().0,
+            ..CacheImageFile::default()
+        };
+        // Thumbnails are named by this hash, so it must not change between builds.
+        assert_eq!(entry.content_hash, "f01357e5d0b99af0");
+        entry.update_metadata(&source).unwrap();
+        let freshness = |entry: &CacheImageFile| entry.freshness(&source).unwrap();
+        assert_eq!(freshness(&entry), CacheFreshness::Fresh);
+
+        set_modified(2_000);
+        assert_eq!(freshness(&entry), CacheFreshness::Touched);
+
+        fs::write(&source, b"other").unwrap();
+        set_modified(3_000);
+        assert_eq!(freshness(&entry), CacheFreshness::Stale);
+
+        fs::write(&source, b"a longer file").unwrap();
+        assert_eq!(freshness(&entry), CacheFreshness::Stale);
+
+        entry.update_metadata(&source).unwrap();
+        fs::remove_file(&thumbnail).unwrap();
+        assert_eq!(freshness(&entry), CacheFreshness::Stale);
+    }
+}
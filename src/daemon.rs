@@ -0,0 +1,267 @@
+use crate::{
+    app_state::AppState,
+    cli::{cycle_next_wallpaper, reapply_saved_wallpaper, set_random_wallpapers, set_wallpaper},
+    cli_parser::Cli,
+    database::collect_cache_garbage_if_due,
+    ipc::{self, IpcCommand, IpcRequest},
+    monitors::{Monitor, MonitorEvent, watch_monitors},
+    schedule::Scheduler,
+    wallpaper_changers::WallpaperChangers,
+};
+use chrono::Local;
+use log::{debug, error, info, warn};
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    thread::{self, sleep},
+    time::{Duration, Instant},
+};
+
+// Timer ticks and IPC commands both read, change and write config.json, so
+// they take turns.
+static DAEMON_LOCK: Mutex<()> = Mutex::new(());
+
+fn load_config(args: &Cli) -> anyhow::Result<AppState> {
+    let mut app_state = AppState::get_config_file()?;
+    app_state.apply_cli_arguments(args);
+    Ok(app_state)
+}
+
+pub fn run_daemon(args: &Cli) -> anyhow::Result<()> {
+    let monitor = args.next.clone().unwrap_or_else(|| "All".to_owned());
+    info!(
+        "Starting daemon: changing wallpaper(s) on \"{monitor}\" every {}s",
+        args.interval.as_secs()
+    );
+    let ipc_args = args.clone();
+    if let Err(e) = ipc::serve(move |request| handle_ipc_request(&ipc_args, &request)) {
+        warn!("IPC socket is unavailable: {e}");
+    }
+    let hotplug_args = args.clone();
+    thread::spawn(move || {
+        let result = watch_monitors(|event| {
+            if let MonitorEvent::Added(monitor) = event {
+                handle_monitor_added(&hotplug_args, &monitor);
+            }
+            Ok(())
+        });
+        if let Err(e) = result {
+            warn!("Stopped watching for monitor changes: {e}");
+        }
+    });
+    let mut scheduler = Scheduler::default();
+    let mut timers = MonitorTimers::new(args);
+    loop {
+        let wait = match schedule_tick(args, &mut scheduler) {
+            Some(wait) => wait,
+            None => rotation_tick(args, &monitor, &mut timers)?,
+        };
+        sleep(wait);
+        if let Ok(app_state) = load_config(args) {
+            collect_cache_garbage_if_due(&app_state.saved_wallpapers);
+        }
+    }
+}
+
+/// When each monitor is next due for a new wallpaper. Monitors without a
+/// `--monitor-interval` use `--interval`.
+struct MonitorTimers {
+    interval: Duration,
+    intervals: HashMap<String, Duration>,
+    next_due: HashMap<String, Instant>,
+}
+
+impl MonitorTimers {
+    fn new(args: &Cli) -> MonitorTimers {
+        MonitorTimers {
+            interval: args.interval,
+            intervals: args.monitor_interval.iter().cloned().collect(),
+            next_due: HashMap::new(),
+        }
+    }
+
+    fn interval(&self, monitor: &str) -> Duration {
+        self.intervals
+            .get(monitor)
+            .copied()
+            .unwrap_or(self.interval)
+    }
+
+    /// Returns the monitors whose time has come and starts their next
+    /// interval. Monitors seen for the first time wait one interval.
+    fn take_due(&mut self, monitors: &[String], now: Instant) -> Vec<String> {
+        self.next_due.retain(|m, _| monitors.contains(m));
+        let mut due = vec![];
+        for monitor in monitors {
+            let interval = self.interval(monitor);
+            let next = self
+                .next_due
+                .entry(monitor.clone())
+                .or_insert(now + interval);
+            if *next <= now {
+                *next = now + interval;
+                due.push(monitor.clone());
+            }
+        }
+        due
+    }
+
+    fn wait(&self, now: Instant) -> Duration {
+        self.next_due
+            .values()
+            .min()
+            .map_or(self.interval, |next| next.saturating_duration_since(now))
+    }
+}
+
+/// Changes the wallpaper of every monitor that is due and returns how long to
+/// sleep until the next one is.
+fn rotation_tick(
+    args: &Cli,
+    monitor: &str,
+    timers: &mut MonitorTimers,
+) -> anyhow::Result<Duration> {
+    let Ok(_guard) = DAEMON_LOCK.lock() else {
+        return Err(anyhow::anyhow!("Daemon lock is poisoned"));
+    };
+    // The configuration is re-read on every tick so wallpapers chosen in the
+    // GUI or with --next/--random in between are not overwritten.
+    let mut app_state = match load_config(args) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("Failed to get config file: {e}");
+            return Ok(args.interval);
+        }
+    };
+    let monitors = app_state
+        .saved_wallpapers
+        .iter()
+        .map(|w| w.monitor.clone())
+        .filter(|m| monitor == "All" || m == monitor)
+        .collect::<Vec<_>>();
+    let due = timers.take_due(&monitors, Instant::now());
+    if !due.is_empty()
+        && let Err(e) = daemon_tick(args, &due, &mut app_state)
+    {
+        error!("Failed to change wallpaper(s) in daemon mode: {e}");
+    }
+    Ok(timers.wait(Instant::now()))
+}
+
+/// Runs the configured schedule instead of the interval rotation. Returns how
+/// long to sleep, or `None` when no schedule is configured.
+fn schedule_tick(args: &Cli, scheduler: &mut Scheduler) -> Option<Duration> {
+    let Ok(_guard) = DAEMON_LOCK.lock() else {
+        error!("Daemon lock is poisoned");
+        return None;
+    };
+    let mut app_state = match load_config(args) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("Failed to get config file: {e}");
+            return None;
+        }
+    };
+    if app_state.schedule.is_empty() {
+        return None;
+    }
+    let wait = scheduler.apply(&mut app_state, &Local::now(), args.interval);
+    if let Err(e) = app_state.write_to_config_file() {
+        error!("Failed to write config file: {e}");
+    }
+    Some(wait)
+}
+
+fn daemon_tick(args: &Cli, monitors: &[String], app_state: &mut AppState) -> anyhow::Result<()> {
+    if app_state.saved_wallpapers.iter().any(
+        |w| matches!(&w.changer, WallpaperChangers::MpvPaper(s) if s.slideshow_settings.enable),
+    ) {
+        warn!("mpvpaper slideshow is enabled and will compete with daemon mode");
+    }
+    for monitor in monitors {
+        let result = if args.random {
+            set_random_wallpapers(monitor, app_state)
+        } else {
+            cycle_next_wallpaper(monitor, app_state)
+        };
+        if let Err(e) = result {
+            error!("Failed to change wallpaper on \"{monitor}\": {e}");
+        }
+    }
+    debug!("Daemon tick applied: {:?}", app_state.saved_wallpapers);
+    app_state.write_to_config_file()
+}
+
+fn handle_monitor_added(args: &Cli, monitor: &Monitor) {
+    let Ok(_guard) = DAEMON_LOCK.lock() else {
+        error!("Daemon lock is poisoned");
+        return;
+    };
+    let result = load_config(args).and_then(|mut app_state| {
+        if reapply_saved_wallpaper(monitor, &mut app_state)? {
+            app_state.write_to_config_file()?;
+        }
+        Ok(())
+    });
+    if let Err(e) = result {
+        error!("Failed to restore wallpaper on \"{}\": {e}", monitor.name);
+    }
+}
+
+fn handle_ipc_request(args: &Cli, request: &IpcRequest) {
+    let response = DAEMON_LOCK
+        .lock()
+        .map_err(|_| anyhow::anyhow!("Daemon lock is poisoned"))
+        .and_then(|_guard| execute_ipc_command(args, &request.command));
+    request.respond(response);
+}
+
+fn execute_ipc_command(args: &Cli, command: &IpcCommand) -> anyhow::Result<String> {
+    let mut app_state = load_config(args)?;
+    match command {
+        IpcCommand::Set { monitor, path } => set_wallpaper(monitor, path, &mut app_state)?,
+        IpcCommand::Next(monitor) => {
+            cycle_next_wallpaper(monitor.as_deref().unwrap_or("All"), &mut app_state)?;
+        }
+        IpcCommand::Random => set_random_wallpapers("All", &mut app_state)?,
+        IpcCommand::Query => return Ok(serde_json::to_string(&app_state.saved_wallpapers)?),
+        // config.json is re-read for every command and tick, so there is no
+        // cached state to drop.
+        IpcCommand::Reload => return Ok("OK".to_owned()),
+    }
+    app_state.write_to_config_file()?;
+    Ok("OK".to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn monitors_change_on_their_own_interval() {
+        let args = Cli::parse_from([
+            "waytrogen",
+            "--daemon",
+            "--interval",
+            "10m",
+            "--monitor-interval",
+            "DP-2=4m",
+        ]);
+        let mut timers = MonitorTimers::new(&args);
+        let monitors = ["DP-1".to_owned(), "DP-2".to_owned()];
+        let minutes = |m: u64| Duration::from_secs(m * 60);
+        let start = Instant::now();
+
+        assert!(timers.take_due(&monitors, start).is_empty());
+        assert_eq!(timers.wait(start), minutes(4));
+        assert_eq!(timers.take_due(&monitors, start + minutes(4)), ["DP-2"]);
+        assert_eq!(timers.wait(start + minutes(4)), minutes(4));
+        assert_eq!(timers.take_due(&monitors, start + minutes(8)), ["DP-2"]);
+        assert_eq!(timers.wait(start + minutes(8)), minutes(2));
+        assert_eq!(timers.take_due(&monitors, start + minutes(10)), ["DP-1"]);
+        // Unplugged monitors are forgotten.
+        timers.take_due(&monitors[..1], start + minutes(11));
+        assert_eq!(timers.wait(start + minutes(11)), minutes(9));
+    }
+}
@@ -1,88 +1,812 @@
-use crate::common::{CACHE_FILE_NAME, CONFIG_APP_NAME, CacheImageFile};
+use crate::common::{
+    CACHE_FILE_NAME, CONFIG_APP_NAME, CacheFreshness, CacheImageFile, DEFAULT_IMAGE_WEIGHT,
+    HistoryEntry, Wallpaper, get_thumbnail_dir,
+};
 use crate::locale::TRANSLATION;
+use crate::media::{MediaInfo, MediaKind};
+use crate::palette::{parse_hex, to_hex};
+use crate::sort::ImageUsage;
+use crate::span::collect_span_garbage;
+use crate::wallpaper_changers::WallpaperChangers;
 use anyhow::anyhow;
 use log::{debug, trace, warn};
-use rusqlite::Connection;
-use std::path::{Path, PathBuf};
+use rusqlite::{Connection, OptionalExtension, Row, Transaction, TransactionBehavior};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Entries kept per monitor; older ones are dropped when new ones are added.
+const HISTORY_LIMIT: i64 = 500;
+const HISTORY_COLUMNS: &str = "id, monitor, path, changer, applied_at, undone";
+const IMAGE_FILE_COLUMNS: &str =
+    "image, name, date, path, favorite, weight, size, hash, kind, width, height, duration, palette,
+     phash, created";
+/// How often the GUI and daemon clean up the cache on their own.
+const GC_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Thumbnails younger than this may still be waiting for their cache entry.
+const GC_THUMBNAIL_GRACE: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CacheGarbage {
+    pub entries: usize,
+    pub thumbnails: usize,
+}
 
 pub struct DatabaseConnection {
     connetion: Connection,
 }
 
+/// Schema changes of the cache database, oldest first. Migration `n` moves a
+/// cache from `user_version` `n` to `n + 1`, so new ones are only ever
+/// appended.
+const MIGRATIONS: [fn(&Connection) -> anyhow::Result<()>; 9] = [
+    create_initial_tables,
+    upgrade_image_files,
+    create_wallpaper_state_tables,
+    create_image_tag_table,
+    add_media_columns,
+    add_palette_column,
+    add_perceptual_hash_column,
+    move_thumbnails,
+    add_created_column,
+];
+
+/// The tables caches had before they were versioned, which is why they may
+/// already exist.
+fn create_initial_tables(conn: &Connection) -> anyhow::Result<()> {
+    conn.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS imagefile
+          (
+             image TEXT NOT NULL,
+             name TEXT NOT NULL,
+             date INTEGER NOT NULL,
+             path TEXT NOT NULL,
+             favorite INTEGER NOT NULL
+          );
+        CREATE TABLE IF NOT EXISTS collection
+          (
+             name TEXT PRIMARY KEY NOT NULL
+          );
+        CREATE TABLE IF NOT EXISTS collection_image
+          (
+             collection TEXT NOT NULL REFERENCES collection(name) ON DELETE CASCADE,
+             path TEXT NOT NULL,
+             PRIMARY KEY (collection, path)
+          );
+        ",
+    )?;
+    Ok(())
+}
+
+/// Adds the rating and content columns to `imagefile` and makes paths unique.
+/// Unversioned caches appended a row on every change, so only the newest row
+/// of each path is kept.
+fn upgrade_image_files(conn: &Connection) -> anyhow::Result<()> {
+    conn.execute_batch(
+        "
+        ALTER TABLE imagefile ADD COLUMN weight INTEGER NOT NULL DEFAULT 3;
+        ALTER TABLE imagefile ADD COLUMN size INTEGER NOT NULL DEFAULT 0;
+        ALTER TABLE imagefile ADD COLUMN hash TEXT NOT NULL DEFAULT '';
+        DELETE FROM imagefile
+          WHERE rowid NOT IN (SELECT MAX(rowid) FROM imagefile GROUP BY path);
+        CREATE UNIQUE INDEX IF NOT EXISTS imagefile_path ON imagefile(path);
+        ",
+    )?;
+    Ok(())
+}
+
+fn create_wallpaper_state_tables(conn: &Connection) -> anyhow::Result<()> {
+    conn.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS history
+          (
+             id INTEGER PRIMARY KEY AUTOINCREMENT,
+             monitor TEXT NOT NULL,
+             path TEXT NOT NULL,
+             changer TEXT NOT NULL,
+             applied_at INTEGER NOT NULL,
+             undone INTEGER NOT NULL DEFAULT 0
+          );
+        CREATE TABLE IF NOT EXISTS shuffle_bag
+          (
+             pool TEXT NOT NULL,
+             path TEXT NOT NULL,
+             PRIMARY KEY (pool, path)
+          );
+        CREATE TABLE IF NOT EXISTS image_shown
+          (
+             path TEXT PRIMARY KEY NOT NULL,
+             shown_at INTEGER NOT NULL
+          );
+        CREATE TABLE IF NOT EXISTS cache_meta
+          (
+             key TEXT PRIMARY KEY NOT NULL,
+             value INTEGER NOT NULL
+          );
+        ",
+    )?;
+    Ok(())
+}
+
+/// User defined tags, stored normalized by [`crate::query::normalize_tag`].
+fn create_image_tag_table(conn: &Connection) -> anyhow::Result<()> {
+    conn.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS image_tag
+          (
+             path TEXT NOT NULL,
+             tag TEXT NOT NULL,
+             PRIMARY KEY (path, tag)
+          );
+        ",
+    )?;
+    Ok(())
+}
+
+/// Media properties recorded with thumbnails. An empty `kind` marks entries
+/// that have not been probed yet.
+fn add_media_columns(conn: &Connection) -> anyhow::Result<()> {
+    conn.execute_batch(
+        "
+        ALTER TABLE imagefile ADD COLUMN kind TEXT NOT NULL DEFAULT '';
+        ALTER TABLE imagefile ADD COLUMN width INTEGER NOT NULL DEFAULT 0;
+        ALTER TABLE imagefile ADD COLUMN height INTEGER NOT NULL DEFAULT 0;
+        ALTER TABLE imagefile ADD COLUMN duration INTEGER NOT NULL DEFAULT 0;
+        ",
+    )?;
+    Ok(())
+}
+
+/// Dominant colors of thumbnails, as comma separated `#rrggbb` values.
+/// Clearing `kind` has every image probed again, which fills them in.
+fn add_palette_column(conn: &Connection) -> anyhow::Result<()> {
+    conn.execute_batch(
+        "
+        ALTER TABLE imagefile ADD COLUMN palette TEXT NOT NULL DEFAULT '';
+        UPDATE imagefile SET kind = '';
+        ",
+    )?;
+    Ok(())
+}
+
+fn add_perceptual_hash_column(conn: &Connection) -> anyhow::Result<()> {
+    conn.execute_batch(
+        "
+        ALTER TABLE imagefile ADD COLUMN phash INTEGER;
+        UPDATE imagefile SET kind = '';
+        ",
+    )?;
+    Ok(())
+}
+
+/// Thumbnails moved to their own directory and are named by a hash that no
+/// longer changes with the Rust version, so every entry is regenerated.
+fn move_thumbnails(conn: &Connection) -> anyhow::Result<()> {
+    conn.execute_batch(
+        "
+        UPDATE imagefile SET image = '', hash = '';
+        ",
+    )?;
+    Ok(())
+}
+
+/// Creation times of the sources, for sorting. Entries without one get it
+/// the next time they are checked.
+fn add_created_column(conn: &Connection) -> anyhow::Result<()> {
+    conn.execute_batch(
+        "
+        ALTER TABLE imagefile ADD COLUMN created INTEGER NOT NULL DEFAULT 0;
+        ",
+    )?;
+    Ok(())
+}
+
+/// Cleans up the cache in the background of the GUI and daemon, at most once
+/// per `GC_INTERVAL`.
+pub fn collect_cache_garbage_if_due(saved_wallpapers: &[Wallpaper]) {
+    let result = DatabaseConnection::new()
+        .and_then(|conn| conn.collect_garbage_if_due(&get_thumbnail_dir()?));
+    match result {
+        Ok(Some(garbage)) => {
+            debug!(
+                "Removed {} cache entries and {} thumbnails",
+                garbage.entries, garbage.thumbnails
+            );
+            match collect_span_garbage(saved_wallpapers) {
+                Ok(tiles) => debug!("Removed {tiles} span tiles"),
+                Err(e) => warn!("Failed to clean up the span tiles: {e}"),
+            }
+        }
+        Ok(None) => {}
+        Err(e) => warn!("Failed to clean up the image cache: {e}"),
+    }
+}
+
 impl DatabaseConnection {
     pub fn new() -> anyhow::Result<DatabaseConnection> {
         let xdg_dirs = xdg::BaseDirectories::with_prefix(CONFIG_APP_NAME);
         let cache_path = xdg_dirs.place_cache_file(CACHE_FILE_NAME)?;
         let conn = Connection::open(cache_path.to_string_lossy().as_ref())?;
+        Self::initialize(conn)
+    }
+
+    fn initialize(conn: Connection) -> anyhow::Result<DatabaseConnection> {
         // WAL mode + a busy timeout make concurrent thumbnail cache writes from
         // the rayon thread pool much less likely to fail with "database is locked".
         conn.execute_batch(
             "
             PRAGMA journal_mode = WAL;
             PRAGMA busy_timeout = 5000;
-            CREATE TABLE IF NOT EXISTS imagefile
-              (
-                 image TEXT NOT NULL,
-                 name TEXT NOT NULL,
-                 date INTEGER NOT NULL,
-                 path TEXT NOT NULL,
-                 favorite INTEGER NOT NULL
-              );
+            PRAGMA foreign_keys = ON;
             ",
         )?;
+        if Self::schema_version(&conn)? < MIGRATIONS.len() {
+            // Several rayon threads may open the cache at once on first start,
+            // so the version is read again under a write lock.
+            let transaction = Transaction::new_unchecked(&conn, TransactionBehavior::Immediate)?;
+            Self::migrate(&transaction)?;
+            transaction.commit()?;
+        }
         Ok(DatabaseConnection { connetion: conn })
     }
 
-    pub fn select_image_file(&self, path: &Path) -> anyhow::Result<CacheImageFile> {
-        let query = "SELECT image, name, date, path, favorite FROM ImageFile where path = ?1 AND typeof(image) != 'blob';";
-        let mut statement = self.connetion.prepare(query)?;
-
-        let mut pix_buf_bytes = statement
-            .query_map([&path.to_string_lossy()], |row| {
-                let favorite = row.get::<usize, i32>(4)?;
-                let favorite = favorite > 0;
-                Ok(CacheImageFile {
-                    cached_image_path: PathBuf::from(row.get::<usize, String>(0)?),
-                    name: row.get(1)?,
-                    date: row.get(2)?,
-                    path: PathBuf::from(row.get::<usize, String>(3)?),
-                    favorite,
-                })
-            })?
-            .filter_map(std::result::Result::ok)
-            .collect::<Vec<_>>();
-        if pix_buf_bytes.is_empty() {
-            return Err(anyhow!("No result could be found"));
+    #[cfg(test)]
+    pub(crate) fn open_in_memory() -> anyhow::Result<DatabaseConnection> {
+        Self::initialize(Connection::open_in_memory()?)
+    }
+
+    fn schema_version(conn: &Connection) -> anyhow::Result<usize> {
+        let version = conn.query_row("PRAGMA user_version;", [], |row| row.get::<usize, i64>(0))?;
+        Ok(usize::try_from(version)?)
+    }
+
+    /// Applies the migrations the cache has not seen yet, recording each one in
+    /// `user_version`.
+    fn migrate(conn: &Connection) -> anyhow::Result<()> {
+        let version = Self::schema_version(conn)?;
+        if version > MIGRATIONS.len() {
+            warn!(
+                "Cache database version {version} is newer than this version of {CONFIG_APP_NAME} supports"
+            );
         }
-        let image = pix_buf_bytes.pop().unwrap();
-        debug!(
-            "Matches: {:#?}",
-            pix_buf_bytes
-                .iter()
-                .filter(|i| i.favorite)
-                .collect::<Vec<_>>()
-        );
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            debug!("Migrating cache database to version {}", index + 1);
+            migration(conn)?;
+            conn.execute_batch(&format!("PRAGMA user_version = {};", index + 1))?;
+        }
+        Ok(())
+    }
+
+    fn image_file(row: &Row<'_>) -> rusqlite::Result<CacheImageFile> {
+        let (width, height, duration) = (row.get(9)?, row.get(10)?, row.get(11)?);
+        Ok(CacheImageFile {
+            cached_image_path: PathBuf::from(row.get::<usize, String>(0)?),
+            name: row.get(1)?,
+            date: row.get(2)?,
+            created: row.get(14)?,
+            path: PathBuf::from(row.get::<usize, String>(3)?),
+            favorite: row.get::<usize, i32>(4)? > 0,
+            weight: row.get(5)?,
+            size: row.get::<usize, i64>(6)?.try_into().unwrap_or_default(),
+            content_hash: row.get(7)?,
+            media: MediaKind::from_name(&row.get::<usize, String>(8)?).map(|kind| MediaInfo {
+                kind,
+                width,
+                height,
+                duration,
+            }),
+            palette: row
+                .get::<usize, String>(12)?
+                .split(',')
+                .filter_map(parse_hex)
+                .collect(),
+            perceptual_hash: row.get::<usize, Option<i64>>(13)?.map(i64::cast_unsigned),
+        })
+    }
+
+    pub fn select_image_file(&self, path: &Path) -> anyhow::Result<CacheImageFile> {
+        let image = self
+            .connetion
+            .query_row(
+                &format!(
+                    "SELECT {IMAGE_FILE_COLUMNS} FROM imagefile
+                     WHERE path = ?1 AND typeof(image) != 'blob';"
+                ),
+                [&path.to_string_lossy()],
+                Self::image_file,
+            )
+            .optional()?
+            .ok_or_else(|| anyhow!("No result could be found"))?;
         debug!("Image: {image:#?}");
         Ok(image)
     }
 
+    /// The cached entry of `path` when it can be shown as is, without
+    /// generating a thumbnail or writing to the database.
+    pub fn select_fresh_image_file(&self, path: &Path) -> Option<CacheImageFile> {
+        let mut image_file = self.select_image_file(path).ok()?;
+        image_file.path = path.to_path_buf();
+        (image_file.size != 0
+            && image_file.created != 0
+            && image_file.media.is_some()
+            && matches!(image_file.freshness(path), Ok(CacheFreshness::Fresh)))
+        .then_some(image_file)
+    }
+
     pub fn insert_image_file(&self, image_file: &CacheImageFile) -> anyhow::Result<()> {
-        let query = "INSERT INTO ImageFile(image, name, date, path, favorite) VALUES (:image, :name, :date, :path, :favorite);";
-        let favorite = i32::from(image_file.favorite);
+        let media = image_file.media;
         self.connetion.execute(
-            query,
-            (
+            &format!(
+                "INSERT INTO imagefile({IMAGE_FILE_COLUMNS})
+                   VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)
+                 ON CONFLICT(path) DO UPDATE SET image = excluded.image, name = excluded.name,
+                   date = excluded.date, favorite = excluded.favorite, weight = excluded.weight,
+                   size = excluded.size, hash = excluded.hash, kind = excluded.kind,
+                   width = excluded.width, height = excluded.height,
+                   duration = excluded.duration, palette = excluded.palette,
+                   phash = excluded.phash, created = excluded.created;"
+            ),
+            rusqlite::params![
                 &image_file.cached_image_path.to_string_lossy(),
                 &image_file.name,
                 &image_file.date,
                 &image_file.path.to_string_lossy(),
-                &favorite,
+                i32::from(image_file.favorite),
+                &image_file.weight,
+                i64::try_from(image_file.size)?,
+                &image_file.content_hash,
+                media.map_or("", |m| m.kind.as_str()),
+                media.map(|m| m.width).unwrap_or_default(),
+                media.map(|m| m.height).unwrap_or_default(),
+                media.map(|m| m.duration).unwrap_or_default(),
+                image_file
+                    .palette
+                    .iter()
+                    .map(|c| to_hex(*c))
+                    .collect::<Vec<_>>()
+                    .join(","),
+                image_file.perceptual_hash.map(u64::cast_signed),
+                &image_file.created,
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn select_collections(&self) -> anyhow::Result<Vec<String>> {
+        let mut statement = self
+            .connetion
+            .prepare("SELECT name FROM collection ORDER BY name;")?;
+        let collections = statement
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        Ok(collections)
+    }
+
+    pub fn insert_collection(&self, name: &str) -> anyhow::Result<()> {
+        self.connetion.execute(
+            "INSERT OR IGNORE INTO collection(name) VALUES (?1);",
+            [name],
+        )?;
+        Ok(())
+    }
+
+    pub fn delete_collection(&self, name: &str) -> anyhow::Result<()> {
+        self.connetion
+            .execute("DELETE FROM collection WHERE name = ?1;", [name])?;
+        Ok(())
+    }
+
+    pub fn select_collection_images(&self, name: &str) -> anyhow::Result<Vec<PathBuf>> {
+        let mut statement = self
+            .connetion
+            .prepare("SELECT path FROM collection_image WHERE collection = ?1 ORDER BY path;")?;
+        let paths = statement
+            .query_map([name], |row| row.get::<usize, String>(0))?
+            .map(|p| p.map(PathBuf::from))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(paths)
+    }
+
+    pub fn insert_collection_image(&self, name: &str, path: &Path) -> anyhow::Result<()> {
+        self.insert_collection(name)?;
+        self.connetion.execute(
+            "INSERT OR IGNORE INTO collection_image(collection, path) VALUES (?1, ?2);",
+            (name, &path.to_string_lossy()),
+        )?;
+        Ok(())
+    }
+
+    pub fn delete_collection_image(&self, name: &str, path: &Path) -> anyhow::Result<()> {
+        self.connetion.execute(
+            "DELETE FROM collection_image WHERE collection = ?1 AND path = ?2;",
+            (name, &path.to_string_lossy()),
+        )?;
+        Ok(())
+    }
+
+    pub fn select_image_tags(&self) -> anyhow::Result<HashMap<PathBuf, Vec<String>>> {
+        let mut statement = self
+            .connetion
+            .prepare("SELECT path, tag FROM image_tag ORDER BY path, tag;")?;
+        let mut tags = HashMap::<PathBuf, Vec<String>>::new();
+        for row in statement.query_map([], |row| {
+            Ok((row.get::<usize, String>(0)?, row.get::<usize, String>(1)?))
+        })? {
+            let (path, tag) = row?;
+            tags.entry(PathBuf::from(path)).or_default().push(tag);
+        }
+        Ok(tags)
+    }
+
+    pub fn insert_image_tag(&self, path: &Path, tag: &str) -> anyhow::Result<()> {
+        self.connetion.execute(
+            "INSERT OR IGNORE INTO image_tag(path, tag) VALUES (?1, ?2);",
+            (&path.to_string_lossy(), tag),
+        )?;
+        Ok(())
+    }
+
+    pub fn delete_image_tag(&self, path: &Path, tag: &str) -> anyhow::Result<()> {
+        self.connetion.execute(
+            "DELETE FROM image_tag WHERE path = ?1 AND tag = ?2;",
+            (&path.to_string_lossy(), tag),
+        )?;
+        Ok(())
+    }
+
+    fn history_entry(row: &Row<'_>) -> rusqlite::Result<HistoryEntry> {
+        let changer = row.get::<usize, String>(3)?;
+        Ok(HistoryEntry {
+            id: row.get(0)?,
+            monitor: row.get(1)?,
+            path: PathBuf::from(row.get::<usize, String>(2)?),
+            changer: serde_json::from_str(&changer).unwrap_or_default(),
+            applied_at: row.get(4)?,
+            undone: row.get::<usize, i32>(5)? > 0,
+        })
+    }
+
+    /// Records a newly applied wallpaper. Anything that was undone on the
+    /// monitor can no longer be redone afterwards.
+    pub fn insert_history(
+        &self,
+        monitor: &str,
+        path: &Path,
+        changer: &WallpaperChangers,
+    ) -> anyhow::Result<()> {
+        let applied_at = i64::try_from(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())?;
+        let transaction = self.connetion.unchecked_transaction()?;
+        transaction.execute(
+            "DELETE FROM history WHERE monitor = ?1 AND undone = 1;",
+            [monitor],
+        )?;
+        transaction.execute(
+            "INSERT INTO history(monitor, path, changer, applied_at) VALUES (?1, ?2, ?3, ?4);",
+            (
+                monitor,
+                &path.to_string_lossy(),
+                &serde_json::to_string(changer)?,
+                &applied_at,
+            ),
+        )?;
+        transaction.execute(
+            "DELETE FROM history WHERE monitor = ?1 AND id NOT IN
+               (SELECT id FROM history WHERE monitor = ?1 ORDER BY applied_at DESC, id DESC LIMIT ?2);",
+            (monitor, HISTORY_LIMIT),
+        )?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    pub fn select_history(&self, monitor: &str, limit: usize) -> anyhow::Result<Vec<HistoryEntry>> {
+        let mut statement = self.connetion.prepare(&format!(
+            "SELECT {HISTORY_COLUMNS} FROM history WHERE monitor = ?1
+             ORDER BY applied_at DESC, id DESC LIMIT ?2;"
+        ))?;
+        let entries = statement
+            .query_map((monitor, i64::try_from(limit)?), Self::history_entry)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(entries)
+    }
+
+    pub fn undo_history(&self, monitor: &str) -> anyhow::Result<Option<HistoryEntry>> {
+        let transaction = self.connetion.unchecked_transaction()?;
+        let applied = {
+            let mut statement = transaction.prepare(&format!(
+                "SELECT {HISTORY_COLUMNS} FROM history WHERE monitor = ?1 AND undone = 0
+                 ORDER BY applied_at DESC, id DESC LIMIT 2;"
+            ))?;
+            statement
+                .query_map([monitor], Self::history_entry)?
+                .collect::<Result<Vec<_>, _>>()?
+        };
+        let [current, previous] = applied.as_slice() else {
+            return Ok(None);
+        };
+        transaction.execute("UPDATE history SET undone = 1 WHERE id = ?1;", [current.id])?;
+        transaction.commit()?;
+        Ok(Some(previous.clone()))
+    }
+
+    pub fn redo_history(&self, monitor: &str) -> anyhow::Result<Option<HistoryEntry>> {
+        let transaction = self.connetion.unchecked_transaction()?;
+        let Some(next) = transaction
+            .query_row(
+                &format!(
+                    "SELECT {HISTORY_COLUMNS} FROM history WHERE monitor = ?1 AND undone = 1
+                     ORDER BY applied_at, id LIMIT 1;"
+                ),
+                [monitor],
+                Self::history_entry,
+            )
+            .optional()?
+        else {
+            return Ok(None);
+        };
+        transaction.execute("UPDATE history SET undone = 0 WHERE id = ?1;", [next.id])?;
+        transaction.commit()?;
+        Ok(Some(next))
+    }
+
+    /// Every wallpaper that was applied and not undone, oldest first.
+    pub fn select_applied_history(&self) -> anyhow::Result<Vec<HistoryEntry>> {
+        let mut statement = self.connetion.prepare(&format!(
+            "SELECT {HISTORY_COLUMNS} FROM history WHERE undone = 0 ORDER BY applied_at, id;"
+        ))?;
+        let entries = statement
+            .query_map([], Self::history_entry)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(entries)
+    }
+
+    /// Merges an entry recorded elsewhere into the history by its timestamp.
+    /// Entries that are already in the history are skipped so imports can be
+    /// repeated.
+    pub fn import_history(&self, entry: &HistoryEntry) -> anyhow::Result<()> {
+        let transaction = self.connetion.unchecked_transaction()?;
+        transaction.execute(
+            "INSERT INTO history(monitor, path, changer, applied_at)
+             SELECT ?1, ?2, ?3, ?4 WHERE NOT EXISTS
+               (SELECT 1 FROM history WHERE monitor = ?1 AND path = ?2 AND applied_at = ?4);",
+            (
+                &entry.monitor,
+                &entry.path.to_string_lossy(),
+                &serde_json::to_string(&entry.changer)?,
+                &entry.applied_at,
             ),
         )?;
+        transaction.execute(
+            "DELETE FROM history WHERE monitor = ?1 AND id NOT IN
+               (SELECT id FROM history WHERE monitor = ?1 ORDER BY applied_at DESC, id DESC LIMIT ?2);",
+            (&entry.monitor, HISTORY_LIMIT),
+        )?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    pub fn select_image_ratings(&self) -> anyhow::Result<HashMap<PathBuf, (bool, u8)>> {
+        let mut statement = self
+            .connetion
+            .prepare("SELECT path, favorite, weight FROM imagefile;")?;
+        let ratings = statement
+            .query_map([], |row| {
+                Ok((
+                    PathBuf::from(row.get::<usize, String>(0)?),
+                    (
+                        row.get::<usize, i32>(1)? > 0,
+                        row.get::<usize, Option<u8>>(2)?
+                            .unwrap_or(DEFAULT_IMAGE_WEIGHT),
+                    ),
+                ))
+            })?
+            .collect::<Result<HashMap<_, _>, _>>()?;
+        Ok(ratings)
+    }
+
+    /// Sets the favorite flag and rating of `path`. Images that are not cached
+    /// yet get a placeholder entry whose thumbnail is generated when the image
+    /// is next shown in the grid.
+    pub fn upsert_image_rating(
+        &self,
+        path: &Path,
+        favorite: bool,
+        weight: u8,
+    ) -> anyhow::Result<()> {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        self.connetion.execute(
+            "INSERT INTO imagefile(image, name, date, path, favorite, weight)
+               VALUES ('', ?1, 0, ?2, ?3, ?4)
+             ON CONFLICT(path) DO UPDATE SET favorite = excluded.favorite, weight = excluded.weight;",
+            (name, &path.to_string_lossy(), i32::from(favorite), weight),
+        )?;
+        Ok(())
+    }
 
+    /// When each image was last set as a wallpaper by a random pick, in seconds
+    /// since the epoch.
+    pub fn select_image_shown(&self) -> anyhow::Result<HashMap<PathBuf, i64>> {
+        let mut statement = self
+            .connetion
+            .prepare("SELECT path, shown_at FROM image_shown;")?;
+        let shown = statement
+            .query_map([], |row| {
+                Ok((PathBuf::from(row.get::<usize, String>(0)?), row.get(1)?))
+            })?
+            .collect::<Result<HashMap<_, _>, _>>()?;
+        Ok(shown)
+    }
+
+    /// When each image was last set as a wallpaper and how often it appears in
+    /// the history. Images only picked at random count as used once.
+    pub fn select_image_usage(&self) -> anyhow::Result<HashMap<PathBuf, ImageUsage>> {
+        let mut statement = self.connetion.prepare(
+            "SELECT path, MAX(used_at), SUM(uses) FROM
+               (SELECT path, applied_at AS used_at, 1 AS uses FROM history WHERE undone = 0
+                UNION ALL SELECT path, shown_at, 0 FROM image_shown)
+             GROUP BY path;",
+        )?;
+        let usage = statement
+            .query_map([], |row| {
+                Ok((
+                    PathBuf::from(row.get::<usize, String>(0)?),
+                    ImageUsage {
+                        last_used: row.get(1)?,
+                        uses: row.get::<usize, u32>(2)?.max(1),
+                    },
+                ))
+            })?
+            .collect::<Result<HashMap<_, _>, _>>()?;
+        Ok(usage)
+    }
+
+    pub fn insert_image_shown(&self, path: &Path) -> anyhow::Result<()> {
+        let shown_at = i64::try_from(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())?;
+        self.connetion.execute(
+            "INSERT OR REPLACE INTO image_shown(path, shown_at) VALUES (?1, ?2);",
+            (&path.to_string_lossy(), shown_at),
+        )?;
         Ok(())
     }
 
+    pub fn select_shuffle_drawn(&self, pool: &str) -> anyhow::Result<HashSet<PathBuf>> {
+        let mut statement = self
+            .connetion
+            .prepare("SELECT path FROM shuffle_bag WHERE pool = ?1;")?;
+        let paths = statement
+            .query_map([pool], |row| row.get::<usize, String>(0))?
+            .map(|p| p.map(PathBuf::from))
+            .collect::<Result<HashSet<_>, _>>()?;
+        Ok(paths)
+    }
+
+    pub fn insert_shuffle_drawn(&self, pool: &str, path: &Path) -> anyhow::Result<()> {
+        self.connetion.execute(
+            "INSERT OR IGNORE INTO shuffle_bag(pool, path) VALUES (?1, ?2);",
+            (pool, &path.to_string_lossy()),
+        )?;
+        Ok(())
+    }
+
+    pub fn clear_shuffle_bag(&self, pool: &str) -> anyhow::Result<()> {
+        self.connetion
+            .execute("DELETE FROM shuffle_bag WHERE pool = ?1;", [pool])?;
+        Ok(())
+    }
+
+    /// Deletes cache entries whose source image no longer exists, and
+    /// thumbnails in `thumbnail_dir` that no entry refers to. With
+    /// `keep_library`, entries of missing images that are favorited, rated or
+    /// tagged are kept, since their drive may only be unmounted.
+    pub fn collect_garbage(
+        &self,
+        thumbnail_dir: &Path,
+        keep_library: bool,
+    ) -> anyhow::Result<CacheGarbage> {
+        self.collect_garbage_before(
+            thumbnail_dir,
+            SystemTime::now() - GC_THUMBNAIL_GRACE,
+            keep_library,
+        )
+    }
+
+    /// Runs `collect_garbage` unless it already ran recently, keeping the
+    /// library of missing images.
+    pub fn collect_garbage_if_due(
+        &self,
+        thumbnail_dir: &Path,
+    ) -> anyhow::Result<Option<CacheGarbage>> {
+        let last_run = self
+            .connetion
+            .query_row(
+                "SELECT value FROM cache_meta WHERE key = 'last_gc';",
+                [],
+                |row| row.get::<usize, i64>(0),
+            )
+            .optional()?
+            .unwrap_or_default();
+        let now = i64::try_from(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())?;
+        if now - last_run < i64::try_from(GC_INTERVAL.as_secs())? {
+            return Ok(None);
+        }
+        self.collect_garbage(thumbnail_dir, true).map(Some)
+    }
+
+    /// Thumbnails modified after `cutoff` are kept even when unreferenced.
+    fn collect_garbage_before(
+        &self,
+        thumbnail_dir: &Path,
+        cutoff: SystemTime,
+        keep_library: bool,
+    ) -> anyhow::Result<CacheGarbage> {
+        let mut garbage = CacheGarbage::default();
+        let paths = {
+            let mut statement = self.connetion.prepare(
+                "SELECT path FROM imagefile UNION SELECT path FROM image_shown
+                 UNION SELECT path FROM shuffle_bag UNION SELECT path FROM image_tag;",
+            )?;
+            statement
+                .query_map([], |row| row.get::<usize, String>(0))?
+                .collect::<Result<Vec<_>, _>>()?
+        };
+        let transaction = self.connetion.unchecked_transaction()?;
+        for path in paths.iter().filter(|p| !Path::new(p).exists()) {
+            if keep_library {
+                garbage.entries += transaction.execute(
+                    "DELETE FROM imagefile WHERE path = ?1 AND favorite = 0
+                       AND COALESCE(weight, ?2) = ?2
+                       AND NOT EXISTS (SELECT 1 FROM image_tag WHERE path = ?1);",
+                    (path, DEFAULT_IMAGE_WEIGHT),
+                )?;
+            } else {
+                garbage.entries +=
+                    transaction.execute("DELETE FROM imagefile WHERE path = ?1;", [path])?;
+                transaction.execute("DELETE FROM image_tag WHERE path = ?1;", [path])?;
+            }
+            transaction.execute("DELETE FROM image_shown WHERE path = ?1;", [path])?;
+            transaction.execute("DELETE FROM shuffle_bag WHERE path = ?1;", [path])?;
+        }
+        let now = i64::try_from(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())?;
+        transaction.execute(
+            "INSERT OR REPLACE INTO cache_meta(key, value) VALUES ('last_gc', ?1);",
+            [now],
+        )?;
+        transaction.commit()?;
+
+        let referenced = {
+            let mut statement = self.connetion.prepare("SELECT image FROM imagefile;")?;
+            statement
+                .query_map([], |row| row.get::<usize, String>(0))?
+                .map(|p| p.map(PathBuf::from))
+                .collect::<Result<HashSet<_>, _>>()?
+        };
+        for entry in fs::read_dir(thumbnail_dir)?.filter_map(Result::ok) {
+            let thumbnail = entry.path();
+            let is_old = entry
+                .metadata()
+                .and_then(|m| m.modified())
+                .is_ok_and(|modified| modified < cutoff);
+            if thumbnail.extension().is_some_and(|e| e == "png")
+                && thumbnail.is_file()
+                && is_old
+                && !referenced.contains(&thumbnail)
+            {
+                match fs::remove_file(&thumbnail) {
+                    Ok(()) => garbage.thumbnails += 1,
+                    Err(e) => warn!("Failed to delete {}: {e}", thumbnail.display()),
+                }
+            }
+        }
+        debug!("Cache garbage collected: {garbage:?}");
+        Ok(garbage)
+    }
+
     pub fn check_cache(path: &Path) -> anyhow::Result<CacheImageFile> {
         let conn = DatabaseConnection::new()?;
         match conn.select_image_file(path) {
@@ -91,6 +815,22 @@ impl DatabaseConnection {
                 // cache key. Always keep the real filesystem path from the caller so
                 // subsequent file operations (e.g. applying the wallpaper) work.
                 f.path = path.to_path_buf();
+                match f.freshness(path)? {
+                    CacheFreshness::Fresh if f.size != 0 && f.created != 0 && f.media.is_some() => {
+                    }
+                    CacheFreshness::Fresh | CacheFreshness::Touched => {
+                        f.update_metadata(path)?;
+                        if f.media.is_none() {
+                            f.record_properties(path);
+                        }
+                        conn.insert_image_file(&f)?;
+                    }
+                    CacheFreshness::Stale => {
+                        debug!("Regenerating outdated thumbnail of {}", path.display());
+                        f = f.regenerate(path)?;
+                        conn.insert_image_file(&f)?;
+                    }
+                }
                 trace!("{}: {:#?}", TRANSLATION.get_translation("cache-hit"), f);
                 Ok(f)
             }
@@ -130,3 +870,266 @@ impl DatabaseConnection {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn collections_keep_their_images_until_deleted() {
+        let conn = DatabaseConnection::open_in_memory().unwrap();
+        conn.insert_collection("work").unwrap();
+        conn.insert_collection_image("night", Path::new("/w/b.png"))
+            .unwrap();
+        conn.insert_collection_image("night", Path::new("/w/a.png"))
+            .unwrap();
+        conn.insert_collection_image("night", Path::new("/w/a.png"))
+            .unwrap();
+
+        assert_eq!(conn.select_collections().unwrap(), ["night", "work"]);
+        assert_eq!(
+            conn.select_collection_images("night").unwrap(),
+            [PathBuf::from("/w/a.png"), PathBuf::from("/w/b.png")]
+        );
+
+        conn.delete_collection_image("night", Path::new("/w/b.png"))
+            .unwrap();
+        assert_eq!(
+            conn.select_collection_images("night").unwrap(),
+            [PathBuf::from("/w/a.png")]
+        );
+
+        conn.delete_collection("night").unwrap();
+        assert_eq!(conn.select_collections().unwrap(), ["work"]);
+        assert!(conn.select_collection_images("night").unwrap().is_empty());
+    }
+
+    #[test]
+    fn tags_are_kept_per_image() {
+        let conn = DatabaseConnection::open_in_memory().unwrap();
+        conn.insert_image_tag(Path::new("/w/a.png"), "nature")
+            .unwrap();
+        conn.insert_image_tag(Path::new("/w/a.png"), "beach")
+            .unwrap();
+        conn.insert_image_tag(Path::new("/w/a.png"), "beach")
+            .unwrap();
+        conn.insert_image_tag(Path::new("/w/b.png"), "dark")
+            .unwrap();
+        conn.delete_image_tag(Path::new("/w/b.png"), "dark")
+            .unwrap();
+
+        let tags = conn.select_image_tags().unwrap();
+        assert_eq!(tags.len(), 1);
+        assert_eq!(tags[Path::new("/w/a.png")], ["beach", "nature"]);
+    }
+
+    #[test]
+    fn history_steps_back_and_forth_per_monitor() {
+        let conn = DatabaseConnection::open_in_memory().unwrap();
+        let changer = WallpaperChangers::default();
+        let path = |p: &str| PathBuf::from(p);
+        for p in ["/a.png", "/b.png", "/c.png"] {
+            conn.insert_history("DP-1", Path::new(p), &changer).unwrap();
+        }
+        conn.insert_history("DP-2", Path::new("/x.png"), &changer)
+            .unwrap();
+
+        let undo = |m| conn.undo_history(m).unwrap().map(|e| e.path);
+        let redo = |m| conn.redo_history(m).unwrap().map(|e| e.path);
+        assert_eq!(undo("DP-1"), Some(path("/b.png")));
+        assert_eq!(undo("DP-1"), Some(path("/a.png")));
+        assert_eq!(undo("DP-1"), None);
+        assert_eq!(undo("DP-2"), None);
+        assert_eq!(redo("DP-1"), Some(path("/b.png")));
+
+        // Applying something new drops what was left to redo.
+        conn.insert_history("DP-1", Path::new("/d.png"), &changer)
+            .unwrap();
+        assert_eq!(redo("DP-1"), None);
+        let history = conn.select_history("DP-1", 10).unwrap();
+        assert_eq!(
+            history.iter().map(|e| e.path.clone()).collect::<Vec<_>>(),
+            [path("/d.png"), path("/b.png"), path("/a.png")]
+        );
+        assert_eq!(history[0].changer, changer);
+
+        // Undone entries do not count, random picks count once.
+        conn.insert_history("DP-2", Path::new("/b.png"), &changer)
+            .unwrap();
+        conn.insert_image_shown(Path::new("/e.png")).unwrap();
+        let usage = conn.select_image_usage().unwrap();
+        assert_eq!(usage[Path::new("/b.png")].uses, 2);
+        assert!(!usage.contains_key(Path::new("/c.png")));
+        assert_eq!(usage[Path::new("/e.png")].uses, 1);
+    }
+
+    #[test]
+    fn imported_history_is_merged_by_time() {
+        let conn = DatabaseConnection::open_in_memory().unwrap();
+        let changer = WallpaperChangers::default();
+        conn.insert_history("DP-1", Path::new("/new.png"), &changer)
+            .unwrap();
+        let old = HistoryEntry {
+            monitor: "DP-1".to_owned(),
+            path: PathBuf::from("/old.png"),
+            changer: changer.clone(),
+            applied_at: 1_000,
+            ..HistoryEntry::default()
+        };
+        conn.import_history(&old).unwrap();
+        conn.import_history(&old).unwrap();
+
+        let history = conn.select_history("DP-1", 10).unwrap();
+        assert_eq!(
+            history.iter().map(|e| e.path.clone()).collect::<Vec<_>>(),
+            [PathBuf::from("/new.png"), PathBuf::from("/old.png")]
+        );
+        assert_eq!(
+            conn.undo_history("DP-1").unwrap().map(|e| e.path),
+            Some(PathBuf::from("/old.png"))
+        );
+    }
+
+    #[test]
+    fn latest_rating_of_each_image_wins() {
+        let conn = DatabaseConnection::open_in_memory().unwrap();
+        let mut image = CacheImageFile {
+            path: PathBuf::from("/a.png"),
+            weight: DEFAULT_IMAGE_WEIGHT,
+            ..CacheImageFile::default()
+        };
+        conn.insert_image_file(&image).unwrap();
+        image.favorite = true;
+        image.weight = 5;
+        conn.insert_image_file(&image).unwrap();
+        assert_eq!(
+            conn.select_image_ratings().unwrap(),
+            HashMap::from([(PathBuf::from("/a.png"), (true, 5))])
+        );
+        assert_eq!(conn.select_image_file(Path::new("/a.png")).unwrap(), image);
+    }
+
+    #[test]
+    fn garbage_collection_drops_missing_sources_and_orphaned_thumbnails() {
+        let temp = TempDir::new().unwrap();
+        let dir = temp.path();
+        let source = dir.join("kept.jpg");
+        fs::write(&source, b"image").unwrap();
+        let (kept, orphan) = (dir.join("kept.png"), dir.join("orphan.png"));
+        fs::write(&kept, b"").unwrap();
+        fs::write(&orphan, b"").unwrap();
+
+        let conn = DatabaseConnection::open_in_memory().unwrap();
+        for (path, image) in [
+            (source.clone(), kept.clone()),
+            (dir.join("gone.jpg"), kept.clone()),
+        ] {
+            conn.insert_image_file(&CacheImageFile {
+                path,
+                cached_image_path: image,
+                ..CacheImageFile::default()
+            })
+            .unwrap();
+        }
+        conn.insert_image_shown(&dir.join("gone.jpg")).unwrap();
+
+        let garbage = conn
+            .collect_garbage_before(dir, SystemTime::now() + Duration::from_secs(60), false)
+            .unwrap();
+        assert_eq!(
+            garbage,
+            CacheGarbage {
+                entries: 1,
+                thumbnails: 1
+            }
+        );
+        assert!(conn.select_image_file(&source).is_ok());
+        assert!(conn.select_image_shown().unwrap().is_empty());
+        assert!(conn.collect_garbage_if_due(dir).unwrap().is_none());
+    }
+
+    #[test]
+    fn automatic_garbage_collection_keeps_the_library_of_unmounted_drives() {
+        let temp = TempDir::new().unwrap();
+        let dir = temp.path();
+        let conn = DatabaseConnection::open_in_memory().unwrap();
+        let unmounted = |name: &str| dir.join("unmounted").join(name);
+        for (name, favorite) in [
+            ("favorite.png", true),
+            ("tagged.png", false),
+            ("plain.png", false),
+        ] {
+            conn.insert_image_file(&CacheImageFile {
+                path: unmounted(name),
+                favorite,
+                weight: DEFAULT_IMAGE_WEIGHT,
+                ..CacheImageFile::default()
+            })
+            .unwrap();
+        }
+        conn.insert_image_tag(&unmounted("tagged.png"), "nature")
+            .unwrap();
+
+        let garbage = conn.collect_garbage(dir, true).unwrap();
+        assert_eq!(garbage.entries, 1);
+        assert!(
+            conn.select_image_file(&unmounted("favorite.png"))
+                .unwrap()
+                .favorite
+        );
+        assert!(conn.select_image_file(&unmounted("plain.png")).is_err());
+        assert_eq!(conn.select_image_tags().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn new_caches_are_created_at_the_latest_version() {
+        let conn = DatabaseConnection::open_in_memory().unwrap();
+        assert_eq!(
+            DatabaseConnection::schema_version(&conn.connetion).unwrap(),
+            MIGRATIONS.len()
+        );
+        // Running the migrations again is a no-op.
+        DatabaseConnection::migrate(&conn.connetion).unwrap();
+        conn.insert_history("DP-1", Path::new("/a.png"), &WallpaperChangers::default())
+            .unwrap();
+    }
+
+    #[test]
+    fn old_caches_keep_their_newest_row_per_path() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "
+            CREATE TABLE imagefile (image TEXT NOT NULL, name TEXT NOT NULL,
+              date INTEGER NOT NULL, path TEXT NOT NULL, favorite INTEGER NOT NULL);
+            INSERT INTO imagefile VALUES ('/t/1.png', 'a.png', 1, '/a.png', 0);
+            INSERT INTO imagefile VALUES ('/t/2.png', 'a.png', 1, '/a.png', 1);
+            ",
+        )
+        .unwrap();
+        let conn = DatabaseConnection::initialize(conn).unwrap();
+        let image = conn.select_image_file(Path::new("/a.png")).unwrap();
+        assert!(image.favorite);
+        assert_eq!(image.weight, DEFAULT_IMAGE_WEIGHT);
+        conn.insert_image_file(&image).unwrap();
+        assert_eq!(conn.select_image_ratings().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn shuffle_bags_are_kept_per_pool() {
+        let conn = DatabaseConnection::open_in_memory().unwrap();
+        conn.insert_shuffle_drawn("DP-1", Path::new("/a.png"))
+            .unwrap();
+        conn.insert_shuffle_drawn("DP-1", Path::new("/a.png"))
+            .unwrap();
+        conn.insert_shuffle_drawn("DP-2", Path::new("/b.png"))
+            .unwrap();
+        assert_eq!(
+            conn.select_shuffle_drawn("DP-1").unwrap(),
+            HashSet::from([PathBuf::from("/a.png")])
+        );
+        conn.clear_shuffle_bag("DP-1").unwrap();
+        assert!(conn.select_shuffle_drawn("DP-1").unwrap().is_empty());
+        assert_eq!(conn.select_shuffle_drawn("DP-2").unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,266 @@
+use crate::common::CacheImageFile;
+use anyhow::anyhow;
+use image::{DynamicImage, imageops::FilterType};
+use std::{
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+};
+
+/// Hashes at most this many of their 64 bits apart show the same picture.
+pub const DUPLICATE_DISTANCE: u32 = 6;
+
+/// A difference hash: one bit per pixel of a 9x8 grayscale copy of the image,
+/// set when it is brighter than its right neighbour. Scaling and
+/// recompression barely change it.
+#[must_use]
+pub fn perceptual_hash(image: &DynamicImage) -> u64 {
+    let small = image.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    let mut hash = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            let brighter = small.get_pixel(x, y)[0] > small.get_pixel(x + 1, y)[0];
+            hash = (hash << 1) | u64::from(brighter);
+        }
+    }
+    hash
+}
+
+/// Hashes with fewer set or unset bits than this come from flat or smoothly
+/// shaded images, which all hash alike whatever they show.
+const MIN_HASH_DETAIL: u32 = 8;
+
+/// Aspect ratios further apart than this, relative to the larger one, are
+/// different crops rather than copies.
+const MAX_ASPECT_DIFFERENCE: f64 = 0.02;
+
+fn pixels(image: &CacheImageFile) -> u64 {
+    image
+        .media
+        .map_or(0, |m| u64::from(m.width) * u64::from(m.height))
+}
+
+fn is_detailed(hash: u64) -> bool {
+    (MIN_HASH_DETAIL..=64 - MIN_HASH_DETAIL).contains(&hash.count_ones())
+}
+
+/// Whether `image` is a copy of `keeper`: their hashes are close and their
+/// shapes match, so a close hash by chance does not get an image trashed.
+fn is_copy_of(image: &CacheImageFile, keeper: &CacheImageFile) -> bool {
+    let (Some(hash), Some(keeper_hash)) = (image.perceptual_hash, keeper.perceptual_hash) else {
+        return false;
+    };
+    let ratio = |i: &CacheImageFile| i.media.and_then(|m| m.aspect_ratio());
+    let (Some(ratio), Some(keeper_ratio)) = (ratio(image), ratio(keeper)) else {
+        return false;
+    };
+    (hash ^ keeper_hash).count_ones() <= DUPLICATE_DISTANCE
+        && (ratio - keeper_ratio).abs() <= MAX_ASPECT_DIFFERENCE * ratio.max(keeper_ratio)
+}
+
+/// Groups images that are copies of the same picture. Each group starts with
+/// the image to keep, the one with the highest resolution and then the
+/// largest file, and every other image in it is compared against that one.
+/// Images without a hash or with too little detail to tell apart are left
+/// out.
+#[must_use]
+pub fn find_duplicates(images: &[CacheImageFile]) -> Vec<Vec<CacheImageFile>> {
+    let mut candidates = images
+        .iter()
+        .filter(|i| i.perceptual_hash.is_some_and(is_detailed))
+        .collect::<Vec<_>>();
+    candidates.sort_by(|a, b| {
+        pixels(b)
+            .cmp(&pixels(a))
+            .then(b.size.cmp(&a.size))
+            .then(a.path.cmp(&b.path))
+    });
+    let mut grouped = vec![false; candidates.len()];
+    let mut groups = vec![];
+    for (k, keeper) in candidates.iter().enumerate() {
+        if grouped[k] {
+            continue;
+        }
+        let mut group = vec![(*keeper).clone()];
+        for (i, image) in candidates.iter().enumerate().skip(k + 1) {
+            if !grouped[i] && is_copy_of(image, keeper) {
+                grouped[i] = true;
+                group.push((*image).clone());
+            }
+        }
+        if group.len() > 1 {
+            groups.push(group);
+        }
+    }
+    groups
+}
+
+/// Percent-encodes a path for the `Path` key of a `.trashinfo` file.
+fn encode_trash_path(path: &Path) -> String {
+    path.to_string_lossy()
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                char::from(b).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+/// Moves `path` into the trash directory `trash` following the freedesktop
+/// trash specification, so file managers can restore it. A symlink is
+/// trashed itself, not the file it points to.
+fn move_to_trash_dir(path: &Path, trash: &Path) -> anyhow::Result<PathBuf> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("{} has no file name", path.display()))?;
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let path = fs::canonicalize(parent)?.join(file_name);
+    let (files, info) = (trash.join("files"), trash.join("info"));
+    fs::create_dir_all(&files)?;
+    fs::create_dir_all(&info)?;
+    let name = file_name.to_string_lossy().into_owned();
+    // Creating the info file first reserves the name in the trash.
+    let (trashed, info_file, mut info_writer) = (1..)
+        .map(|n| match n {
+            1 => name.clone(),
+            n => format!("{n}.{name}"),
+        })
+        .find_map(|candidate| {
+            let trashed = files.join(&candidate);
+            if trashed.exists() {
+                return None;
+            }
+            let info_file = info.join(format!("{candidate}.trashinfo"));
+            OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&info_file)
+                .ok()
+                .map(|writer| (trashed, info_file, writer))
+        })
+        .ok_or_else(|| anyhow!("Failed to find a free name in the trash"))?;
+    let result = (|| -> anyhow::Result<()> {
+        write!(
+            info_writer,
+            "[Trash Info]\nPath={}\nDeletionDate={}\n",
+            encode_trash_path(&path),
+            chrono::Local::now().format("%Y-%m-%dT%H:%M:%S")
+        )?;
+        match fs::rename(&path, &trashed) {
+            Err(e) if e.kind() == ErrorKind::CrossesDevices => {
+                if fs::symlink_metadata(&path)?.is_symlink() {
+                    symlink(fs::read_link(&path)?, &trashed)?;
+                } else {
+                    fs::copy(&path, &trashed)?;
+                }
+                fs::remove_file(&path)?;
+            }
+            result => result?,
+        }
+        Ok(())
+    })();
+    if let Err(e) = result {
+        let _ = fs::remove_file(&info_file);
+        return Err(e);
+    }
+    Ok(trashed)
+}
+
+/// Moves `path` to the trash of the user, from where it can be restored.
+pub fn move_to_trash(path: &Path) -> anyhow::Result<()> {
+    let trash = xdg::BaseDirectories::new()
+        .get_data_home()
+        .ok_or_else(|| anyhow!("Failed to get the data directory"))?
+        .join("Trash");
+    move_to_trash_dir(path, &trash)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::MediaInfo;
+    use image::{Rgb, RgbImage};
+    use tempfile::TempDir;
+
+    #[test]
+    fn resized_copies_are_grouped_largest_first() {
+        // Blocks at the same relative positions whatever the size.
+        let blocks = |width, height, columns: u32, rows: u32| {
+            DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+                let block = x * columns / width + y * rows / height;
+                Rgb([(block % 3 * 120) as u8, (block % 5 * 60) as u8, 90])
+            }))
+        };
+        let picture = |width, height| blocks(width, height, 7, 5);
+        let other = blocks(400, 225, 4, 9);
+        let entry = |path: &str, image: &DynamicImage| CacheImageFile {
+            path: PathBuf::from(path),
+            perceptual_hash: Some(perceptual_hash(image)),
+            media: Some(MediaInfo {
+                width: image.width(),
+                height: image.height(),
+                ..MediaInfo::default()
+            }),
+            ..CacheImageFile::default()
+        };
+        let flat = |width, height| DynamicImage::ImageRgb8(RgbImage::new(width, height));
+        let images = [
+            entry("/w/small.jpg", &picture(400, 225)),
+            entry("/w/other.png", &other),
+            entry("/w/large.png", &picture(1920, 1080)),
+            // Same blocks in another shape, and flat images that hash alike.
+            entry("/w/square.png", &picture(400, 400)),
+            entry("/w/black.png", &flat(400, 225)),
+            entry("/w/black-large.png", &flat(1920, 1080)),
+            CacheImageFile::default(),
+        ];
+        assert!(
+            (images[3].perceptual_hash.unwrap() ^ images[0].perceptual_hash.unwrap()).count_ones()
+                <= DUPLICATE_DISTANCE
+        );
+
+        let groups = find_duplicates(&images);
+        assert_eq!(groups.len(), 1);
+        let paths = groups[0].iter().map(|i| i.path.clone()).collect::<Vec<_>>();
+        assert_eq!(paths, ["/w/large.png", "/w/small.jpg"].map(PathBuf::from));
+    }
+
+    #[test]
+    fn trashed_files_can_be_restored() {
+        let temp = TempDir::new().unwrap();
+        let dir = temp.path();
+        let trash = dir.join("Trash");
+        for _ in 0..2 {
+            fs::write(dir.join("a b.png"), b"a").unwrap();
+            move_to_trash_dir(&dir.join("a b.png"), &trash).unwrap();
+        }
+
+        assert!(!dir.join("a b.png").exists());
+        assert!(trash.join("files/a b.png").is_file());
+        assert!(trash.join("files/2.a b.png").is_file());
+        let info = fs::read_to_string(trash.join("info/a b.png.trashinfo")).unwrap();
+        assert!(info.starts_with("[Trash Info]\nPath=/"));
+        assert!(info.contains("/a%20b.png\nDeletionDate="));
+    }
+
+    #[test]
+    fn trashing_a_symlink_keeps_its_target() {
+        let temp = TempDir::new().unwrap();
+        let dir = temp.path();
+        let (target, link) = (dir.join("target.png"), dir.join("link.png"));
+        fs::write(&target, b"a").unwrap();
+        symlink(&target, &link).unwrap();
+
+        let trashed = move_to_trash_dir(&link, &dir.join("Trash")).unwrap();
+        assert!(target.is_file());
+        assert!(fs::symlink_metadata(&link).is_err());
+        assert_eq!(fs::read_link(trashed).unwrap(), target);
+    }
+}
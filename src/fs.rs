@@ -1,30 +1,195 @@
-use crate::{
-    app_state::SortBy, common::sort_by_sort_dropdown_string, wallpaper_changers::WallpaperChangers,
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
 };
-use std::path::PathBuf;
-
-pub fn get_image_files(
-    path: &str,
-    sort_dropdown: &SortBy,
-    invert_sort_switch_state: bool,
-) -> Vec<PathBuf> {
-    let mut files = walkdir::WalkDir::new(path)
-        .follow_links(true)
-        .follow_root_links(true)
-        .into_iter()
-        .filter_map(std::result::Result::ok)
-        .filter(|f| f.file_type().is_file())
-        .map(|d| d.path().to_path_buf())
-        .filter(|p| {
-            WallpaperChangers::all_accepted_formats().iter().any(|f| {
-                f == p
-                    .extension()
-                    .unwrap_or_default()
-                    .to_str()
-                    .unwrap_or_default()
+
+/// A folder wallpapers are drawn from, with rules for which of its files count.
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct WallpaperSource {
+    pub path: PathBuf,
+    /// How many levels of subfolders to look into. `Some(0)` only takes the
+    /// images directly inside `path`, `None` walks the whole tree.
+    pub max_depth: Option<usize>,
+    /// Glob patterns matched against paths relative to `path`, e.g.
+    /// `landscape/**`. Without any, every image is included.
+    pub include: Vec<String>,
+    /// Glob patterns for images and folders to leave out, e.g. `**/raw`.
+    pub exclude: Vec<String>,
+    /// Also take files and folders whose names start with a dot.
+    pub include_hidden: bool,
+}
+
+fn build_glob_set(patterns: &[String]) -> GlobSet {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        match Glob::new(pattern) {
+            Ok(glob) => {
+                builder.add(glob);
+            }
+            Err(e) => warn!("Ignoring invalid glob pattern \"{pattern}\": {e}"),
+        }
+    }
+    builder.build().unwrap_or_else(|_| GlobSet::empty())
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|n| n.to_string_lossy().starts_with('.'))
+}
+
+pub struct SourceFilter<'a> {
+    source: &'a WallpaperSource,
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+    formats: &'a [String],
+}
+
+impl SourceFilter<'_> {
+    fn skips_dir(&self, relative: &Path) -> bool {
+        (!self.source.include_hidden && is_hidden(relative)) || self.exclude.is_match(relative)
+    }
+
+    /// Whether `path` is an image this source takes. The file is not read.
+    #[must_use]
+    pub fn accepts(&self, path: &Path) -> bool {
+        let Ok(relative) = path.strip_prefix(&self.source.path) else {
+            return false;
+        };
+        let depth = relative.components().count();
+        if depth == 0 || self.source.max_depth.is_some_and(|d| depth > d + 1) {
+            return false;
+        }
+        let extension = relative
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        self.formats.contains(&extension)
+            && !relative
+                .ancestors()
+                .skip(1)
+                .filter(|a| !a.as_os_str().is_empty())
+                .any(|a| self.skips_dir(a))
+            && (self.source.include_hidden || !is_hidden(relative))
+            && self.include.as_ref().is_none_or(|i| i.is_match(relative))
+            && !self.exclude.is_match(relative)
+    }
+}
+
+impl WallpaperSource {
+    #[must_use]
+    pub fn new(path: PathBuf) -> WallpaperSource {
+        WallpaperSource {
+            path,
+            ..WallpaperSource::default()
+        }
+    }
+
+    /// Compiles the rules for images with one of the given extensions.
+    /// Invalid glob patterns are logged and left out.
+    #[must_use]
+    pub fn filter<'a>(&'a self, formats: &'a [String]) -> SourceFilter<'a> {
+        SourceFilter {
+            source: self,
+            include: (!self.include.is_empty()).then(|| build_glob_set(&self.include)),
+            exclude: build_glob_set(&self.exclude),
+            formats,
+        }
+    }
+
+    #[must_use]
+    pub fn image_files(&self, formats: &[String]) -> Vec<PathBuf> {
+        let filter = self.filter(formats);
+        let mut walk = walkdir::WalkDir::new(&self.path)
+            .follow_links(true)
+            .follow_root_links(true);
+        if let Some(depth) = self.max_depth {
+            walk = walk.max_depth(depth + 1);
+        }
+        walk.into_iter()
+            .filter_entry(|e| {
+                !e.file_type().is_dir()
+                    || e.depth() == 0
+                    || e.path()
+                        .strip_prefix(&self.path)
+                        .is_ok_and(|r| !filter.skips_dir(r))
             })
-        })
-        .collect::<Vec<_>>();
-    sort_by_sort_dropdown_string(&mut files, sort_dropdown, invert_sort_switch_state);
-    files
+            .filter_map(std::result::Result::ok)
+            .filter(|e| e.file_type().is_file())
+            .map(walkdir::DirEntry::into_path)
+            .filter(|p| filter.accepts(p))
+            .collect()
+    }
+}
+
+/// The images of all `sources` with one of the given extensions, in source
+/// order. Images reachable from several sources are listed once.
+#[must_use]
+pub fn get_image_files(sources: &[WallpaperSource], formats: &[String]) -> Vec<PathBuf> {
+    let mut seen = HashSet::new();
+    sources
+        .iter()
+        .flat_map(|s| s.image_files(formats))
+        .filter(|p| seen.insert(p.clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn sources_apply_depth_globs_and_hidden_rules() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path();
+        for file in [
+            "a.png",
+            "b.txt",
+            ".hidden.png",
+            "day/c.png",
+            "day/raw/d.png",
+            "day/deep/e.jpg",
+            ".cache/f.png",
+        ] {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"").unwrap();
+        }
+        let formats = ["png".to_owned(), "jpg".to_owned()];
+        let files = |source: &WallpaperSource| {
+            let mut files = source
+                .image_files(&formats)
+                .into_iter()
+                .map(|p| p.strip_prefix(root).unwrap().to_string_lossy().into_owned())
+                .collect::<Vec<_>>();
+            files.sort();
+            files
+        };
+
+        let mut source = WallpaperSource::new(root.to_path_buf());
+        assert_eq!(
+            files(&source),
+            ["a.png", "day/c.png", "day/deep/e.jpg", "day/raw/d.png"]
+        );
+        source.exclude = vec!["**/raw".to_owned()];
+        source.include = vec!["*.png".to_owned()];
+        assert_eq!(files(&source), ["a.png", "day/c.png"]);
+        source.max_depth = Some(0);
+        source.include_hidden = true;
+        assert_eq!(files(&source), [".hidden.png", "a.png"]);
+
+        let filter = source.filter(&formats);
+        assert!(filter.accepts(&root.join("a.png")));
+        assert!(!filter.accepts(&root.join("day/c.png")));
+        assert!(!filter.accepts(Path::new("/elsewhere/a.png")));
+
+        let other = WallpaperSource::new(root.join("day"));
+        assert_eq!(get_image_files(&[source.clone(), other], &formats).len(), 5);
+    }
 }
@@ -0,0 +1,246 @@
+use crate::{app_state::Messages, common::get_runtime_dir};
+use futures::SinkExt;
+use iced::Subscription;
+use log::{debug, error, warn};
+use std::{
+    fs,
+    io::{BufRead, BufReader, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, mpsc},
+    thread,
+    time::Duration,
+};
+
+const IPC_SOCKET_NAME: &str = "waytrogen.sock";
+const IPC_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IpcCommand {
+    Set { monitor: String, path: PathBuf },
+    Next(Option<String>),
+    Random,
+    Query,
+    Reload,
+}
+
+impl IpcCommand {
+    pub fn parse(line: &str) -> anyhow::Result<IpcCommand> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        let mut fields = line.splitn(3, ' ');
+        let command = match (fields.next(), fields.next(), fields.next()) {
+            (Some("set"), Some(monitor), Some(path)) if !monitor.is_empty() && !path.is_empty() => {
+                IpcCommand::Set {
+                    monitor: monitor.to_owned(),
+                    path: PathBuf::from(path),
+                }
+            }
+            (Some("next"), None, None) => IpcCommand::Next(None),
+            (Some("next"), Some(monitor), None) if !monitor.is_empty() => {
+                IpcCommand::Next(Some(monitor.to_owned()))
+            }
+            (Some("random"), None, None) => IpcCommand::Random,
+            (Some("query"), None, None) => IpcCommand::Query,
+            (Some("reload"), None, None) => IpcCommand::Reload,
+            _ => anyhow::bail!("unknown command \"{line}\""),
+        };
+        Ok(command)
+    }
+}
+
+/// A command received over the socket together with the channel its reply is
+/// written back on. The reply is a single line: `OK`, `ERROR: <reason>` or the
+/// JSON answer to `query`.
+#[derive(Clone, Debug)]
+pub struct IpcRequest {
+    pub command: IpcCommand,
+    responder: Arc<Mutex<Option<mpsc::Sender<String>>>>,
+}
+
+impl IpcRequest {
+    pub fn respond(&self, response: anyhow::Result<String>) {
+        let response = match response {
+            Ok(r) => r,
+            Err(e) => format!("ERROR: {e}"),
+        };
+        if let Ok(mut responder) = self.responder.lock()
+            && let Some(sender) = responder.take()
+        {
+            let _ = sender.send(response);
+        }
+    }
+}
+
+pub fn ipc_socket_path() -> anyhow::Result<PathBuf> {
+    Ok(get_runtime_dir()?.join(IPC_SOCKET_NAME))
+}
+
+fn bind_socket(socket: &Path) -> anyhow::Result<UnixListener> {
+    if socket.exists() {
+        if UnixStream::connect(socket).is_ok() {
+            anyhow::bail!(
+                "another waytrogen instance is listening on {}",
+                socket.display()
+            );
+        }
+        fs::remove_file(socket)?;
+    }
+    Ok(UnixListener::bind(socket)?)
+}
+
+fn handle_connection(
+    stream: UnixStream,
+    handler: &(impl Fn(IpcRequest) + Send + Sync),
+) -> anyhow::Result<()> {
+    stream.set_read_timeout(Some(IPC_TIMEOUT))?;
+    stream.set_write_timeout(Some(IPC_TIMEOUT))?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let response = match IpcCommand::parse(&line) {
+        Ok(command) => {
+            debug!("IPC command received: {command:?}");
+            let (sender, receiver) = mpsc::channel();
+            handler(IpcRequest {
+                command,
+                responder: Arc::new(Mutex::new(Some(sender))),
+            });
+            receiver
+                .recv_timeout(IPC_TIMEOUT)
+                .unwrap_or_else(|_| "ERROR: request timed out".to_owned())
+        }
+        Err(e) => format!("ERROR: {e}"),
+    };
+    let mut stream = stream;
+    stream.write_all(response.as_bytes())?;
+    stream.write_all(b"\n")?;
+    Ok(())
+}
+
+fn serve_at(
+    listener: UnixListener,
+    handler: impl Fn(IpcRequest) + Send + Sync + 'static,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(e) = handle_connection(stream, &handler) {
+                        warn!("Failed to answer IPC request: {e}");
+                    }
+                }
+                Err(e) => warn!("Failed to accept IPC connection: {e}"),
+            }
+        }
+    })
+}
+
+/// Listens on the waytrogen socket in a background thread, passing every
+/// command to `handler`.
+pub fn serve(
+    handler: impl Fn(IpcRequest) + Send + Sync + 'static,
+) -> anyhow::Result<thread::JoinHandle<()>> {
+    let socket = ipc_socket_path()?;
+    let listener = bind_socket(&socket)?;
+    debug!("Listening for IPC commands on {}", socket.display());
+    Ok(serve_at(listener, handler))
+}
+
+fn ipc_request_at(socket: &Path, command: &str) -> anyhow::Result<String> {
+    if command.contains(['\n', '\r']) {
+        anyhow::bail!("IPC commands cannot contain newlines");
+    }
+    let mut stream = UnixStream::connect(socket)?;
+    stream.set_read_timeout(Some(IPC_TIMEOUT * 2))?;
+    stream.set_write_timeout(Some(IPC_TIMEOUT))?;
+    stream.write_all(command.as_bytes())?;
+    stream.write_all(b"\n")?;
+
+    let mut response = String::new();
+    BufReader::new(stream).read_line(&mut response)?;
+    let response = response.trim_end_matches(['\r', '\n']);
+    if response.is_empty() {
+        anyhow::bail!("waytrogen returned an empty IPC response");
+    }
+    if let Some(error) = response.strip_prefix("ERROR:") {
+        anyhow::bail!("{}", error.trim());
+    }
+    Ok(response.to_owned())
+}
+
+pub fn send_ipc_command(command: &str) -> anyhow::Result<()> {
+    let socket = ipc_socket_path()?;
+    match ipc_request_at(&socket, command) {
+        Ok(response) => {
+            println!("{response}");
+            Ok(())
+        }
+        Err(e) => {
+            error!("IPC request failed: {e}");
+            Err(e)
+        }
+    }
+}
+
+fn ipc_stream() -> impl futures::Stream<Item = Messages> {
+    iced::stream::channel(16, async |output| {
+        let handler = move |request: IpcRequest| {
+            let mut output = output.clone();
+            if let Err(e) =
+                futures::executor::block_on(output.send(Messages::IpcCommandReceived(request)))
+            {
+                warn!("Failed to forward IPC command to the GUI: {e}");
+            }
+        };
+        if let Err(e) = serve(handler) {
+            warn!("IPC socket is unavailable: {e}");
+        }
+        futures::future::pending::<()>().await;
+    })
+}
+
+pub fn ipc_subscription() -> Subscription<Messages> {
+    Subscription::run(ipc_stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn commands_are_parsed_with_paths_containing_spaces() {
+        assert_eq!(
+            IpcCommand::parse("set DP-1 /wallpapers/space name.png\n").unwrap(),
+            IpcCommand::Set {
+                monitor: "DP-1".to_owned(),
+                path: PathBuf::from("/wallpapers/space name.png"),
+            }
+        );
+        assert_eq!(IpcCommand::parse("next").unwrap(), IpcCommand::Next(None));
+        assert_eq!(
+            IpcCommand::parse("next HDMI-A-1").unwrap(),
+            IpcCommand::Next(Some("HDMI-A-1".to_owned()))
+        );
+        assert_eq!(IpcCommand::parse("query").unwrap(), IpcCommand::Query);
+        assert!(IpcCommand::parse("set DP-1").is_err());
+        assert!(IpcCommand::parse("random now").is_err());
+    }
+
+    #[test]
+    fn server_answers_requests_and_reports_errors() {
+        let root = TempDir::new().unwrap();
+        let socket = root.path().join(IPC_SOCKET_NAME);
+        let listener = bind_socket(&socket).unwrap();
+        serve_at(listener, |request| match request.command {
+            IpcCommand::Query => request.respond(Ok("[]".to_owned())),
+            _ => request.respond(Err(anyhow::anyhow!("not supported"))),
+        });
+
+        assert_eq!(ipc_request_at(&socket, "query").unwrap(), "[]");
+        let error = ipc_request_at(&socket, "random").unwrap_err();
+        assert!(error.to_string().contains("not supported"));
+        let error = ipc_request_at(&socket, "dance").unwrap_err();
+        assert!(error.to_string().contains("unknown command"));
+        assert!(bind_socket(&socket).is_err());
+    }
+}
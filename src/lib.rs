@@ -2,10 +2,23 @@ pub mod app_state;
 pub mod changers;
 pub mod cli;
 pub mod common;
+pub mod daemon;
 pub mod database;
+pub mod duplicates;
 pub mod fs;
+pub mod ipc;
+pub mod library;
 pub mod locale;
+pub mod media;
 pub mod monitors;
+pub mod palette;
+pub mod query;
+pub mod schedule;
+pub mod shuffle;
+pub mod sort;
+pub mod span;
 pub mod theme;
+pub mod thumbnail;
 pub mod wallpaper_changers;
+pub mod watcher;
 pub mod cli_parser;
@@ -0,0 +1,266 @@
+use crate::{
+    common::{DEFAULT_IMAGE_WEIGHT, HistoryEntry},
+    database::DatabaseConnection,
+    query::normalize_tag,
+    wallpaper_changers::WallpaperChangers,
+};
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct LibraryImage {
+    pub path: PathBuf,
+    pub favorite: bool,
+    pub weight: u8,
+    /// Missing in libraries exported before images could be tagged.
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct LibraryCollection {
+    pub name: String,
+    pub images: Vec<PathBuf>,
+}
+
+/// A wallpaper applied on a monitor, without the undo state of the GUI.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct LibraryHistoryEntry {
+    pub monitor: String,
+    pub path: PathBuf,
+    pub changer: WallpaperChangers,
+    pub applied_at: i64,
+}
+
+/// The per-image data users curate by hand, as opposed to thumbnails that can
+/// always be generated again.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Library {
+    pub images: Vec<LibraryImage>,
+    pub collections: Vec<LibraryCollection>,
+    pub history: Vec<LibraryHistoryEntry>,
+}
+
+impl Library {
+    /// Reads the favorites, ratings, tags, collections and history out of the
+    /// cache database.
+    pub fn export(conn: &DatabaseConnection) -> anyhow::Result<Library> {
+        let mut tags = conn.select_image_tags()?;
+        let ratings = conn.select_image_ratings()?;
+        let paths = ratings
+            .keys()
+            .chain(tags.keys())
+            .cloned()
+            .collect::<HashSet<_>>();
+        let mut images = paths
+            .into_iter()
+            .map(|path| {
+                let (favorite, weight) = ratings
+                    .get(&path)
+                    .copied()
+                    .unwrap_or((false, DEFAULT_IMAGE_WEIGHT));
+                LibraryImage {
+                    tags: tags.remove(&path).unwrap_or_default(),
+                    path,
+                    favorite,
+                    weight,
+                }
+            })
+            .filter(|i| i.favorite || i.weight != DEFAULT_IMAGE_WEIGHT || !i.tags.is_empty())
+            .collect::<Vec<_>>();
+        images.sort_by(|a, b| a.path.cmp(&b.path));
+        let collections = conn
+            .select_collections()?
+            .into_iter()
+            .map(|name| {
+                let images = conn.select_collection_images(&name)?;
+                Ok(LibraryCollection { name, images })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let history = conn
+            .select_applied_history()?
+            .into_iter()
+            .map(|e| LibraryHistoryEntry {
+                monitor: e.monitor,
+                path: e.path,
+                changer: e.changer,
+                applied_at: e.applied_at,
+            })
+            .collect();
+        Ok(Library {
+            images,
+            collections,
+            history,
+        })
+    }
+
+    /// Writes the library into the cache database, replacing the ratings of the
+    /// images it lists and adding to existing tags, collections and history.
+    pub fn import(&self, conn: &DatabaseConnection) -> anyhow::Result<()> {
+        for image in &self.images {
+            conn.upsert_image_rating(&image.path, image.favorite, image.weight)?;
+            for tag in &image.tags {
+                conn.insert_image_tag(&image.path, &normalize_tag(tag))?;
+            }
+        }
+        for collection in &self.collections {
+            conn.insert_collection(&collection.name)?;
+            for image in &collection.images {
+                conn.insert_collection_image(&collection.name, image)?;
+            }
+        }
+        let mut history = self.history.iter().collect::<Vec<_>>();
+        history.sort_by_key(|e| e.applied_at);
+        for entry in history {
+            conn.import_history(&HistoryEntry {
+                monitor: entry.monitor.clone(),
+                path: entry.path.clone(),
+                changer: entry.changer.clone(),
+                applied_at: entry.applied_at,
+                ..Default::default()
+            })?;
+        }
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.images.is_empty() && self.collections.is_empty() && self.history.is_empty()
+    }
+
+    fn map_paths(mut self, f: impl Fn(&Path) -> PathBuf) -> Library {
+        for image in &mut self.images {
+            image.path = f(&image.path);
+        }
+        for collection in &mut self.collections {
+            for image in &mut collection.images {
+                *image = f(image);
+            }
+        }
+        for entry in &mut self.history {
+            entry.path = f(&entry.path);
+        }
+        self
+    }
+
+    /// Makes paths inside `root` relative to it. Images outside of it keep
+    /// their absolute path.
+    #[must_use]
+    pub fn relative_to(self, root: &Path) -> Library {
+        self.map_paths(|p| p.strip_prefix(root).unwrap_or(p).to_path_buf())
+    }
+
+    #[must_use]
+    pub fn resolve(self, root: &Path) -> Library {
+        self.map_paths(|p| root.join(p))
+    }
+
+    fn has_relative_paths(&self) -> bool {
+        self.images.iter().any(|i| i.path.is_relative())
+            || self
+                .collections
+                .iter()
+                .flat_map(|c| &c.images)
+                .any(|p| p.is_relative())
+            || self.history.iter().any(|e| e.path.is_relative())
+    }
+
+    /// Writes the library as JSON with paths relative to `root`, so it can be
+    /// imported on another machine or after the folder moved.
+    pub fn write_to_file(self, file: &Path, root: Option<&Path>) -> anyhow::Result<()> {
+        let library = match root {
+            Some(root) => self.relative_to(root),
+            None => self,
+        };
+        fs::write(file, serde_json::to_string_pretty(&library)?)?;
+        Ok(())
+    }
+
+    /// Reads a library written by [`Library::write_to_file`], resolving
+    /// relative paths against `root`.
+    pub fn read_from_file(file: &Path, root: Option<&Path>) -> anyhow::Result<Library> {
+        let library = serde_json::from_str::<Library>(&fs::read_to_string(file)?)?;
+        match root {
+            Some(root) => Ok(library.resolve(root)),
+            None if library.has_relative_paths() => Err(anyhow!(
+                "{} has relative paths but no wallpaper folder is set",
+                file.display()
+            )),
+            None => Ok(library),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn library_survives_a_fresh_cache() {
+        let conn = DatabaseConnection::open_in_memory().unwrap();
+        conn.upsert_image_rating(Path::new("/w/a.png"), true, DEFAULT_IMAGE_WEIGHT)
+            .unwrap();
+        conn.upsert_image_rating(Path::new("/w/b.png"), false, 5)
+            .unwrap();
+        conn.upsert_image_rating(Path::new("/w/c.png"), false, DEFAULT_IMAGE_WEIGHT)
+            .unwrap();
+        conn.insert_image_tag(Path::new("/w/d.png"), "beach")
+            .unwrap();
+        conn.insert_collection_image("night", Path::new("/w/b.png"))
+            .unwrap();
+        let library = Library::export(&conn).unwrap();
+        assert_eq!(library.images.len(), 3);
+        assert_eq!(library.images[2].tags, ["beach"]);
+
+        let fresh = DatabaseConnection::open_in_memory().unwrap();
+        library.import(&fresh).unwrap();
+        assert_eq!(Library::export(&fresh).unwrap(), library);
+        let image = fresh.select_image_file(Path::new("/w/a.png")).unwrap();
+        assert!(image.favorite);
+        assert_eq!(image.name, "a.png");
+    }
+
+    #[test]
+    fn library_moves_to_a_new_wallpaper_folder() {
+        let conn = DatabaseConnection::open_in_memory().unwrap();
+        let changer = WallpaperChangers::default();
+        conn.upsert_image_rating(Path::new("/old/a.png"), true, 4)
+            .unwrap();
+        conn.insert_collection_image("night", Path::new("/elsewhere/b.png"))
+            .unwrap();
+        for p in ["/old/a.png", "/old/sub/c.png"] {
+            conn.insert_history("DP-1", Path::new(p), &changer).unwrap();
+        }
+        conn.undo_history("DP-1").unwrap();
+
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("library.json");
+        Library::export(&conn)
+            .unwrap()
+            .write_to_file(&file, Some(Path::new("/old")))
+            .unwrap();
+        assert!(Library::read_from_file(&file, None).is_err());
+        let library = Library::read_from_file(&file, Some(Path::new("/new"))).unwrap();
+
+        assert_eq!(library.images[0].path, PathBuf::from("/new/a.png"));
+        assert_eq!(
+            library.collections[0].images,
+            [PathBuf::from("/elsewhere/b.png")]
+        );
+        assert_eq!(library.history.len(), 1);
+
+        let fresh = DatabaseConnection::open_in_memory().unwrap();
+        library.import(&fresh).unwrap();
+        library.import(&fresh).unwrap();
+        let history = fresh.select_history("DP-1", 10).unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].path, PathBuf::from("/new/a.png"));
+    }
+}
@@ -4,10 +4,13 @@ use std::{thread::sleep, time::Duration};
 use waytrogen::{
     app_state::AppState,
     cli::{
-	cycle_next_wallpaper, delete_image_cache, print_app_version, print_wallpaper_state,
-        restore_wallpapers, set_random_wallpapers,
+        collect_cache_garbage, cycle_next_wallpaper, delete_app_data, export_library,
+        find_duplicate_wallpapers, import_library, print_app_version, print_wallpaper_state,
+        restore_wallpapers, set_previous_wallpaper, set_random_wallpapers, set_spanned_wallpaper,
     },
-    cli_parser::Cli
+    cli_parser::Cli,
+    daemon::run_daemon,
+    ipc::send_ipc_command,
 };
 use clap::Parser;
 
@@ -27,21 +30,21 @@ fn main() -> anyhow::Result<()> {
         }
     };
 
-    if let Some(e) = args.external_script.as_ref() {
-        config_file.executable_script = e.clone();
-    }
-    if let Some(h) = args.hide_bottom_bar {
-        config_file.hide_changer_options_box = h;
-    }
+    config_file.apply_cli_arguments(&args);
 
-    if args.restore {
+    if let Some(command) = args.ipc.as_ref() {
+        send_ipc_command(command)
+    } else if args.daemon {
+        sleep(Duration::from_millis(args.startup_delay));
+        run_daemon(&args)
+    } else if args.restore {
         sleep(Duration::from_millis(args.startup_delay));
         restore_wallpapers(&config_file)
     } else if args.list_current_wallpapers {
         print_wallpaper_state(&config_file)
     } else if args.random {
         sleep(Duration::from_millis(args.startup_delay));
-        match set_random_wallpapers(&mut config_file) {
+        match set_random_wallpapers("All", &mut config_file) {
             Ok(_) => config_file.write_to_config_file(),
             Err(e) => Err(e),
         }
@@ -49,12 +52,31 @@ fn main() -> anyhow::Result<()> {
         print_app_version()
     } else if args.next.is_some() {
         sleep(Duration::from_millis(args.startup_delay));
-        match cycle_next_wallpaper(&args, &mut config_file) {
+        match cycle_next_wallpaper(&args.next.clone().unwrap_or_default(), &mut config_file) {
+            Ok(_) => config_file.write_to_config_file(),
+            Err(e) => Err(e),
+        }
+    } else if let Some(monitor) = args.previous.as_ref() {
+        match set_previous_wallpaper(monitor, &mut config_file) {
+            Ok(_) => config_file.write_to_config_file(),
+            Err(e) => Err(e),
+        }
+    } else if let Some(image) = args.span.as_ref() {
+        sleep(Duration::from_millis(args.startup_delay));
+        match set_spanned_wallpaper(image, &mut config_file) {
             Ok(_) => config_file.write_to_config_file(),
             Err(e) => Err(e),
         }
-    } else if args.delete_cache {
-        delete_image_cache()
+    } else if args.delete_cache || args.reset_config || args.purge {
+        delete_app_data(&args)
+    } else if let Some(file) = args.export_library.as_ref() {
+        export_library(file, &config_file)
+    } else if let Some(file) = args.import_library.as_ref() {
+        import_library(file, &config_file)
+    } else if args.gc_cache {
+        collect_cache_garbage(&config_file)
+    } else if args.find_duplicates {
+        find_duplicate_wallpapers(&config_file, args.trash_duplicates)
     } else {
         match AppState::run_application(config_file) {
             Ok(_) => Ok(()),
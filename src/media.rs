@@ -0,0 +1,155 @@
+use image::{ImageFormat, ImageReader};
+use log::debug;
+use std::{
+    path::Path,
+    process::{Command, Stdio},
+    sync::LazyLock,
+};
+use which::which;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum MediaKind {
+    /// Neither the `image` crate nor `ffprobe` could read the file.
+    #[default]
+    Unknown,
+    Image,
+    /// Animated image formats such as GIF.
+    Animation,
+    Video,
+}
+
+impl MediaKind {
+    /// The name stored in the cache database and used by search queries.
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Unknown => "unknown",
+            Self::Image => "image",
+            Self::Animation => "animation",
+            Self::Video => "video",
+        }
+    }
+
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<MediaKind> {
+        [Self::Unknown, Self::Image, Self::Animation, Self::Video]
+            .into_iter()
+            .find(|k| k.as_str() == name)
+    }
+}
+
+/// Properties of a wallpaper recorded alongside its thumbnail. Sizes are zero
+/// when they could not be read.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct MediaInfo {
+    pub kind: MediaKind,
+    pub width: u32,
+    pub height: u32,
+    /// Length of videos in whole seconds, zero for still images.
+    pub duration: u32,
+}
+
+impl MediaInfo {
+    #[must_use]
+    pub fn aspect_ratio(&self) -> Option<f64> {
+        (self.width > 0 && self.height > 0).then(|| f64::from(self.width) / f64::from(self.height))
+    }
+}
+
+/// Reads the size of images from their header, falling back to `ffprobe` for
+/// videos and formats the `image` crate does not know.
+#[must_use]
+pub fn probe(path: &Path) -> MediaInfo {
+    if let Some(info) = probe_image(path) {
+        return info;
+    }
+    static FFPROBE: LazyLock<bool> = LazyLock::new(|| which("ffprobe").is_ok());
+    if *FFPROBE {
+        match probe_ffprobe(path) {
+            Ok(info) => return info,
+            Err(e) => debug!("ffprobe could not read {}: {e}", path.display()),
+        }
+    }
+    MediaInfo::default()
+}
+
+fn probe_image(path: &Path) -> Option<MediaInfo> {
+    let reader = ImageReader::open(path).ok()?.with_guessed_format().ok()?;
+    let kind = match reader.format()? {
+        ImageFormat::Gif => MediaKind::Animation,
+        _ => MediaKind::Image,
+    };
+    let (width, height) = reader.into_dimensions().ok()?;
+    Some(MediaInfo {
+        kind,
+        width,
+        height,
+        duration: 0,
+    })
+}
+
+fn probe_ffprobe(path: &Path) -> anyhow::Result<MediaInfo> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "error", "-select_streams", "v:0"])
+        .args(["-show_entries", "stream=width,height:format=duration"])
+        .args(["-of", "default=noprint_wrappers=1"])
+        .arg(path)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!("ffprobe failed with {}", output.status));
+    }
+    Ok(parse_ffprobe(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Parses the `key=value` lines of `ffprobe`. Files without a video stream
+/// have no size; stills decoded by ffmpeg report a single frame's duration.
+fn parse_ffprobe(output: &str) -> MediaInfo {
+    let mut info = MediaInfo::default();
+    let mut duration = 0.0;
+    for (key, value) in output.lines().filter_map(|l| l.split_once('=')) {
+        match key {
+            "width" => info.width = value.parse().unwrap_or_default(),
+            "height" => info.height = value.parse().unwrap_or_default(),
+            "duration" => duration = value.parse::<f64>().unwrap_or_default(),
+            _ => {}
+        }
+    }
+    if info.width > 0 && info.height > 0 {
+        if duration > 0.1 {
+            info.kind = MediaKind::Video;
+            info.duration = duration.round() as u32;
+        } else {
+            info.kind = MediaKind::Image;
+        }
+    }
+    info
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn media_properties_are_read_from_headers_and_ffprobe() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("image.png");
+        image::RgbImage::new(64, 36).save(&path).unwrap();
+        let info = probe(&path);
+        assert_eq!(
+            (info.kind, info.width, info.height),
+            (MediaKind::Image, 64, 36)
+        );
+        assert!((info.aspect_ratio().unwrap() - 16.0 / 9.0).abs() < 1e-9);
+
+        let video = parse_ffprobe("width=3840\nheight=2160\nduration=12.600000\n");
+        assert_eq!(video.kind, MediaKind::Video);
+        assert_eq!(video.duration, 13);
+        let still = parse_ffprobe("width=100\nheight=200\nduration=N/A\n");
+        assert_eq!(still.kind, MediaKind::Image);
+        assert_eq!(parse_ffprobe("duration=180.0\n").kind, MediaKind::Unknown);
+        assert_eq!(MediaKind::from_name("video"), Some(MediaKind::Video));
+    }
+}
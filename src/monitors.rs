@@ -1,17 +1,147 @@
-use crate::locale::TRANSLATION;
+use crate::{app_state::Messages, common::Wallpaper, locale::TRANSLATION};
+use futures::SinkExt;
+use iced::Subscription;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt::Display};
 use wayland_client::{
-    Connection, Dispatch, QueueHandle,
+    Connection, Dispatch, Proxy, QueueHandle, WEnum,
     protocol::{wl_output, wl_registry},
 };
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Orientation {
+    Landscape,
+    Portrait,
+}
+
+impl Display for Orientation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Landscape => write!(f, "{}", TRANSLATION.get_translation("landscape")),
+            Self::Portrait => write!(f, "{}", TRANSLATION.get_translation("portrait")),
+        }
+    }
+}
+
+impl Orientation {
+    #[must_use]
+    pub fn from_size(width: u32, height: u32) -> Self {
+        if height > width {
+            Self::Portrait
+        } else {
+            Self::Landscape
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Monitor {
+    pub name: String,
+    pub description: String,
+    pub make: String,
+    pub model: String,
+    pub x: i32,
+    pub y: i32,
+    /// Current mode in hardware pixels, before the transform is applied.
+    pub width: i32,
+    pub height: i32,
+    pub refresh: i32,
+    pub scale: i32,
+    /// Raw `wl_output.transform` value.
+    pub transform: u32,
+}
+
+impl Monitor {
+    #[must_use]
+    pub fn is_rotated(&self) -> bool {
+        // 90, 270, flipped-90 and flipped-270 all swap width and height.
+        self.transform % 2 == 1
+    }
+
+    /// Mode size in pixels, swapped for rotated transforms. `scale` is not
+    /// applied.
+    #[must_use]
+    pub fn logical_size(&self) -> (u32, u32) {
+        let (width, height) = (
+            u32::try_from(self.width).unwrap_or_default(),
+            u32::try_from(self.height).unwrap_or_default(),
+        );
+        if self.is_rotated() {
+            (height, width)
+        } else {
+            (width, height)
+        }
+    }
+
+    #[must_use]
+    pub fn orientation(&self) -> Orientation {
+        let (width, height) = self.logical_size();
+        Orientation::from_size(width, height)
+    }
+
+    /// Updates the monitor from a `wl_output` event. Returns `true` on `done`,
+    /// after which the compositor has sent every property of the output.
+    fn apply_output_event(&mut self, event: wl_output::Event) -> bool {
+        match event {
+            wl_output::Event::Geometry {
+                x,
+                y,
+                make,
+                model,
+                transform,
+                ..
+            } => {
+                self.x = x;
+                self.y = y;
+                self.make = make;
+                self.model = model;
+                if let WEnum::Value(t) = transform {
+                    self.transform = t.into();
+                }
+            }
+            wl_output::Event::Mode {
+                flags,
+                width,
+                height,
+                refresh,
+            } => {
+                if let WEnum::Value(f) = flags
+                    && f.contains(wl_output::Mode::Current)
+                {
+                    self.width = width;
+                    self.height = height;
+                    self.refresh = refresh;
+                }
+            }
+            wl_output::Event::Scale { factor } => self.scale = factor,
+            wl_output::Event::Name { name } => self.name = name,
+            wl_output::Event::Description { description } => self.description = description,
+            wl_output::Event::Done => return true,
+            _ => {}
+        }
+        false
+    }
+
+    #[must_use]
+    pub fn label(&self) -> String {
+        let (width, height) = self.logical_size();
+        if width == 0 || height == 0 {
+            return self.name.clone();
+        }
+        format!("{} ({width}x{height}, {})", self.name, self.orientation())
+    }
+}
+
 #[derive(Default)]
 pub struct AvailableMonitors {
     pub available_monitors: Vec<String>,
+    pub monitors: Vec<Monitor>,
 }
 
 impl Dispatch<wl_registry::WlRegistry, ()> for AvailableMonitors {
     fn event(
-        _: &mut Self,
+        state: &mut Self,
         proxy: &wl_registry::WlRegistry,
         event: <wl_registry::WlRegistry as wayland_client::Proxy>::Event,
         (): &(),
@@ -25,22 +155,31 @@ impl Dispatch<wl_registry::WlRegistry, ()> for AvailableMonitors {
         } = event
             && interface == "wl_output"
         {
-            proxy.bind::<wl_output::WlOutput, _, _>(name, version.min(4), qhandle, ());
+            state.monitors.push(Monitor {
+                scale: 1,
+                ..Monitor::default()
+            });
+            proxy.bind::<wl_output::WlOutput, _, _>(
+                name,
+                version.min(4),
+                qhandle,
+                state.monitors.len() - 1,
+            );
         }
     }
 }
 
-impl Dispatch<wl_output::WlOutput, ()> for AvailableMonitors {
+impl Dispatch<wl_output::WlOutput, usize> for AvailableMonitors {
     fn event(
         state: &mut Self,
         _: &wl_output::WlOutput,
         event: <wl_output::WlOutput as wayland_client::Proxy>::Event,
-        (): &(),
+        index: &usize,
         _: &Connection,
         _: &QueueHandle<Self>,
     ) {
-        if let wl_output::Event::Name { name } = event {
-            state.available_monitors.push(name);
+        if let Some(monitor) = state.monitors.get_mut(*index) {
+            monitor.apply_output_event(event);
         }
     }
 }
@@ -54,10 +193,266 @@ impl AvailableMonitors {
         let mut available_monitors = AvailableMonitors::default();
         event_queue.roundtrip(&mut available_monitors)?;
         event_queue.roundtrip(&mut available_monitors)?;
-        available_monitors.available_monitors.sort();
+        available_monitors.monitors.retain(|m| !m.name.is_empty());
         available_monitors
-            .available_monitors
-            .insert(0, TRANSLATION.get_translation("All"));
+            .monitors
+            .sort_by(|m1, m2| m1.name.cmp(&m2.name));
+        available_monitors.available_monitors = monitor_names(&available_monitors.monitors);
         Ok(available_monitors)
     }
+
+    #[must_use]
+    pub fn find(&self, name: &str) -> Option<&Monitor> {
+        self.monitors.iter().find(|m| m.name == name)
+    }
+}
+
+/// Names shown in the monitor dropdown: "All" followed by every connector.
+#[must_use]
+pub fn monitor_names(monitors: &[Monitor]) -> Vec<String> {
+    std::iter::once(TRANSLATION.get_translation("All"))
+        .chain(monitors.iter().map(|m| m.name.clone()))
+        .collect()
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MonitorEvent {
+    Added(Monitor),
+    Changed(Monitor),
+    Removed(String),
+}
+
+struct TrackedOutput {
+    output: wl_output::WlOutput,
+    monitor: Monitor,
+    announced: bool,
+}
+
+/// Keeps the connected outputs in sync with the compositor for as long as the
+/// event queue is dispatched, queueing a [`MonitorEvent`] for every change.
+#[derive(Default)]
+struct OutputWatcher {
+    outputs: HashMap<u32, TrackedOutput>,
+    events: Vec<MonitorEvent>,
+}
+
+impl Dispatch<wl_registry::WlRegistry, ()> for OutputWatcher {
+    fn event(
+        state: &mut Self,
+        proxy: &wl_registry::WlRegistry,
+        event: <wl_registry::WlRegistry as wayland_client::Proxy>::Event,
+        (): &(),
+        _: &Connection,
+        qhandle: &QueueHandle<Self>,
+    ) {
+        match event {
+            wl_registry::Event::Global {
+                name,
+                interface,
+                version,
+            } if interface == "wl_output" => {
+                let output = proxy.bind(name, version.min(4), qhandle, name);
+                state.outputs.insert(
+                    name,
+                    TrackedOutput {
+                        output,
+                        monitor: Monitor {
+                            scale: 1,
+                            ..Monitor::default()
+                        },
+                        announced: false,
+                    },
+                );
+            }
+            wl_registry::Event::GlobalRemove { name } => {
+                if let Some(tracked) = state.outputs.remove(&name) {
+                    if tracked.output.version() >= 3 {
+                        tracked.output.release();
+                    }
+                    if tracked.announced {
+                        state
+                            .events
+                            .push(MonitorEvent::Removed(tracked.monitor.name));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<wl_output::WlOutput, u32> for OutputWatcher {
+    fn event(
+        state: &mut Self,
+        _: &wl_output::WlOutput,
+        event: <wl_output::WlOutput as wayland_client::Proxy>::Event,
+        name: &u32,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        let Some(tracked) = state.outputs.get_mut(name) else {
+            return;
+        };
+        if !tracked.monitor.apply_output_event(event) || tracked.monitor.name.is_empty() {
+            return;
+        }
+        let event = if tracked.announced {
+            MonitorEvent::Changed(tracked.monitor.clone())
+        } else {
+            tracked.announced = true;
+            MonitorEvent::Added(tracked.monitor.clone())
+        };
+        state.events.push(event);
+    }
+}
+
+/// Blocks on the Wayland connection and calls `on_event` whenever an output is
+/// plugged in, unplugged or reconfigured. Outputs connected when the watch
+/// starts are not reported. Returns when `on_event` fails.
+pub fn watch_monitors(
+    mut on_event: impl FnMut(MonitorEvent) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let conn = Connection::connect_to_env()?;
+    let mut event_queue = conn.new_event_queue::<OutputWatcher>();
+    let qh = event_queue.handle();
+    conn.display().get_registry(&qh, ());
+    let mut watcher = OutputWatcher::default();
+    event_queue.roundtrip(&mut watcher)?;
+    event_queue.roundtrip(&mut watcher)?;
+    watcher.events.clear();
+    loop {
+        event_queue.blocking_dispatch(&mut watcher)?;
+        for event in watcher.events.drain(..) {
+            debug!("Monitor event: {event:?}");
+            on_event(event)?;
+        }
+    }
+}
+
+fn monitor_stream() -> impl futures::Stream<Item = Messages> {
+    iced::stream::channel(16, async |output| {
+        std::thread::spawn(move || {
+            let result = watch_monitors(|event| {
+                let mut output = output.clone();
+                futures::executor::block_on(output.send(Messages::MonitorsChanged(event)))?;
+                Ok(())
+            });
+            if let Err(e) = result {
+                warn!("Stopped watching for monitor changes: {e}");
+            }
+        });
+        futures::future::pending::<()>().await;
+    })
+}
+
+pub fn monitor_subscription() -> Subscription<Messages> {
+    Subscription::run(monitor_stream)
+}
+
+/// Finds the saved wallpaper that belongs on `monitor`: its own entry, an entry
+/// saved under a previous connector name for the same screen, or the entry
+/// covering all monitors. Returns its index in `saved_wallpapers` and the
+/// monitor name to pass to the changer.
+#[must_use]
+pub fn saved_wallpaper_for(
+    monitor: &Monitor,
+    saved_wallpapers: &[Wallpaper],
+    monitors: &[Monitor],
+) -> Option<(usize, String)> {
+    saved_wallpapers
+        .iter()
+        .position(|w| {
+            w.monitor == monitor.name
+                || resolve_monitor_name(&w.monitor, &w.monitor_description, monitors)
+                    == monitor.name
+        })
+        .map(|i| (i, monitor.name.clone()))
+        .or_else(|| {
+            saved_wallpapers
+                .iter()
+                .position(|w| w.monitor == "All" || w.monitor == TRANSLATION.get_translation("All"))
+                .map(|i| (i, saved_wallpapers[i].monitor.clone()))
+        })
+}
+
+/// Returns the connector a saved wallpaper should be applied to. Connector
+/// names such as `DP-1` can change between boots, so when the saved name is
+/// gone the monitor with the same description (make, model and serial) is used.
+#[must_use]
+pub fn resolve_monitor_name(name: &str, description: &str, monitors: &[Monitor]) -> String {
+    if description.is_empty() || monitors.iter().any(|m| m.name == name) {
+        return name.to_owned();
+    }
+    monitors
+        .iter()
+        .find(|m| m.description == description)
+        .map_or_else(|| name.to_owned(), |m| m.name.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monitor(name: &str, description: &str) -> Monitor {
+        Monitor {
+            name: name.to_owned(),
+            description: description.to_owned(),
+            width: 2560,
+            height: 1440,
+            scale: 1,
+            ..Monitor::default()
+        }
+    }
+
+    #[test]
+    fn rotated_outputs_are_portrait() {
+        let mut side = monitor("DP-2", "Dell U2720Q");
+        assert_eq!(side.orientation(), Orientation::Landscape);
+        side.transform = 1;
+        assert_eq!(side.logical_size(), (1440, 2560));
+        assert_eq!(side.orientation(), Orientation::Portrait);
+        side.transform = 2;
+        assert_eq!(side.orientation(), Orientation::Landscape);
+    }
+
+    #[test]
+    fn renamed_connectors_are_matched_by_description() {
+        let monitors = [monitor("DP-3", "Dell U2720Q"), monitor("HDMI-A-1", "LG")];
+        assert_eq!(
+            resolve_monitor_name("DP-1", "Dell U2720Q", &monitors),
+            "DP-3"
+        );
+        assert_eq!(
+            resolve_monitor_name("HDMI-A-1", "LG", &monitors),
+            "HDMI-A-1"
+        );
+        assert_eq!(resolve_monitor_name("DP-1", "", &monitors), "DP-1");
+        assert_eq!(resolve_monitor_name("DP-1", "Unknown", &monitors), "DP-1");
+    }
+
+    #[test]
+    fn replugged_monitor_gets_its_saved_wallpaper() {
+        let wallpaper = |monitor: &str, description: &str| Wallpaper {
+            monitor: monitor.to_owned(),
+            monitor_description: description.to_owned(),
+            ..Wallpaper::default()
+        };
+        let dock = monitor("DP-5", "Dell U2720Q");
+        let monitors = [monitor("eDP-1", "Laptop"), dock.clone()];
+        let saved = [
+            wallpaper("eDP-1", "Laptop"),
+            wallpaper("DP-3", "Dell U2720Q"),
+        ];
+        assert_eq!(
+            saved_wallpaper_for(&dock, &saved, &monitors),
+            Some((1, "DP-5".to_owned()))
+        );
+        let unknown = monitor("HDMI-A-1", "LG");
+        assert_eq!(saved_wallpaper_for(&unknown, &saved, &monitors), None);
+        let saved = [wallpaper("All", "")];
+        assert_eq!(
+            saved_wallpaper_for(&unknown, &saved, &monitors),
+            Some((0, "All".to_owned()))
+        );
+    }
 }
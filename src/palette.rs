@@ -0,0 +1,121 @@
+use image::RgbImage;
+use std::{cmp::Reverse, collections::HashMap};
+
+pub const PALETTE_SIZE: usize = 5;
+/// Colors covering less of the image than this are left out of palettes.
+const MIN_SHARE: f64 = 0.05;
+/// Quantized colors closer than this are counted as one, so gradients do not
+/// split into many small shares.
+const MERGE_DISTANCE: f64 = 0.08;
+/// How close a palette color has to be for `color:` search terms to match.
+pub const COLOR_MATCH_DISTANCE: f64 = 0.15;
+
+pub type Rgb = [u8; 3];
+
+/// Perceptual distance between two colors using the "redmean" weighting,
+/// scaled so black and white are 1.0 apart.
+#[must_use]
+pub fn color_distance(a: Rgb, b: Rgb) -> f64 {
+    let mean_red = (f64::from(a[0]) + f64::from(b[0])) / 2.0;
+    let [dr, dg, db] = [0, 1, 2].map(|i| f64::from(a[i]) - f64::from(b[i]));
+    let distance = ((2.0 + mean_red / 256.0) * dr * dr
+        + 4.0 * dg * dg
+        + (2.0 + (255.0 - mean_red) / 256.0) * db * db)
+        .sqrt();
+    // Scaled by the distance between black and white.
+    distance / (255.0 * (8.0 + 255.0 / 256.0_f64).sqrt())
+}
+
+/// Distance from `color` to the nearest color of `palette`, or `f64::MAX` for
+/// images without a palette.
+#[must_use]
+pub fn palette_distance(palette: &[Rgb], color: Rgb) -> f64 {
+    palette
+        .iter()
+        .map(|c| color_distance(*c, color))
+        .min_by(f64::total_cmp)
+        .unwrap_or(f64::MAX)
+}
+
+#[must_use]
+pub fn to_hex(color: Rgb) -> String {
+    format!("#{:02x}{:02x}{:02x}", color[0], color[1], color[2])
+}
+
+/// Parses `#rrggbb`, with or without the `#`.
+#[must_use]
+pub fn parse_hex(hex: &str) -> Option<Rgb> {
+    let hex = hex.strip_prefix('#').unwrap_or(hex);
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+/// The colors covering most of `image`, most common first.
+#[must_use]
+pub fn dominant_colors(image: &RgbImage) -> Vec<Rgb> {
+    // Sums and pixel counts of colors quantized to 4 bits per channel.
+    let mut buckets = HashMap::<[u8; 3], ([u64; 3], u64)>::new();
+    for pixel in image.pixels() {
+        let (sum, count) = buckets.entry(pixel.0.map(|c| c >> 4)).or_default();
+        for (s, c) in sum.iter_mut().zip(pixel.0) {
+            *s += u64::from(c);
+        }
+        *count += 1;
+    }
+    let mut buckets = buckets.into_values().collect::<Vec<_>>();
+    buckets.sort_by_key(|b| Reverse(b.1));
+
+    let mut clusters: Vec<([u64; 3], u64)> = vec![];
+    let mean = |(sum, count): &([u64; 3], u64)| sum.map(|s| (s / count.max(&1)) as u8);
+    for bucket in buckets {
+        let color = mean(&bucket);
+        match clusters
+            .iter_mut()
+            .find(|c| color_distance(mean(c), color) < MERGE_DISTANCE)
+        {
+            Some(cluster) => {
+                for (s, b) in cluster.0.iter_mut().zip(bucket.0) {
+                    *s += b;
+                }
+                cluster.1 += bucket.1;
+            }
+            None => clusters.push(bucket),
+        }
+    }
+    clusters.sort_by_key(|c| Reverse(c.1));
+    let total = image.pixels().len().max(1) as f64;
+    clusters
+        .iter()
+        .filter(|c| c.1 as f64 / total >= MIN_SHARE)
+        .take(PALETTE_SIZE)
+        .map(mean)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn palettes_keep_the_large_color_areas() {
+        // Three quarters of a slightly noisy dark blue, a quarter orange and
+        // a few white pixels.
+        let image = RgbImage::from_fn(40, 40, |x, y| match (x, y) {
+            (0, 0..4) => image::Rgb([255, 255, 255]),
+            (0..30, _) => image::Rgb([30, 40, 90 + (x % 3) as u8]),
+            _ => image::Rgb([240, 130, 20]),
+        });
+        let palette = dominant_colors(&image);
+        assert_eq!(palette.len(), 2);
+        assert!(color_distance(palette[0], [30, 40, 91]) < 0.01);
+        assert!(palette_distance(&palette, parse_hex("#f08214").unwrap()) < 0.01);
+        assert!(palette_distance(&palette, [255, 255, 255]) > COLOR_MATCH_DISTANCE);
+        assert!((color_distance([0, 0, 0], [255, 255, 255]) - 1.0).abs() < 1e-9);
+        assert_eq!(to_hex([46, 52, 64]), "#2e3440");
+        assert_eq!(parse_hex("2e3440"), Some([46, 52, 64]));
+        assert_eq!(parse_hex("#2e34"), None);
+    }
+}
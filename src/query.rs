@@ -0,0 +1,402 @@
+use crate::{
+    common::CacheImageFile,
+    media::MediaKind,
+    monitors::Orientation,
+    palette::{COLOR_MATCH_DISTANCE, Rgb, palette_distance, parse_hex, to_hex},
+};
+
+/// Relative difference below which `ratio:` considers two aspect ratios equal,
+/// so both 2560x1080 and 3440x1440 count as 21:9.
+const RATIO_TOLERANCE: f64 = 0.03;
+
+/// Tags are compared without surrounding spaces and ignoring case.
+#[must_use]
+pub fn normalize_tag(tag: &str) -> String {
+    tag.trim().to_lowercase()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Property {
+    Width,
+    Height,
+    Ratio,
+    Duration,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Comparison {
+    Less,
+    LessOrEqual,
+    Equal,
+    GreaterOrEqual,
+    Greater,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Condition {
+    /// Lowercase, so matching ignores case.
+    Name(String),
+    Tag(String),
+    Kind(MediaKind),
+    Orientation(Orientation),
+    Property(Property, Comparison, f64),
+    Color(Rgb),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Term {
+    negated: bool,
+    condition: Condition,
+}
+
+/// A parsed search bar query. Terms are separated by spaces and all of them
+/// have to match:
+///
+/// - `beach` or `name:beach`: the file name contains "beach"
+/// - `tag:nature`: the image has the tag "nature"
+/// - `w>=3840`, `h<1080`, `duration>30`: compares the width, height or
+///   length in seconds; `:` or `=` compare for equality
+/// - `ratio:21:9`, `ratio>1.5`: compares the aspect ratio
+/// - `landscape`, `portrait`, `image`, `animation`, `video`: orientation and
+///   kind, also written as `is:video`
+/// - `color:#2e3440`: one of the dominant colors is close to this one
+/// - `-tag:dark`: a leading `-` turns any term around
+///
+/// Quotes keep spaces in a value, as in `tag:"night sky"`. Case is ignored.
+/// Images whose properties are not known yet only match negated property
+/// terms.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Query {
+    terms: Vec<Term>,
+}
+
+/// Splits on whitespace outside of double quotes and drops the quotes.
+fn tokenize(query: &str) -> Vec<String> {
+    let mut tokens = vec![];
+    let mut token = String::new();
+    let mut quoted = false;
+    for c in query.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+            }
+            c => token.push(c),
+        }
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+    tokens
+}
+
+/// Parses `16:9`, `16/9` or `1.78`.
+fn parse_ratio(value: &str) -> Option<f64> {
+    match value.split_once([':', '/']) {
+        Some((width, height)) => {
+            let (width, height) = (width.parse::<f64>().ok()?, height.parse::<f64>().ok()?);
+            (height > 0.0).then(|| width / height)
+        }
+        None => value.parse().ok(),
+    }
+}
+
+/// Parses the keyword terms, like `portrait` or `video`.
+fn parse_keyword(keyword: &str) -> Option<Condition> {
+    Some(match keyword {
+        "landscape" => Condition::Orientation(Orientation::Landscape),
+        "portrait" => Condition::Orientation(Orientation::Portrait),
+        _ => Condition::Kind(MediaKind::from_name(keyword).filter(|k| *k != MediaKind::Unknown)?),
+    })
+}
+
+/// Parses property comparisons like `w>=3840` or `ratio:21:9`.
+fn parse_property(token: &str) -> Option<Condition> {
+    let split = token.find([':', '<', '>', '='])?;
+    let property = match &token[..split] {
+        "w" | "width" => Property::Width,
+        "h" | "height" => Property::Height,
+        "ratio" => Property::Ratio,
+        "duration" => Property::Duration,
+        _ => return None,
+    };
+    let rest = &token[split..];
+    let (comparison, value) = [
+        (">=", Comparison::GreaterOrEqual),
+        ("<=", Comparison::LessOrEqual),
+        (">", Comparison::Greater),
+        ("<", Comparison::Less),
+        ("=", Comparison::Equal),
+        (":", Comparison::Equal),
+    ]
+    .into_iter()
+    .find_map(|(op, comparison)| rest.strip_prefix(op).map(|value| (comparison, value)))?;
+    let value = match property {
+        Property::Ratio => parse_ratio(value)?,
+        _ => value.parse().ok()?,
+    };
+    Some(Condition::Property(property, comparison, value))
+}
+
+fn parse_condition(token: &str) -> Option<Condition> {
+    let lowercase = token.to_lowercase();
+    if let Some((field, value)) = token.split_once(':') {
+        match field.to_lowercase().as_str() {
+            "tag" => return Some(Condition::Tag(normalize_tag(value))),
+            "name" => return Some(Condition::Name(value.to_lowercase())),
+            "is" => return parse_keyword(&value.to_lowercase()),
+            "color" | "colour" => return parse_hex(value).map(Condition::Color),
+            _ => {}
+        }
+    }
+    parse_keyword(&lowercase)
+        .or_else(|| parse_property(&lowercase))
+        .or(Some(Condition::Name(lowercase)))
+}
+
+impl Comparison {
+    fn holds(self, value: f64, target: f64, tolerance: f64) -> bool {
+        match self {
+            Self::Less => value < target,
+            Self::LessOrEqual => value <= target + tolerance,
+            Self::Equal => (value - target).abs() <= tolerance,
+            Self::GreaterOrEqual => value >= target - tolerance,
+            Self::Greater => value > target,
+        }
+    }
+}
+
+impl Condition {
+    fn is_empty(&self) -> bool {
+        match self {
+            Self::Name(value) | Self::Tag(value) => value.is_empty(),
+            _ => false,
+        }
+    }
+
+    /// `name` is the lowercase file name of `image`.
+    fn matches(&self, image: &CacheImageFile, name: &str, tags: &[String]) -> bool {
+        let media = image.media.as_ref();
+        let size = media.filter(|m| m.width > 0 && m.height > 0);
+        match self {
+            Self::Name(value) => name.contains(value.as_str()),
+            Self::Tag(value) => tags.contains(value),
+            Self::Kind(kind) => media.is_some_and(|m| m.kind == *kind),
+            Self::Orientation(orientation) => {
+                size.is_some_and(|m| Orientation::from_size(m.width, m.height) == *orientation)
+            }
+            Self::Property(property, comparison, target) => {
+                let (value, tolerance) = match (property, size) {
+                    (_, None) => return false,
+                    (Property::Width, Some(m)) => (f64::from(m.width), 0.0),
+                    (Property::Height, Some(m)) => (f64::from(m.height), 0.0),
+                    (Property::Ratio, Some(m)) => (
+                        m.aspect_ratio().unwrap_or_default(),
+                        target * RATIO_TOLERANCE,
+                    ),
+                    (Property::Duration, Some(m)) => (f64::from(m.duration), 0.0),
+                };
+                comparison.holds(value, *target, tolerance)
+            }
+            Self::Color(color) => palette_distance(&image.palette, *color) <= COLOR_MATCH_DISTANCE,
+        }
+    }
+}
+
+impl Query {
+    #[must_use]
+    pub fn parse(query: &str) -> Query {
+        let terms = tokenize(query)
+            .into_iter()
+            .filter_map(|token| {
+                let (negated, token) = match token.strip_prefix('-') {
+                    Some(rest) if !rest.is_empty() => (true, rest.to_owned()),
+                    _ => (false, token),
+                };
+                parse_condition(&token)
+                    .filter(|c| !c.is_empty())
+                    .map(|condition| Term { negated, condition })
+            })
+            .collect();
+        Query { terms }
+    }
+
+    /// A query requiring every tag in `tags`, or its absence for tags
+    /// starting with `-`.
+    #[must_use]
+    pub fn from_tags(tags: &[String]) -> Query {
+        let terms = tags
+            .iter()
+            .map(|tag| {
+                let (negated, tag) = match tag.strip_prefix('-') {
+                    Some(rest) => (true, rest),
+                    None => (false, tag.as_str()),
+                };
+                Term {
+                    negated,
+                    condition: Condition::Tag(normalize_tag(tag)),
+                }
+            })
+            .filter(|t| !t.condition.is_empty())
+            .collect();
+        Query { terms }
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    #[must_use]
+    pub fn matches(&self, image: &CacheImageFile, tags: &[String]) -> bool {
+        let name = image.name.to_lowercase();
+        self.terms
+            .iter()
+            .all(|term| term.condition.matches(image, &name, tags) != term.negated)
+    }
+
+    /// The color of the first color term that is not negated, which the grid
+    /// is sorted by.
+    #[must_use]
+    pub fn color(&self) -> Option<Rgb> {
+        self.terms.iter().find_map(|t| match t.condition {
+            Condition::Color(color) if !t.negated => Some(color),
+            _ => None,
+        })
+    }
+}
+
+/// Adds `term` to the search bar text `query`, or removes it when it is
+/// already there. Adding a term removes those in `exclusive`, e.g. `portrait`
+/// when switching to `landscape`.
+#[must_use]
+pub fn toggle_term(query: &str, term: &str, exclusive: &[&str]) -> String {
+    let present = has_term(query, term);
+    let mut words = query
+        .split_whitespace()
+        .filter(|w| {
+            !w.eq_ignore_ascii_case(term)
+                && (present || !exclusive.iter().any(|e| w.eq_ignore_ascii_case(e)))
+        })
+        .collect::<Vec<_>>();
+    if !present {
+        words.push(term);
+    }
+    words.join(" ")
+}
+
+/// Replaces the color terms of the search bar text `query` with `color`, or
+/// removes them.
+#[must_use]
+pub fn set_color_term(query: &str, color: Option<Rgb>) -> String {
+    query
+        .split_whitespace()
+        .filter(|w| {
+            !w.split_once(':')
+                .is_some_and(|(f, _)| ["color", "colour"].contains(&f.to_lowercase().as_str()))
+        })
+        .map(str::to_owned)
+        .chain(color.map(|c| format!("color:{}", to_hex(c))))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Whether the search bar text `query` contains `term` as a whole word.
+#[must_use]
+pub fn has_term(query: &str, term: &str) -> bool {
+    query
+        .split_whitespace()
+        .any(|w| w.eq_ignore_ascii_case(term))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::MediaInfo;
+
+    fn image(name: &str, media: Option<MediaInfo>) -> CacheImageFile {
+        CacheImageFile {
+            name: name.to_owned(),
+            media,
+            ..CacheImageFile::default()
+        }
+    }
+
+    #[test]
+    fn queries_combine_names_tags_and_negation() {
+        let tags = |t: &[&str]| t.iter().map(|t| (*t).to_owned()).collect::<Vec<_>>();
+        let query = Query::parse("tag:Nature -tag:dark  name:BEACH");
+        assert!(query.matches(&image("Sunny_beach.png", None), &tags(&["nature"])));
+        assert!(!query.matches(&image("sunny_beach.png", None), &tags(&["nature", "dark"])));
+        assert!(!query.matches(&image("forest.png", None), &tags(&["nature"])));
+
+        let query = Query::parse("tag:\"night sky\" city");
+        assert!(query.matches(&image("city.jpg", None), &tags(&["night sky"])));
+        assert!(!query.matches(&image("city.jpg", None), &tags(&["night"])));
+
+        assert!(Query::parse("  - tag: ").matches(&image("-", None), &[]));
+        assert!(Query::parse("").is_empty());
+        let query = Query::from_tags(&["nature".to_owned(), "-dark".to_owned()]);
+        assert!(query.matches(&image("x.png", None), &tags(&["nature"])));
+        assert!(!query.matches(&image("x.png", None), &tags(&["nature", "dark"])));
+    }
+
+    #[test]
+    fn queries_compare_media_properties() {
+        let media = |kind, width, height| MediaInfo {
+            kind,
+            width,
+            height,
+            duration: 0,
+        };
+        let uhd = media(MediaKind::Image, 3840, 2160);
+        let wide = media(MediaKind::Image, 3440, 1440);
+        let phone = media(MediaKind::Video, 1080, 2340);
+        let matches = |query: &str, media: &MediaInfo| {
+            Query::parse(query).matches(&image("wall.png", Some(*media)), &[])
+        };
+
+        assert!(matches("w>=3840 landscape image", &uhd));
+        assert!(!matches("w>=3840", &wide));
+        assert!(matches("ratio:16:9", &uhd));
+        assert!(matches("RATIO:21:9 -video", &wide));
+        assert!(!matches("ratio:21:9", &uhd));
+        assert!(matches("is:video portrait h>2000", &phone));
+        assert!(matches("ratio<1", &phone));
+        assert!(!Query::parse("video").matches(&image("video.mp4", None), &[]));
+        assert!(Query::parse("name:video").matches(&image("video.mp4", None), &[]));
+        assert!(!matches("w>=1", &MediaInfo::default()));
+    }
+
+    #[test]
+    fn color_terms_match_the_palette() {
+        let nord = CacheImageFile {
+            palette: vec![[46, 52, 64], [136, 192, 208]],
+            ..CacheImageFile::default()
+        };
+        let query = Query::parse("color:#2f3541");
+        assert!(query.matches(&nord, &[]));
+        assert_eq!(query.color(), Some([47, 53, 65]));
+        assert!(!Query::parse("color:#ff0000").matches(&nord, &[]));
+        assert!(!query.matches(&CacheImageFile::default(), &[]));
+        assert_eq!(Query::parse("-color:#2f3541 colour:nope").color(), None);
+        assert_eq!(
+            set_color_term("tag:x color:#000000", Some([255, 0, 0])),
+            "tag:x color:#ff0000"
+        );
+        assert_eq!(set_color_term("Colour:#000000 tag:x", None), "tag:x");
+    }
+
+    #[test]
+    fn toolbar_toggles_edit_the_query_text() {
+        assert_eq!(toggle_term("tag:x", "video", &["image"]), "tag:x video");
+        assert_eq!(toggle_term("tag:x  Video", "video", &["image"]), "tag:x");
+        assert_eq!(
+            toggle_term("image tag:x", "video", &["image"]),
+            "tag:x video"
+        );
+        assert!(has_term("w>=3840 video", "w>=3840"));
+    }
+}
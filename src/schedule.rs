@@ -0,0 +1,439 @@
+use crate::{
+    app_state::AppState,
+    cli::{changer_for_monitor, get_collection_images, set_wallpaper},
+    cli_parser::parse_interval,
+    database::DatabaseConnection,
+    shuffle::{draw_image, images_to_avoid},
+    wallpaper_changers::WallpaperChanger,
+};
+use anyhow::anyhow;
+use chrono::{DateTime, Duration as ChronoDuration, NaiveDate, NaiveTime, TimeZone, Utc};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    f64::consts::PI,
+    fmt::Display,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Location {
+    /// Degrees north.
+    pub latitude: f64,
+    /// Degrees east.
+    pub longitude: f64,
+}
+
+/// When a schedule entry starts. Written as `HH:MM`, `sunrise` or `sunset`,
+/// optionally followed by an offset such as `sunset-30m` or `sunrise+1h`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum ScheduleTime {
+    Clock(NaiveTime),
+    Sunrise(i64),
+    Sunset(i64),
+}
+
+impl ScheduleTime {
+    pub fn parse(s: &str) -> anyhow::Result<ScheduleTime> {
+        let s = s.trim();
+        for (event, constructor) in [
+            ("sunrise", ScheduleTime::Sunrise as fn(i64) -> ScheduleTime),
+            ("sunset", ScheduleTime::Sunset),
+        ] {
+            let Some(offset) = s.strip_prefix(event) else {
+                continue;
+            };
+            let seconds = match offset.chars().next() {
+                None => 0,
+                Some('+') => i64::try_from(parse_interval(&offset[1..])?.as_secs())?,
+                Some('-') => -i64::try_from(parse_interval(&offset[1..])?.as_secs())?,
+                Some(_) => return Err(anyhow!("Invalid offset \"{offset}\", use + or -")),
+            };
+            return Ok(constructor(seconds));
+        }
+        NaiveTime::parse_from_str(s, "%H:%M")
+            .map(ScheduleTime::Clock)
+            .map_err(|_| anyhow!("Invalid schedule time \"{s}\", use HH:MM, sunrise or sunset"))
+    }
+
+    #[must_use]
+    pub fn is_solar(&self) -> bool {
+        !matches!(self, ScheduleTime::Clock(_))
+    }
+
+    /// The moment this time falls on `date` in the time zone `tz`. Solar times
+    /// need a location and do not exist during polar day or night.
+    pub fn resolve<Tz: TimeZone>(
+        &self,
+        date: NaiveDate,
+        location: Option<Location>,
+        tz: &Tz,
+    ) -> Option<DateTime<Tz>> {
+        match self {
+            ScheduleTime::Clock(time) => tz.from_local_datetime(&date.and_time(*time)).earliest(),
+            ScheduleTime::Sunrise(offset) | ScheduleTime::Sunset(offset) => {
+                let (sunrise, sunset) = sun_times(date, location?)?;
+                let event = if matches!(self, ScheduleTime::Sunrise(_)) {
+                    sunrise
+                } else {
+                    sunset
+                };
+                Some((event + ChronoDuration::seconds(*offset)).with_timezone(tz))
+            }
+        }
+    }
+}
+
+impl Display for ScheduleTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (event, offset) = match self {
+            ScheduleTime::Clock(time) => return write!(f, "{}", time.format("%H:%M")),
+            ScheduleTime::Sunrise(offset) => ("sunrise", offset),
+            ScheduleTime::Sunset(offset) => ("sunset", offset),
+        };
+        match offset {
+            0 => write!(f, "{event}"),
+            o if o % 60 == 0 => write!(f, "{event}{o:+}m", o = o / 60),
+            o => write!(f, "{event}{o:+}s"),
+        }
+    }
+}
+
+impl TryFrom<String> for ScheduleTime {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        ScheduleTime::parse(&value)
+    }
+}
+
+impl From<ScheduleTime> for String {
+    fn from(value: ScheduleTime) -> Self {
+        value.to_string()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScheduleSource {
+    Image(PathBuf),
+    /// A random supported image from the folder.
+    Folder(PathBuf),
+    Collection(String),
+}
+
+impl ScheduleSource {
+    /// Folders and collections keep rotating while their entry is active.
+    #[must_use]
+    pub fn rotates(&self) -> bool {
+        !matches!(self, ScheduleSource::Image(_))
+    }
+}
+
+/// Shows `source` on `monitor` from `start` until the next entry for the same
+/// monitor starts.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ScheduleEntry {
+    pub monitor: String,
+    pub start: ScheduleTime,
+    pub source: ScheduleSource,
+}
+
+fn julian_to_utc(julian_day: f64) -> Option<DateTime<Utc>> {
+    #[allow(clippy::cast_possible_truncation)]
+    let milliseconds = ((julian_day - 2_440_587.5) * 86_400_000.0).round() as i64;
+    DateTime::from_timestamp_millis(milliseconds)
+}
+
+/// Sunrise and sunset on `date` using the NOAA sunrise equation. Returns `None`
+/// when the sun does not rise or set that day.
+#[must_use]
+pub fn sun_times(date: NaiveDate, location: Location) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    let to_radians = |degrees: f64| degrees * PI / 180.0;
+    let j2000 = NaiveDate::from_ymd_opt(2000, 1, 1)?;
+    #[allow(clippy::cast_precision_loss)]
+    let days = (date - j2000).num_days() as f64;
+    let mean_solar_noon = days - location.longitude / 360.0;
+    let mean_anomaly = (357.5291 + 0.985_600_28 * mean_solar_noon).rem_euclid(360.0);
+    let m = to_radians(mean_anomaly);
+    let center = 1.9148 * m.sin() + 0.0200 * (2.0 * m).sin() + 0.0003 * (3.0 * m).sin();
+    let ecliptic_longitude =
+        to_radians((mean_anomaly + center + 180.0 + 102.9372).rem_euclid(360.0));
+    let transit = 2_451_545.0 + mean_solar_noon + 0.0053 * m.sin()
+        - 0.0069 * (2.0 * ecliptic_longitude).sin();
+    let declination = (ecliptic_longitude.sin() * to_radians(23.4397).sin()).asin();
+    let latitude = to_radians(location.latitude);
+    let cos_hour_angle = (to_radians(-0.833).sin() - latitude.sin() * declination.sin())
+        / (latitude.cos() * declination.cos());
+    if !(-1.0..=1.0).contains(&cos_hour_angle) {
+        return None;
+    }
+    let hour_angle = cos_hour_angle.acos() * 180.0 / PI;
+    Some((
+        julian_to_utc(transit - hour_angle / 360.0)?,
+        julian_to_utc(transit + hour_angle / 360.0)?,
+    ))
+}
+
+/// Every start of every entry from yesterday to tomorrow, so ranges that run
+/// past midnight are found too.
+fn occurrences<'a, Tz: TimeZone>(
+    schedule: &'a [ScheduleEntry],
+    location: Option<Location>,
+    now: &DateTime<Tz>,
+) -> Vec<(DateTime<Tz>, &'a ScheduleEntry)> {
+    let today = now.date_naive();
+    [today.pred_opt(), Some(today), today.succ_opt()]
+        .into_iter()
+        .flatten()
+        .flat_map(|date| {
+            schedule.iter().filter_map(move |entry| {
+                entry
+                    .start
+                    .resolve(date, location, &now.timezone())
+                    .map(|start| (start, entry))
+            })
+        })
+        .collect()
+}
+
+#[must_use]
+pub fn active_entries<'a, Tz: TimeZone>(
+    schedule: &'a [ScheduleEntry],
+    location: Option<Location>,
+    now: &DateTime<Tz>,
+) -> Vec<&'a ScheduleEntry> {
+    let mut active: HashMap<&str, (DateTime<Tz>, &ScheduleEntry)> = HashMap::new();
+    for (start, entry) in occurrences(schedule, location, now) {
+        if start > *now {
+            continue;
+        }
+        match active.get(entry.monitor.as_str()) {
+            Some((latest, _)) if *latest >= start => {}
+            _ => {
+                active.insert(&entry.monitor, (start, entry));
+            }
+        }
+    }
+    let mut entries = active.into_values().map(|(_, e)| e).collect::<Vec<_>>();
+    entries.sort_by(|e1, e2| e1.monitor.cmp(&e2.monitor));
+    entries
+}
+
+#[must_use]
+pub fn next_change<Tz: TimeZone>(
+    schedule: &[ScheduleEntry],
+    location: Option<Location>,
+    now: &DateTime<Tz>,
+) -> Option<DateTime<Tz>> {
+    occurrences(schedule, location, now)
+        .into_iter()
+        .map(|(start, _)| start)
+        .filter(|start| start > now)
+        .min()
+}
+
+fn pick_image(source: &ScheduleSource, monitor: &str, app_state: &AppState) -> Option<PathBuf> {
+    let accepted_formats = changer_for_monitor(monitor, app_state).accepted_formats();
+    let is_supported = |p: &Path| {
+        p.extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| accepted_formats.iter().any(|f| f == e))
+    };
+    let candidates = match source {
+        ScheduleSource::Image(path) => return Some(path.clone()),
+        ScheduleSource::Folder(folder) => walkdir::WalkDir::new(folder)
+            .follow_links(true)
+            .into_iter()
+            .filter_map(std::result::Result::ok)
+            .filter(|f| f.file_type().is_file())
+            .map(walkdir::DirEntry::into_path)
+            .filter(|p| is_supported(p))
+            .collect::<Vec<_>>(),
+        ScheduleSource::Collection(name) => get_collection_images(name)
+            .into_iter()
+            .filter(|p| is_supported(p))
+            .collect(),
+    };
+    let avoid = images_to_avoid(
+        monitor,
+        &app_state.saved_wallpapers,
+        app_state.distinct_random_monitors,
+    );
+    DatabaseConnection::new()
+        .and_then(|conn| {
+            draw_image(
+                &conn,
+                monitor,
+                &candidates,
+                &avoid,
+                app_state.weighted_random,
+            )
+        })
+        .unwrap_or_else(|e| {
+            warn!("Failed to draw from the shuffle bag of \"{monitor}\": {e}");
+            None
+        })
+}
+
+/// Applies the schedule from the background mode. Remembers what was applied
+/// on each monitor so entries are only re-applied when they start, or when a
+/// folder or collection is due for its next image.
+#[derive(Default)]
+pub struct Scheduler {
+    applied: HashMap<String, (ScheduleEntry, Instant)>,
+}
+
+impl Scheduler {
+    /// Applies the entries that are due and returns how long to wait before
+    /// the next call.
+    pub fn apply<Tz: TimeZone>(
+        &mut self,
+        app_state: &mut AppState,
+        now: &DateTime<Tz>,
+        interval: Duration,
+    ) -> Duration {
+        let location = app_state.schedule_location;
+        let schedule = app_state.schedule.clone();
+        if location.is_none() && schedule.iter().any(|e| e.start.is_solar()) {
+            warn!("Schedule uses sunrise or sunset but schedule_location is not set");
+        }
+        for entry in active_entries(&schedule, location, now) {
+            let due = match self.applied.get(&entry.monitor) {
+                Some((applied, at)) => {
+                    applied != entry || (entry.source.rotates() && at.elapsed() >= interval)
+                }
+                None => true,
+            };
+            if !due {
+                continue;
+            }
+            // Recorded even on failure so a broken entry is retried on the next
+            // rotation instead of on every wake-up.
+            self.applied
+                .insert(entry.monitor.clone(), (entry.clone(), Instant::now()));
+            let Some(image) = pick_image(&entry.source, &entry.monitor, app_state) else {
+                warn!(
+                    "No supported wallpapers found in {:?} for display \"{}\"",
+                    entry.source, entry.monitor
+                );
+                continue;
+            };
+            info!(
+                "Schedule: setting {} on \"{}\" ({})",
+                image.display(),
+                entry.monitor,
+                entry.start
+            );
+            if let Err(e) = set_wallpaper(&entry.monitor, &image, app_state) {
+                warn!(
+                    "Failed to apply schedule entry for \"{}\": {e}",
+                    entry.monitor
+                );
+            }
+        }
+
+        let mut wait = interval;
+        if let Some(next) = next_change(&schedule, location, now)
+            && let Ok(until_next) = (next - now.clone()).to_std()
+        {
+            wait = wait.min(until_next);
+        }
+        for (entry, at) in self.applied.values() {
+            if entry.source.rotates() {
+                wait = wait.min(interval.saturating_sub(at.elapsed()));
+            }
+        }
+        debug!("Next schedule check in {}s", wait.as_secs());
+        wait.max(Duration::from_secs(1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(monitor: &str, start: &str, image: &str) -> ScheduleEntry {
+        ScheduleEntry {
+            monitor: monitor.to_owned(),
+            start: ScheduleTime::parse(start).unwrap(),
+            source: ScheduleSource::Image(PathBuf::from(image)),
+        }
+    }
+
+    fn at(hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 6, 21, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn schedule_times_round_trip_through_strings() {
+        for s in ["07:30", "sunrise", "sunset-30m", "sunrise+2m", "sunset+45s"] {
+            assert_eq!(ScheduleTime::parse(s).unwrap().to_string(), s);
+        }
+        assert_eq!(
+            ScheduleTime::parse("sunset+1h").unwrap(),
+            ScheduleTime::Sunset(3600)
+        );
+        assert!(ScheduleTime::parse("25:00").is_err());
+        assert!(ScheduleTime::parse("sunset*2").is_err());
+        assert!(ScheduleTime::parse("noon").is_err());
+    }
+
+    #[test]
+    fn sun_times_match_known_values() {
+        let london = Location {
+            latitude: 51.5074,
+            longitude: -0.1278,
+        };
+        let date = NaiveDate::from_ymd_opt(2024, 6, 21).unwrap();
+        let (sunrise, sunset) = sun_times(date, london).unwrap();
+        // Published times: 03:43 and 20:21 UTC.
+        assert!((sunrise - at(3, 43)).num_minutes().abs() <= 3);
+        assert!((sunset - at(20, 21)).num_minutes().abs() <= 3);
+
+        let tromso = Location {
+            latitude: 69.65,
+            longitude: 18.96,
+        };
+        assert_eq!(sun_times(date, tromso), None);
+    }
+
+    #[test]
+    fn latest_started_entry_wins_and_wraps_past_midnight() {
+        let schedule = [
+            entry("DP-1", "07:00", "/day.png"),
+            entry("DP-1", "21:00", "/night.png"),
+            entry("HDMI-A-1", "12:00", "/noon.png"),
+        ];
+        let active = |now| {
+            active_entries(&schedule, None, &now)
+                .into_iter()
+                .map(|e| e.source.clone())
+                .collect::<Vec<_>>()
+        };
+        let image = |p: &str| ScheduleSource::Image(PathBuf::from(p));
+
+        assert_eq!(active(at(3, 0)), [image("/night.png"), image("/noon.png")]);
+        assert_eq!(active(at(8, 0)), [image("/day.png"), image("/noon.png")]);
+        assert_eq!(active(at(22, 0)), [image("/night.png"), image("/noon.png")]);
+        assert_eq!(next_change(&schedule, None, &at(8, 0)), Some(at(12, 0)));
+        assert_eq!(
+            next_change(&schedule, None, &at(22, 0)),
+            Some(at(7, 0) + ChronoDuration::days(1))
+        );
+    }
+
+    #[test]
+    fn solar_entries_are_skipped_without_a_location() {
+        let schedule = [entry("DP-1", "sunset", "/night.png")];
+        assert!(active_entries(&schedule, None, &at(23, 0)).is_empty());
+        let london = Location {
+            latitude: 51.5074,
+            longitude: -0.1278,
+        };
+        assert_eq!(active_entries(&schedule, Some(london), &at(23, 0)).len(), 1);
+    }
+}
@@ -0,0 +1,237 @@
+use crate::{
+    common::{DEFAULT_IMAGE_WEIGHT, MAX_IMAGE_WEIGHT, Wallpaper},
+    database::DatabaseConnection,
+};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// How much more likely a favorite is to be picked in weighted mode.
+const FAVORITE_BOOST: f64 = 2.0;
+/// An image shown this long ago or earlier has its full weight again.
+const RECENCY_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+/// Weight multiplier of an image that was shown just now.
+const MIN_RECENCY_FACTOR: f64 = 0.05;
+
+/// Picks from the images of `eligible` that were not drawn yet in this round,
+/// preferring ones outside `avoid`. When only avoided images are left, an
+/// already drawn one outside `avoid` is repeated before an avoided one is
+/// shown. Returns the image and whether a new round had to be started because
+/// every eligible image was already drawn.
+fn choose(
+    eligible: &[PathBuf],
+    drawn: &HashSet<PathBuf>,
+    avoid: &HashSet<PathBuf>,
+) -> Option<(PathBuf, bool)> {
+    let undrawn = eligible
+        .iter()
+        .filter(|p| !drawn.contains(*p))
+        .collect::<Vec<_>>();
+    let new_round = undrawn.is_empty();
+    let everything = eligible.iter().collect::<Vec<_>>();
+    let candidates = [
+        outside(&undrawn, avoid),
+        outside(&everything, avoid),
+        undrawn,
+        everything,
+    ]
+    .into_iter()
+    .find(|c| !c.is_empty())?;
+    Some((
+        candidates[rand::random_range(0..candidates.len())].clone(),
+        new_round,
+    ))
+}
+
+fn outside<'a>(images: &[&'a PathBuf], avoid: &HashSet<PathBuf>) -> Vec<&'a PathBuf> {
+    images
+        .iter()
+        .filter(|p| !avoid.contains(**p))
+        .copied()
+        .collect()
+}
+
+/// Relative chance of an image being picked in weighted mode: its star
+/// rating, doubled for favorites and reduced while it was shown recently.
+fn image_weight(favorite: bool, stars: u8, shown_at: Option<i64>, now: i64) -> f64 {
+    let mut weight = f64::from(stars.clamp(1, MAX_IMAGE_WEIGHT));
+    if favorite {
+        weight *= FAVORITE_BOOST;
+    }
+    if let Some(shown_at) = shown_at {
+        #[allow(clippy::cast_precision_loss)]
+        let age = (now - shown_at).max(0) as f64;
+        weight *= (age / RECENCY_WINDOW.as_secs_f64()).clamp(MIN_RECENCY_FACTOR, 1.0);
+    }
+    weight
+}
+
+/// Index picked from `weights` with a chance proportional to each weight.
+fn weighted_index(weights: &[f64]) -> Option<usize> {
+    let total = weights.iter().sum::<f64>();
+    if weights.is_empty() || total <= 0.0 {
+        return None;
+    }
+    let mut remaining = rand::random_range(0.0..total);
+    for (index, weight) in weights.iter().enumerate() {
+        if remaining < *weight {
+            return Some(index);
+        }
+        remaining -= weight;
+    }
+    Some(weights.len() - 1)
+}
+
+fn choose_weighted(
+    conn: &DatabaseConnection,
+    eligible: &[PathBuf],
+    avoid: &HashSet<PathBuf>,
+) -> anyhow::Result<Option<PathBuf>> {
+    let mut candidates = eligible
+        .iter()
+        .filter(|p| !avoid.contains(*p))
+        .collect::<Vec<_>>();
+    if candidates.is_empty() {
+        candidates = eligible.iter().collect();
+    }
+    let ratings = conn.select_image_ratings()?;
+    let shown = conn.select_image_shown()?;
+    let now = i64::try_from(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())?;
+    let weights = candidates
+        .iter()
+        .map(|p| {
+            let (favorite, stars) = ratings
+                .get(*p)
+                .copied()
+                .unwrap_or((false, DEFAULT_IMAGE_WEIGHT));
+            image_weight(favorite, stars, shown.get(*p).copied(), now)
+        })
+        .collect::<Vec<_>>();
+    Ok(weighted_index(&weights).map(|i| candidates[i].clone()))
+}
+
+/// Draws a random image for `pool`. Images in `avoid` are only drawn when
+/// nothing else is left.
+///
+/// By default images come from a shuffle bag: every image of `eligible` is
+/// drawn once before any of them repeats, and the bag is kept in the cache
+/// database so rounds carry over between invocations. When `weighted` is set
+/// images are picked by their rating and favorite flag instead, with recently
+/// shown ones made less likely.
+pub fn draw_image(
+    conn: &DatabaseConnection,
+    pool: &str,
+    eligible: &[PathBuf],
+    avoid: &HashSet<PathBuf>,
+    weighted: bool,
+) -> anyhow::Result<Option<PathBuf>> {
+    let image = if weighted {
+        choose_weighted(conn, eligible, avoid)?
+    } else {
+        let drawn = conn.select_shuffle_drawn(pool)?;
+        let Some((image, new_round)) = choose(eligible, &drawn, avoid) else {
+            return Ok(None);
+        };
+        if new_round {
+            conn.clear_shuffle_bag(pool)?;
+        }
+        conn.insert_shuffle_drawn(pool, &image)?;
+        Some(image)
+    };
+    if let Some(image) = &image {
+        conn.insert_image_shown(image)?;
+    }
+    Ok(image)
+}
+
+/// Images to steer away from when drawing for `monitor`: the one it shows now
+/// so a new round does not start with a repeat and, when `distinct` is set,
+/// the ones shown on the other outputs.
+#[must_use]
+pub fn images_to_avoid(
+    monitor: &str,
+    wallpapers: &[Wallpaper],
+    distinct: bool,
+) -> HashSet<PathBuf> {
+    wallpapers
+        .iter()
+        .filter(|w| distinct || w.monitor == monitor)
+        .map(|w| Path::new(&w.path).to_path_buf())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(names: &[&str]) -> Vec<PathBuf> {
+        names.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn every_image_is_drawn_once_per_round() {
+        let eligible = paths(&["/a.png", "/b.png", "/c.png"]);
+        let mut drawn = HashSet::new();
+        for _ in 0..eligible.len() {
+            let (image, new_round) = choose(&eligible, &drawn, &HashSet::new()).unwrap();
+            assert!(!new_round);
+            assert!(drawn.insert(image));
+        }
+        let (_, new_round) = choose(&eligible, &drawn, &HashSet::new()).unwrap();
+        assert!(new_round);
+        assert!(choose(&[], &drawn, &HashSet::new()).is_none());
+    }
+
+    #[test]
+    fn avoided_images_are_only_drawn_as_a_last_resort() {
+        let eligible = paths(&["/a.png", "/b.png"]);
+        let avoid = HashSet::from([PathBuf::from("/a.png")]);
+        for _ in 0..10 {
+            let (image, _) = choose(&eligible, &HashSet::new(), &avoid).unwrap();
+            assert_eq!(image, PathBuf::from("/b.png"));
+        }
+        // A new round does not start with the image that is on screen.
+        let drawn = eligible.iter().cloned().collect();
+        let (image, new_round) = choose(&eligible, &drawn, &avoid).unwrap();
+        assert!(new_round);
+        assert_eq!(image, PathBuf::from("/b.png"));
+        let (image, _) = choose(&paths(&["/a.png"]), &HashSet::new(), &avoid).unwrap();
+        assert_eq!(image, PathBuf::from("/a.png"));
+        // Only an avoided image is left in the round: a drawn one is repeated
+        // instead, without starting a new round.
+        let drawn = HashSet::from([PathBuf::from("/b.png")]);
+        for _ in 0..10 {
+            let (image, new_round) = choose(&eligible, &drawn, &avoid).unwrap();
+            assert!(!new_round);
+            assert_eq!(image, PathBuf::from("/b.png"));
+        }
+    }
+
+    #[test]
+    fn favorites_and_stars_raise_weight_and_recent_images_lose_it() {
+        let now = 1_000_000;
+        let day = i64::try_from(RECENCY_WINDOW.as_secs()).unwrap();
+        assert!(image_weight(false, 5, None, now) > image_weight(false, 1, None, now));
+        assert_eq!(
+            image_weight(true, 3, None, now),
+            2.0 * image_weight(false, 3, None, now)
+        );
+        assert!(image_weight(false, 3, Some(now), now) < image_weight(false, 3, None, now));
+        assert_eq!(
+            image_weight(false, 3, Some(now - 2 * day), now),
+            image_weight(false, 3, None, now)
+        );
+        assert!(image_weight(false, 0, Some(now), now) > 0.0);
+    }
+
+    #[test]
+    fn weighted_index_skips_zero_weights() {
+        for _ in 0..20 {
+            assert_eq!(weighted_index(&[0.0, 1.0, 0.0]), Some(1));
+        }
+        assert_eq!(weighted_index(&[]), None);
+        assert_eq!(weighted_index(&[0.0]), None);
+    }
+}
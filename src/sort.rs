@@ -0,0 +1,227 @@
+use crate::{app_state::SortBy, common::CacheImageFile, database::DatabaseConnection};
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use xxhash_rust::xxh3::Xxh3;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ImageUsage {
+    /// Seconds since the epoch.
+    pub last_used: i64,
+    pub uses: u32,
+}
+
+/// How to order images, shared by the image grid and `--next` so both walk
+/// through them the same way.
+#[derive(Debug, Clone, Copy)]
+pub struct SortOrder<'a> {
+    pub sort_by: &'a SortBy,
+    pub invert: bool,
+    /// Shuffles `SortBy::Random`; the order only changes with the seed.
+    pub seed: u64,
+    pub usage: &'a HashMap<PathBuf, ImageUsage>,
+}
+
+/// Parts of a file name: runs of digits compare by their value and text
+/// ignores case, so `img2` comes before `img10`.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum NamePart {
+    Number(usize, String),
+    Text(String),
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum SortKey {
+    Number(u64),
+    Name(Vec<NamePart>),
+}
+
+fn natural_key(name: &str) -> Vec<NamePart> {
+    let mut parts = vec![];
+    let mut rest = name;
+    while let Some(first) = rest.chars().next() {
+        let digits = first.is_ascii_digit();
+        let end = rest
+            .find(|c: char| c.is_ascii_digit() != digits)
+            .unwrap_or(rest.len());
+        let (part, tail) = rest.split_at(end);
+        parts.push(if digits {
+            let value = part.trim_start_matches('0');
+            NamePart::Number(value.len(), value.to_owned())
+        } else {
+            NamePart::Text(part.to_lowercase())
+        });
+        rest = tail;
+    }
+    parts
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn seconds(time: io::Result<SystemTime>) -> u32 {
+    time.ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .and_then(|d| u32::try_from(d.as_secs()).ok())
+        .unwrap_or_default()
+}
+
+/// Only reads the entry, never the file: the grid re-sorts on the UI thread.
+fn sort_key(image: &CacheImageFile, order: &SortOrder) -> SortKey {
+    let usage = || order.usage.get(&image.path).copied().unwrap_or_default();
+    let media = image.media.unwrap_or_default();
+    SortKey::Number(match order.sort_by {
+        SortBy::Name => return SortKey::Name(natural_key(&file_name(&image.path))),
+        SortBy::Date => u64::from(image.created),
+        SortBy::Modified => u64::from(image.date),
+        SortBy::Size => image.size,
+        SortBy::Resolution => u64::from(media.width) * u64::from(media.height),
+        // Ratios are kept to six decimals, more than enough to tell them apart.
+        SortBy::AspectRatio => media
+            .aspect_ratio()
+            .map_or(0, |r| (r * 1_000_000.0).round() as u64),
+        SortBy::LastUsed => u64::try_from(usage().last_used).unwrap_or_default(),
+        SortBy::MostUsed => u64::from(usage().uses),
+        SortBy::Random => {
+            let mut hasher = Xxh3::with_seed(order.seed);
+            hasher.update(image.path.as_os_str().as_encoded_bytes());
+            hasher.digest()
+        }
+    })
+}
+
+/// Sorts `images`, breaking ties by path. Usage orders put the most recently
+/// or most often used images first, all others start with the smallest value.
+pub fn sort_images(images: &mut [CacheImageFile], order: &SortOrder) {
+    images.sort_by_cached_key(|i| (sort_key(i, order), i.path.clone()));
+    let descending = matches!(order.sort_by, SortBy::LastUsed | SortBy::MostUsed);
+    if descending != order.invert {
+        images.reverse();
+    }
+}
+
+/// Sorts files that may not be cached yet. Resolutions and aspect ratios are
+/// looked up in the cache, times and sizes are read from the files.
+pub fn sort_paths(files: &mut Vec<PathBuf>, order: &SortOrder) {
+    let conn = matches!(order.sort_by, SortBy::Resolution | SortBy::AspectRatio)
+        .then(DatabaseConnection::new)
+        .and_then(Result::ok);
+    let reads_file = matches!(
+        order.sort_by,
+        SortBy::Date | SortBy::Modified | SortBy::Size
+    );
+    let entry = |path: PathBuf| {
+        let cached = conn.as_ref().and_then(|c| c.select_image_file(&path).ok());
+        let mut image = CacheImageFile {
+            path,
+            ..cached.unwrap_or_default()
+        };
+        if reads_file && let Ok(metadata) = fs::metadata(&image.path) {
+            image.date = seconds(metadata.modified());
+            image.created = seconds(metadata.created().or_else(|_| metadata.modified()));
+            image.size = metadata.len();
+        }
+        image
+    };
+    let mut images = files.drain(..).map(entry).collect::<Vec<_>>();
+    sort_images(&mut images, order);
+    files.extend(images.into_iter().map(|i| i.path));
+}
+
+/// Loads what `sort_by` needs from the cache: the usage of every image for
+/// the usage orders, nothing for the others.
+#[must_use]
+pub fn load_usage(sort_by: &SortBy) -> HashMap<PathBuf, ImageUsage> {
+    if !matches!(sort_by, SortBy::LastUsed | SortBy::MostUsed) {
+        return HashMap::new();
+    }
+    match DatabaseConnection::new().and_then(|conn| conn.select_image_usage()) {
+        Ok(usage) => usage,
+        Err(e) => {
+            log::error!("Failed to load image usage: {e}");
+            HashMap::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::MediaInfo;
+
+    #[test]
+    fn images_sort_naturally_by_name_property_and_usage() {
+        let image = |path: &str, width: u32, size: u64| CacheImageFile {
+            path: PathBuf::from(path),
+            size,
+            created: u32::try_from(size).unwrap() * 100,
+            media: Some(MediaInfo {
+                width,
+                height: 1080,
+                ..MediaInfo::default()
+            }),
+            ..CacheImageFile::default()
+        };
+        let mut images = vec![
+            image("/w/img10.png", 1920, 30),
+            image("/w/IMG2.png", 3840, 10),
+            image("/w/img1.png", 2560, 20),
+        ];
+        let usage = HashMap::from([(
+            PathBuf::from("/w/img1.png"),
+            ImageUsage {
+                last_used: 100,
+                uses: 3,
+            },
+        )]);
+        let paths = |images: &[CacheImageFile]| {
+            images
+                .iter()
+                .map(|i| file_name(&i.path))
+                .collect::<Vec<_>>()
+        };
+        let mut sort = |sort_by: &SortBy, invert: bool, seed: u64| {
+            let order = SortOrder {
+                sort_by,
+                invert,
+                seed,
+                usage: &usage,
+            };
+            sort_images(&mut images, &order);
+            paths(&images)
+        };
+
+        assert_eq!(
+            sort(&SortBy::Name, false, 0),
+            ["img1.png", "IMG2.png", "img10.png"]
+        );
+        assert_eq!(
+            sort(&SortBy::Name, true, 0),
+            ["img10.png", "IMG2.png", "img1.png"]
+        );
+        assert_eq!(
+            sort(&SortBy::Resolution, false, 0),
+            ["img10.png", "img1.png", "IMG2.png"]
+        );
+        assert_eq!(
+            sort(&SortBy::Size, true, 0),
+            ["img10.png", "img1.png", "IMG2.png"]
+        );
+        // Sorting uses the recorded creation times, not the files.
+        assert_eq!(
+            sort(&SortBy::Date, false, 0),
+            ["IMG2.png", "img1.png", "img10.png"]
+        );
+        assert_eq!(sort(&SortBy::MostUsed, false, 0)[0], "img1.png");
+        let shuffled = sort(&SortBy::Random, false, 7);
+        sort(&SortBy::Name, false, 0);
+        assert_eq!(sort(&SortBy::Random, false, 7), shuffled);
+    }
+}
@@ -0,0 +1,352 @@
+use crate::{
+    common::{CONFIG_APP_NAME, Wallpaper},
+    locale::TRANSLATION,
+    monitors::{AvailableMonitors, Monitor},
+    wallpaper_changers::{WallpaperChanger, WallpaperChangers},
+};
+use anyhow::anyhow;
+use image::{ImageReader, imageops::FilterType};
+use log::debug;
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use xxhash_rust::xxh3::Xxh3;
+
+const SPAN_CACHE_DIR: &str = "span";
+/// Tiles younger than this may belong to a span that is not saved yet.
+const TILE_GRACE: Duration = Duration::from_secs(60 * 60);
+
+/// Area of the source image, in image pixels, that lands on one monitor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+fn logical_rect(monitor: &Monitor) -> (f64, f64, f64, f64) {
+    let (width, height) = monitor.logical_size();
+    let scale = f64::from(monitor.scale.max(1));
+    (
+        f64::from(monitor.x),
+        f64::from(monitor.y),
+        f64::from(width) / scale,
+        f64::from(height) / scale,
+    )
+}
+
+/// Splits an image across the monitor layout. The image is scaled to cover the
+/// bounding box of all outputs and centred, the same way the "cover" fill
+/// modes do, then each output gets the part under its position.
+#[must_use]
+pub fn span_regions(image_width: u32, image_height: u32, monitors: &[Monitor]) -> Vec<Region> {
+    let rects = monitors.iter().map(logical_rect).collect::<Vec<_>>();
+    let min_x = rects.iter().map(|r| r.0).fold(f64::INFINITY, f64::min);
+    let min_y = rects.iter().map(|r| r.1).fold(f64::INFINITY, f64::min);
+    let max_x = rects
+        .iter()
+        .map(|r| r.0 + r.2)
+        .fold(f64::NEG_INFINITY, f64::max);
+    let max_y = rects
+        .iter()
+        .map(|r| r.1 + r.3)
+        .fold(f64::NEG_INFINITY, f64::max);
+    let (bounds_width, bounds_height) = (max_x - min_x, max_y - min_y);
+    if bounds_width <= 0.0 || bounds_height <= 0.0 {
+        return Vec::new();
+    }
+    let (image_width, image_height) = (f64::from(image_width), f64::from(image_height));
+    // Image pixels per layout pixel.
+    let ratio = (image_width / bounds_width).min(image_height / bounds_height);
+    let offset_x = (image_width - bounds_width * ratio) / 2.0;
+    let offset_y = (image_height - bounds_height * ratio) / 2.0;
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    rects
+        .iter()
+        .map(|(x, y, width, height)| Region {
+            x: (offset_x + (x - min_x) * ratio).round() as u32,
+            y: (offset_y + (y - min_y) * ratio).round() as u32,
+            width: (width * ratio).round().max(1.0) as u32,
+            height: (height * ratio).round().max(1.0) as u32,
+        })
+        .collect()
+}
+
+fn span_cache_dir() -> anyhow::Result<PathBuf> {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix(CONFIG_APP_NAME);
+    let Some(cache_dir) = xdg_dirs.get_cache_home() else {
+        return Err(anyhow!("Failed to get cache directory"));
+    };
+    let span_dir = cache_dir.join(SPAN_CACHE_DIR);
+    fs::create_dir_all(&span_dir)?;
+    Ok(span_dir)
+}
+
+/// Tiles are reused while the image keeps its path, size and modification
+/// time and the monitor layout they were cut for is unchanged.
+fn tile_cache_key(image: &Path, monitors: &[Monitor]) -> anyhow::Result<u64> {
+    let metadata = fs::metadata(image)?;
+    let modified = metadata.modified()?.duration_since(UNIX_EPOCH)?;
+    // Fields are written with their length or at a fixed size so the key is
+    // stable across builds and neighbouring fields cannot run together.
+    let mut hasher = Xxh3::new();
+    let path = image.as_os_str().as_encoded_bytes();
+    hasher.update(&path.len().to_le_bytes());
+    hasher.update(path);
+    hasher.update(&metadata.len().to_le_bytes());
+    hasher.update(&modified.as_nanos().to_le_bytes());
+    for monitor in monitors {
+        hasher.update(&monitor.name.len().to_le_bytes());
+        hasher.update(monitor.name.as_bytes());
+        for value in [
+            monitor.x,
+            monitor.y,
+            monitor.width,
+            monitor.height,
+            monitor.scale,
+        ] {
+            hasher.update(&value.to_le_bytes());
+        }
+        hasher.update(&monitor.transform.to_le_bytes());
+    }
+    Ok(hasher.digest())
+}
+
+/// Cuts `image` into one tile per monitor, in the order of `monitors`. Tiles
+/// are cached under the XDG cache directory and reused while neither the image
+/// nor the layout changes.
+pub fn span_tiles(image: &Path, monitors: &[Monitor]) -> anyhow::Result<Vec<PathBuf>> {
+    if monitors.is_empty() {
+        return Err(anyhow!("No monitors to span the wallpaper across"));
+    }
+    let key = tile_cache_key(image, monitors)?;
+    let cache_dir = span_cache_dir()?;
+    let tiles = monitors
+        .iter()
+        .map(|m| cache_dir.join(format!("{key:016x}-{}.png", m.name)))
+        .collect::<Vec<_>>();
+    if tiles.iter().all(|t| t.is_file()) {
+        debug!(
+            "{}: {}",
+            TRANSLATION.get_translation("cache-hit"),
+            image.display()
+        );
+        return Ok(tiles);
+    }
+
+    let source = ImageReader::open(image)?.with_guessed_format()?.decode()?;
+    let regions = span_regions(source.width(), source.height(), monitors);
+    if regions.len() != monitors.len() {
+        return Err(anyhow!("Monitor layout is missing sizes"));
+    }
+    for ((monitor, region), tile) in monitors.iter().zip(regions).zip(&tiles) {
+        let cropped = source.crop_imm(region.x, region.y, region.width, region.height);
+        let (width, height) = monitor.logical_size();
+        let cropped = if width == 0 || height == 0 {
+            cropped
+        } else {
+            cropped.resize_exact(width, height, FilterType::Lanczos3)
+        };
+        cropped.to_rgb8().save(tile)?;
+    }
+    Ok(tiles)
+}
+
+/// Removes the tiles that no spanned saved wallpaper uses on the current
+/// monitor layout and returns how many were removed.
+pub fn collect_span_garbage(saved_wallpapers: &[Wallpaper]) -> anyhow::Result<usize> {
+    let monitors = AvailableMonitors::get_monitors()?.monitors;
+    remove_unused_tiles(
+        &span_cache_dir()?,
+        saved_wallpapers,
+        &monitors,
+        SystemTime::now() - TILE_GRACE,
+    )
+}
+
+fn remove_unused_tiles(
+    cache_dir: &Path,
+    saved_wallpapers: &[Wallpaper],
+    monitors: &[Monitor],
+    cutoff: SystemTime,
+) -> anyhow::Result<usize> {
+    let used = saved_wallpapers
+        .iter()
+        .filter(|w| w.span)
+        .filter_map(|w| tile_cache_key(Path::new(&w.path), monitors).ok())
+        .map(|key| format!("{key:016x}"))
+        .collect::<HashSet<_>>();
+    let mut removed = 0;
+    for entry in fs::read_dir(cache_dir)?.filter_map(Result::ok) {
+        let tile = entry.path();
+        let key = tile
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .and_then(|s| s.split_once('-'))
+            .map(|(key, _)| key);
+        let is_old = entry
+            .metadata()
+            .and_then(|m| m.modified())
+            .is_ok_and(|modified| modified < cutoff);
+        if tile.extension().is_some_and(|e| e == "png")
+            && is_old
+            && key.is_none_or(|k| !used.contains(k))
+        {
+            fs::remove_file(&tile)?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Sets one image across all `monitors` and returns the entries to save for
+/// them.
+pub fn span_wallpaper(
+    changer: &WallpaperChangers,
+    image: &Path,
+    monitors: &[Monitor],
+) -> anyhow::Result<Vec<Wallpaper>> {
+    let tiles = span_tiles(image, monitors)?;
+    for (monitor, tile) in monitors.iter().zip(tiles) {
+        changer.clone().change(tile, monitor.name.clone())?;
+        // hyprpaper needs a moment to start before it accepts the next output.
+        if matches!(changer, WallpaperChangers::Hyprpaper(_)) {
+            thread::sleep(Duration::from_secs(1));
+        }
+    }
+    Ok(monitors
+        .iter()
+        .map(|m| Wallpaper {
+            monitor: m.name.clone(),
+            path: image.to_str().unwrap_or_default().to_owned(),
+            changer: changer.clone(),
+            monitor_description: m.description.clone(),
+            span: true,
+        })
+        .collect())
+}
+
+/// Replaces the saved wallpapers of the spanned monitors. An entry for all
+/// monitors would override the tiles on restore, so it is dropped as well.
+pub fn save_spanned_wallpapers(saved_wallpapers: &mut Vec<Wallpaper>, spanned: Vec<Wallpaper>) {
+    saved_wallpapers.retain(|w| {
+        w.monitor != "All"
+            && w.monitor != TRANSLATION.get_translation("All")
+            && *w != Wallpaper::default()
+            && !spanned.iter().any(|s| s.monitor == w.monitor)
+    });
+    saved_wallpapers.extend(spanned);
+}
+
+/// Applies a saved wallpaper to `monitor`, cutting the tile again when the
+/// image was spanned across `monitors`.
+pub fn change_saved_wallpaper(
+    wallpaper: &Wallpaper,
+    monitor: &str,
+    monitors: &[Monitor],
+) -> anyhow::Result<()> {
+    let path = PathBuf::from(&wallpaper.path);
+    let image = if wallpaper.span {
+        let index = monitors
+            .iter()
+            .position(|m| m.name == monitor)
+            .ok_or_else(|| anyhow!("Display \"{monitor}\" is not connected"))?;
+        span_tiles(&path, monitors)?.swap_remove(index)
+    } else {
+        path
+    };
+    wallpaper.changer.clone().change(image, monitor.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn monitor(name: &str, x: i32, width: i32, height: i32, scale: i32) -> Monitor {
+        Monitor {
+            name: name.to_owned(),
+            x,
+            width,
+            height,
+            scale,
+            ..Monitor::default()
+        }
+    }
+
+    #[test]
+    fn side_by_side_monitors_get_adjacent_halves() {
+        let monitors = [
+            monitor("DP-1", 0, 1920, 1080, 1),
+            monitor("DP-2", 1920, 1920, 1080, 1),
+        ];
+        assert_eq!(
+            span_regions(3840, 1080, &monitors),
+            [
+                Region {
+                    x: 0,
+                    y: 0,
+                    width: 1920,
+                    height: 1080
+                },
+                Region {
+                    x: 1920,
+                    y: 0,
+                    width: 1920,
+                    height: 1080
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn layout_is_cropped_to_cover_and_respects_scale() {
+        // A HiDPI laptop panel (2x) left of a 1080p screen: both are 1920
+        // layout pixels wide. The 4000x1000 image is wider than the 3840x1080
+        // layout so its sides are cut off evenly.
+        let monitors = [
+            monitor("eDP-1", 0, 3840, 2160, 2),
+            monitor("DP-1", 1920, 1920, 1080, 1),
+        ];
+        let regions = span_regions(4000, 1000, &monitors);
+        assert_eq!(regions[0].width, regions[1].width);
+        assert_eq!(regions[0].height, 1000);
+        assert_eq!(regions[0].x, 2000 - regions[0].width);
+        assert_eq!(regions[1].x, 2000);
+        assert!(span_regions(4000, 1000, &[]).is_empty());
+    }
+
+    #[test]
+    fn only_tiles_of_spanned_wallpapers_are_kept() {
+        let temp = TempDir::new().unwrap();
+        let image = temp.path().join("wide.png");
+        fs::write(&image, b"image").unwrap();
+        let monitors = [monitor("DP-1", 0, 1920, 1080, 1)];
+        let key = tile_cache_key(&image, &monitors).unwrap();
+        let tiles = temp.path().join("span");
+        fs::create_dir(&tiles).unwrap();
+        let used = tiles.join(format!("{key:016x}-DP-1.png"));
+        let stale = tiles.join(format!("{:016x}-DP-1.png", key ^ 1));
+        fs::write(&used, b"tile").unwrap();
+        fs::write(&stale, b"tile").unwrap();
+        let saved = [Wallpaper {
+            monitor: "DP-1".to_owned(),
+            path: image.to_str().unwrap().to_owned(),
+            span: true,
+            ..Wallpaper::default()
+        }];
+
+        let cutoff = SystemTime::now() + Duration::from_secs(60);
+        assert_eq!(
+            remove_unused_tiles(&tiles, &saved, &monitors, cutoff).unwrap(),
+            1
+        );
+        assert!(used.exists());
+        assert!(!stale.exists());
+    }
+}
@@ -0,0 +1,260 @@
+use crate::{
+    common::{THUMBNAIL_HEIGHT, THUMBNAIL_WIDTH},
+    locale::TRANSLATION,
+};
+use anyhow::anyhow;
+use image::{ImageReader, Rgb, RgbImage};
+use log::{debug, trace};
+use std::{
+    fs,
+    io::Cursor,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::LazyLock,
+};
+use tempfile::NamedTempFile;
+use which::which;
+
+/// File name of the thumbnail shown for files no backend could read.
+pub const PLACEHOLDER_THUMBNAIL: &str = "no-thumbnail.png";
+
+/// A way of turning an image or video into a PNG thumbnail.
+pub trait ThumbnailBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn is_available(&self) -> bool;
+    /// Writes to `thumbnail`, an empty `.png` file that is moved into place
+    /// once the backend succeeds.
+    fn write_thumbnail(&self, path: &Path, thumbnail: &Path) -> anyhow::Result<()>;
+}
+
+/// Decodes images in process with the `image` crate.
+pub struct ImageBackend;
+
+/// Grabs a video frame with `ffmpegthumbnailer`, which seeks past black
+/// intros on its own.
+pub struct FfmpegThumbnailerBackend;
+
+/// Grabs the first video frame with `ffmpeg`.
+pub struct FfmpegBackend;
+
+/// A uniquely named file next to the thumbnail that backends write to. It is
+/// renamed into place once complete and removed otherwise, so neither an
+/// interrupted backend nor two writers of the same thumbnail leave a broken
+/// one behind.
+struct PartialThumbnail(NamedTempFile);
+
+impl PartialThumbnail {
+    fn new(thumbnail: &Path) -> anyhow::Result<PartialThumbnail> {
+        let dir = thumbnail
+            .parent()
+            .ok_or_else(|| anyhow!("{} has no parent directory", thumbnail.display()))?;
+        // The tools pick the output format from the extension.
+        let file = tempfile::Builder::new()
+            .prefix(".")
+            .suffix(".part.png")
+            .tempfile_in(dir)?;
+        Ok(PartialThumbnail(file))
+    }
+
+    fn path(&self) -> &Path {
+        self.0.path()
+    }
+
+    fn finish(self, thumbnail: &Path) -> anyhow::Result<()> {
+        self.0.persist(thumbnail)?;
+        Ok(())
+    }
+}
+
+fn run_quietly(command: &mut Command) -> anyhow::Result<()> {
+    trace!("Running {command:?}");
+    let status = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!("{command:?} failed with {status}"))
+    }
+}
+
+fn write_png(image: &RgbImage, thumbnail: &Path) -> anyhow::Result<()> {
+    let mut buff: Vec<u8> = vec![];
+    image.write_to(&mut Cursor::new(&mut buff), image::ImageFormat::Png)?;
+    fs::write(thumbnail, buff)?;
+    Ok(())
+}
+
+impl ThumbnailBackend for ImageBackend {
+    fn name(&self) -> &'static str {
+        "image"
+    }
+
+    fn is_available(&self) -> bool {
+        true
+    }
+
+    fn write_thumbnail(&self, path: &Path, thumbnail: &Path) -> anyhow::Result<()> {
+        let image = ImageReader::open(path)?
+            .with_guessed_format()?
+            .decode()?
+            .thumbnail(THUMBNAIL_WIDTH as u32, THUMBNAIL_HEIGHT as u32)
+            .to_rgb8();
+        write_png(&image, thumbnail)
+    }
+}
+
+impl ThumbnailBackend for FfmpegThumbnailerBackend {
+    fn name(&self) -> &'static str {
+        "ffmpegthumbnailer"
+    }
+
+    fn is_available(&self) -> bool {
+        static AVAILABLE: LazyLock<bool> = LazyLock::new(|| which("ffmpegthumbnailer").is_ok());
+        *AVAILABLE
+    }
+
+    fn write_thumbnail(&self, path: &Path, thumbnail: &Path) -> anyhow::Result<()> {
+        run_quietly(
+            Command::new("ffmpegthumbnailer")
+                .arg("-i")
+                .arg(path)
+                .arg("-o")
+                .arg(thumbnail)
+                .arg("-s")
+                .arg(THUMBNAIL_WIDTH.max(THUMBNAIL_HEIGHT).to_string())
+                .args(["-c", "png"]),
+        )
+    }
+}
+
+impl ThumbnailBackend for FfmpegBackend {
+    fn name(&self) -> &'static str {
+        "ffmpeg"
+    }
+
+    fn is_available(&self) -> bool {
+        static AVAILABLE: LazyLock<bool> = LazyLock::new(|| which("ffmpeg").is_ok());
+        *AVAILABLE
+    }
+
+    fn write_thumbnail(&self, path: &Path, thumbnail: &Path) -> anyhow::Result<()> {
+        run_quietly(
+            Command::new("ffmpeg")
+                .args(["-nostdin", "-loglevel", "error", "-y", "-ss", "00:00:00", "-i"])
+                .arg(path)
+                .args(["-frames:v", "1", "-vf"])
+                .arg(format!(
+                    "scale={THUMBNAIL_WIDTH}:{THUMBNAIL_HEIGHT}:force_original_aspect_ratio=decrease"
+                ))
+                .arg(thumbnail),
+        )
+    }
+}
+
+static BACKENDS: [&dyn ThumbnailBackend; 3] =
+    [&ImageBackend, &FfmpegThumbnailerBackend, &FfmpegBackend];
+
+/// The installed backends, in the order they are tried.
+pub fn available_backends() -> impl Iterator<Item = &'static dyn ThumbnailBackend> {
+    BACKENDS.into_iter().filter(|b| b.is_available())
+}
+
+/// Writes a thumbnail of `path` with the first backend that can read it and
+/// returns that backend's name.
+pub fn write_thumbnail(path: &Path, thumbnail: &Path) -> anyhow::Result<&'static str> {
+    for backend in available_backends() {
+        let partial = PartialThumbnail::new(thumbnail)?;
+        match backend.write_thumbnail(path, partial.path()) {
+            Ok(()) => {
+                partial.finish(thumbnail)?;
+                return Ok(backend.name());
+            }
+            Err(e) => debug!(
+                "{} could not thumbnail {}: {e}",
+                backend.name(),
+                path.display()
+            ),
+        }
+    }
+    Err(anyhow!(
+        "{}: {}",
+        TRANSLATION.get_translation("failed-to-create-thumbnail-for"),
+        path.to_string_lossy()
+    ))
+}
+
+/// Returns the placeholder thumbnail in `thumbnail_dir`, drawing it first
+/// when it is missing: a dark tile with a crossed out frame.
+pub fn placeholder_thumbnail(thumbnail_dir: &Path) -> anyhow::Result<PathBuf> {
+    let placeholder = thumbnail_dir.join(PLACEHOLDER_THUMBNAIL);
+    if placeholder.is_file() {
+        return Ok(placeholder);
+    }
+    let (width, height) = (THUMBNAIL_WIDTH as u32, THUMBNAIL_HEIGHT as u32);
+    let (background, foreground) = (Rgb([40, 40, 40]), Rgb([110, 110, 110]));
+    let border = width / 4;
+    let image = RgbImage::from_fn(width, height, |x, y| {
+        let inside =
+            (border..width - border).contains(&x) && (border..height - border).contains(&y);
+        let frame = inside
+            && (x < border + 4
+                || x >= width - border - 4
+                || y < border + 4
+                || y >= height - border - 4);
+        let cross = inside && (x.abs_diff(y) < 3 || (x + y).abs_diff(width) < 3);
+        if frame || cross {
+            foreground
+        } else {
+            background
+        }
+    });
+    let partial = PartialThumbnail::new(&placeholder)?;
+    write_png(&image, partial.path())?;
+    partial.finish(&placeholder)?;
+    Ok(placeholder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn unreadable_files_get_the_placeholder() {
+        let temp = TempDir::new().unwrap();
+        let dir = temp.path();
+        let broken = dir.join("broken.mp4");
+        fs::write(&broken, b"not a video").unwrap();
+
+        assert!(write_thumbnail(&broken, &dir.join("broken.png")).is_err());
+        let placeholder = placeholder_thumbnail(dir).unwrap();
+        assert_eq!(image::image_dimensions(&placeholder).unwrap(), (400, 400));
+        // Nothing half written is left behind by the tools that failed.
+        let mut files = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        files.sort();
+        assert_eq!(files, ["broken.mp4", PLACEHOLDER_THUMBNAIL]);
+    }
+
+    #[test]
+    fn thumbnails_are_written_through_unique_partial_files() {
+        let temp = TempDir::new().unwrap();
+        let dir = temp.path();
+        let (source, thumbnail) = (dir.join("source.png"), dir.join("thumbnail.png"));
+        RgbImage::new(800, 600).save(&source).unwrap();
+
+        let first = PartialThumbnail::new(&thumbnail).unwrap();
+        let second = PartialThumbnail::new(&thumbnail).unwrap();
+        assert_ne!(first.path(), second.path());
+        drop((first, second));
+
+        assert_eq!(write_thumbnail(&source, &thumbnail).unwrap(), "image");
+        assert_eq!(image::image_dimensions(&thumbnail).unwrap(), (400, 300));
+        assert_eq!(fs::read_dir(dir).unwrap().count(), 2);
+    }
+}
@@ -0,0 +1,298 @@
+use crate::app_state::Messages;
+use anyhow::anyhow;
+use futures::SinkExt;
+use iced::Subscription;
+use inotify::{Event, EventMask, Inotify, WatchDescriptor, WatchMask, Watches};
+use log::{debug, warn};
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+use walkdir::WalkDir;
+
+/// A change below the watched folder. Paths can be files or whole directories.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FolderEvent {
+    /// Something was written, created or moved in at this path.
+    Changed(PathBuf),
+    Removed(PathBuf),
+    Renamed(PathBuf, PathBuf),
+}
+
+/// inotify does not watch recursively, so every directory below the root
+/// gets its own watch.
+struct FolderWatcher {
+    inotify: Inotify,
+    root: PathBuf,
+    dirs: HashMap<WatchDescriptor, PathBuf>,
+}
+
+impl FolderWatcher {
+    fn new(root: &Path) -> anyhow::Result<FolderWatcher> {
+        let mut watcher = FolderWatcher {
+            inotify: Inotify::init()?,
+            root: root.to_path_buf(),
+            dirs: HashMap::new(),
+        };
+        watcher.add_tree(root);
+        Ok(watcher)
+    }
+
+    fn add_tree(&mut self, path: &Path) {
+        let mask = WatchMask::CLOSE_WRITE
+            | WatchMask::CREATE
+            | WatchMask::DELETE
+            | WatchMask::MOVED_FROM
+            | WatchMask::MOVED_TO;
+        for dir in WalkDir::new(path)
+            .into_iter()
+            .filter_map(std::result::Result::ok)
+            .filter(|e| e.file_type().is_dir())
+        {
+            match self.inotify.watches().add(dir.path(), mask) {
+                Ok(wd) => {
+                    self.dirs.insert(wd, dir.into_path());
+                }
+                Err(e) => warn!("Failed to watch {}: {e}", dir.path().display()),
+            }
+        }
+    }
+
+    fn rename_tree(&mut self, from: &Path, to: &Path) {
+        for dir in self.dirs.values_mut() {
+            if let Ok(rest) = dir.strip_prefix(from) {
+                *dir = to.join(rest);
+            }
+        }
+    }
+
+    fn forget_tree(&mut self, path: &Path) {
+        let wds = self
+            .dirs
+            .iter()
+            .filter(|(_, dir)| dir.starts_with(path))
+            .map(|(wd, _)| wd.clone())
+            .collect::<Vec<_>>();
+        for wd in wds {
+            let _ = self.inotify.watches().remove(wd.clone());
+            self.dirs.remove(&wd);
+        }
+    }
+
+    /// Turns a batch of inotify events into folder events. A rename shows up
+    /// as a MOVED_FROM and MOVED_TO pair sharing a cookie; a MOVED_FROM
+    /// without its pair was moved out of the folder.
+    fn process<'a>(
+        &mut self,
+        events: impl Iterator<Item = Event<&'a OsStr>>,
+        on_event: &mut impl FnMut(FolderEvent) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let mut moved_from = HashMap::new();
+        for event in events {
+            if event.mask.contains(EventMask::Q_OVERFLOW) {
+                on_event(FolderEvent::Changed(self.root.clone()))?;
+                continue;
+            }
+            if event.mask.contains(EventMask::IGNORED) {
+                self.dirs.remove(&event.wd);
+                continue;
+            }
+            let (Some(dir), Some(name)) = (self.dirs.get(&event.wd), event.name) else {
+                continue;
+            };
+            let path = dir.join(name);
+            let is_dir = event.mask.contains(EventMask::ISDIR);
+            let folder_event = if event.mask.contains(EventMask::MOVED_FROM) {
+                moved_from.insert(event.cookie, (path, is_dir));
+                continue;
+            } else if event.mask.contains(EventMask::MOVED_TO) {
+                match moved_from.remove(&event.cookie) {
+                    Some((from, _)) => {
+                        if is_dir {
+                            self.rename_tree(&from, &path);
+                        }
+                        FolderEvent::Renamed(from, path)
+                    }
+                    None => {
+                        if is_dir {
+                            self.add_tree(&path);
+                        }
+                        FolderEvent::Changed(path)
+                    }
+                }
+            } else if event.mask.contains(EventMask::CREATE) {
+                // Files are reported once they are closed after writing.
+                if !is_dir {
+                    continue;
+                }
+                self.add_tree(&path);
+                FolderEvent::Changed(path)
+            } else if event.mask.contains(EventMask::CLOSE_WRITE) {
+                FolderEvent::Changed(path)
+            } else if event.mask.contains(EventMask::DELETE) {
+                FolderEvent::Removed(path)
+            } else {
+                continue;
+            };
+            debug!("Folder event: {folder_event:?}");
+            on_event(folder_event)?;
+        }
+        for (from, is_dir) in moved_from.into_values() {
+            if is_dir {
+                self.forget_tree(&from);
+            }
+            on_event(FolderEvent::Removed(from))?;
+        }
+        Ok(())
+    }
+}
+
+/// Watches a folder and everything below it, blocking its thread until a
+/// change comes in.
+pub struct FolderWatch {
+    watcher: FolderWatcher,
+    stopped: Arc<AtomicBool>,
+}
+
+pub struct WatchStopper {
+    stopped: Arc<AtomicBool>,
+    watches: Watches,
+    root: WatchDescriptor,
+}
+
+impl FolderWatch {
+    pub fn new(root: &Path) -> anyhow::Result<FolderWatch> {
+        Ok(FolderWatch {
+            watcher: FolderWatcher::new(root)?,
+            stopped: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    pub fn stopper(&self) -> anyhow::Result<WatchStopper> {
+        let root = self
+            .watcher
+            .dirs
+            .iter()
+            .find(|(_, dir)| **dir == self.watcher.root)
+            .map(|(wd, _)| wd.clone())
+            .ok_or_else(|| anyhow!("{} is not watched", self.watcher.root.display()))?;
+        Ok(WatchStopper {
+            stopped: self.stopped.clone(),
+            watches: self.watcher.inotify.watches(),
+            root,
+        })
+    }
+
+    /// Calls `on_event` for every change until stopped, `on_event` fails or
+    /// the folder is removed. The inotify instance is closed on return.
+    pub fn run(
+        mut self,
+        mut on_event: impl FnMut(FolderEvent) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let mut buffer = [0; 4096];
+        while !self.stopped.load(Ordering::Acquire) && !self.watcher.dirs.is_empty() {
+            match self.watcher.inotify.read_events_blocking(&mut buffer) {
+                Ok(events) => self.watcher.process(events, &mut on_event)?,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl WatchStopper {
+    /// Removing the root watch queues an event, which wakes the blocked read.
+    pub fn stop(mut self) {
+        self.stopped.store(true, Ordering::Release);
+        let _ = self.watches.remove(self.root);
+    }
+}
+
+/// Stops the watch of a subscription when iced drops its stream.
+struct StopOnDrop(Option<WatchStopper>);
+
+impl Drop for StopOnDrop {
+    fn drop(&mut self) {
+        if let Some(stopper) = self.0.take() {
+            stopper.stop();
+        }
+    }
+}
+
+fn start_watch(folder: &Path) -> anyhow::Result<(FolderWatch, WatchStopper)> {
+    let watch = FolderWatch::new(folder)?;
+    let stopper = watch.stopper()?;
+    Ok((watch, stopper))
+}
+
+// `Subscription::run_with` hands its data back by reference.
+#[allow(clippy::ptr_arg)]
+fn folder_stream(folder: &PathBuf) -> impl futures::Stream<Item = Messages> + use<> {
+    let folder = folder.clone();
+    iced::stream::channel(16, async |output| {
+        let (started, stopper) = futures::channel::oneshot::channel();
+        std::thread::spawn(move || {
+            let result = start_watch(&folder).and_then(|(watch, stopper)| {
+                started
+                    .send(stopper)
+                    .map_err(|_| anyhow!("The folder subscription was dropped"))?;
+                watch.run(|event| {
+                    let mut output = output.clone();
+                    futures::executor::block_on(output.send(Messages::FolderChanged(event)))?;
+                    Ok(())
+                })
+            });
+            if let Err(e) = result {
+                warn!("Stopped watching {}: {e}", folder.display());
+            }
+        });
+        let _stop = StopOnDrop(stopper.await.ok());
+        futures::future::pending::<()>().await;
+    })
+}
+
+/// Reports changes below `folder` until the subscription is dropped, e.g.
+/// because another wallpaper folder was picked.
+pub fn folder_subscription(folder: PathBuf) -> Subscription<Messages> {
+    Subscription::run_with(folder, folder_stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, sync::mpsc, time::Duration};
+    use tempfile::TempDir;
+
+    #[test]
+    fn changes_below_the_folder_are_reported() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().to_path_buf();
+        fs::create_dir_all(root.join("sub")).unwrap();
+        let watch = FolderWatch::new(&root).unwrap();
+        let stopper = watch.stopper().unwrap();
+        let (tx, rx) = mpsc::channel();
+        let watch = std::thread::spawn(move || watch.run(|event| Ok(tx.send(event)?)));
+        let next = || rx.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        fs::write(root.join("sub/a.png"), b"a").unwrap();
+        assert_eq!(next(), FolderEvent::Changed(root.join("sub/a.png")));
+        fs::rename(root.join("sub"), root.join("moved")).unwrap();
+        assert_eq!(
+            next(),
+            FolderEvent::Renamed(root.join("sub"), root.join("moved"))
+        );
+        fs::remove_file(root.join("moved/a.png")).unwrap();
+        assert_eq!(next(), FolderEvent::Removed(root.join("moved/a.png")));
+
+        // Stopping wakes the blocked read even though nothing changed.
+        stopper.stop();
+        watch.join().unwrap().unwrap();
+    }
+}